@@ -0,0 +1,772 @@
+//! A language server for the tests. It keeps the documents it is told about and logs every
+//! message it gets to `mock_lsp.log` in its working directory, one JSON object per line with the
+//! method and the text of the document after the message. It takes incremental changes, or
+//! only whole texts when started with `--full`.
+//!
+//! Every `error` and `warning` in a document is published as a diagnostic of that severity.
+//! Completions are a variable replacing the word before the position and a snippet which adds
+//! a `use` line at the top. The definition of a word is its first occurrence in the document and
+//! its references are all of them. Hovering shows the word in a code block.
+//!
+//! Formatting removes the spaces at the ends of lines. The code actions at a word are to
+//! uppercase it in every open document while listing it in `todo.txt` beside the document, to
+//! run a command which appends it to the document, and to replace each diagnostic given with
+//! `fixed`.
+//!
+//! Every call `name(` has the signature `fn name(a: usize, b: usize)`, the parameter after the
+//! last comma being active. Every `let name` without a type has the inlay hint `: i32`.
+//!
+//! The semantic tokens are a `macro` for every word before `!`, a `type` for every capitalized
+//! word and a `mutable` `variable` for every word after `let mut`. Each response has a new
+//! result id, and a delta is one edit replacing what differs from the tokens of the id given.
+//!
+//! Every line starting with `fn`, `struct`, `enum`, `impl` or `mod` declares a symbol named by
+//! the word after it, inside the symbol declared last with less indentation. The workspace
+//! symbols are the ones of every open document whose name contains the query, ignoring case.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+fn main() -> io::Result<()> {
+    let mut input = BufReader::new(io::stdin());
+    let mut output = io::stdout();
+    let mut log = File::create("mock_lsp.log")?;
+    let mut documents: HashMap<String, String> = HashMap::new();
+    let mut semantic_tokens: HashMap<String, (String, Vec<u32>)> = HashMap::new();
+    let mut result_id = 0;
+    let sync = if std::env::args().any(|arg| arg == "--full") {
+        1
+    } else {
+        2
+    };
+
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or_default().to_owned();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned();
+
+        match method.as_str() {
+            "initialize" => respond(
+                &mut output,
+                &message["id"],
+                json!({
+                    "capabilities": {
+                        "textDocumentSync": sync,
+                        "completionProvider": { "triggerCharacters": ["."] },
+                        "definitionProvider": true,
+                        "referencesProvider": true,
+                        "hoverProvider": true,
+                        "documentFormattingProvider": true,
+                        "codeActionProvider": true,
+                        "renameProvider": true,
+                        "executeCommandProvider": { "commands": ["mock.append"] },
+                        "signatureHelpProvider": {
+                            "triggerCharacters": ["("],
+                            "retriggerCharacters": [","],
+                        },
+                        "inlayHintProvider": true,
+                        "documentSymbolProvider": true,
+                        "workspaceSymbolProvider": true,
+                        "semanticTokensProvider": {
+                            "legend": {
+                                "tokenTypes": ["variable", "macro", "type"],
+                                "tokenModifiers": ["mutable"],
+                            },
+                            "full": { "delta": true },
+                        },
+                    }
+                }),
+            )?,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+
+                documents.insert(uri.clone(), text.to_owned());
+            }
+            "textDocument/didChange" => {
+                let document = documents.entry(uri.clone()).or_default();
+
+                for change in params["contentChanges"].as_array().into_iter().flatten() {
+                    let text = change["text"].as_str().unwrap_or_default();
+
+                    if change["range"].is_null() {
+                        *document = text.to_owned();
+                    } else {
+                        let start = offset(document, &change["range"]["start"]);
+                        let end = offset(document, &change["range"]["end"]);
+
+                        document.replace_range(start..end, text);
+                    }
+                }
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+            }
+            "textDocument/completion" => {
+                let text = documents.get(&uri).map(String::as_str).unwrap_or_default();
+
+                respond(
+                    &mut output,
+                    &message["id"],
+                    completions(text, &params["position"]),
+                )?
+            }
+            "textDocument/definition" | "textDocument/references" | "textDocument/hover" => {
+                let text = documents.get(&uri).map(String::as_str).unwrap_or_default();
+                let word = word_at(text, offset(text, &params["position"]));
+                let locations: Vec<Value> = occurrences(text, word)
+                    .map(|range| json!({ "uri": uri, "range": range }))
+                    .collect();
+                let result = match method.as_str() {
+                    "textDocument/definition" => locations.first().cloned().unwrap_or_default(),
+                    "textDocument/references" => json!(locations),
+                    _ if word.is_empty() => Value::Null,
+                    _ => json!({
+                        "contents": {
+                            "kind": "markdown",
+                            "value": format!("```rust\n{}\n```\nA word.", word),
+                        }
+                    }),
+                };
+
+                respond(&mut output, &message["id"], result)?
+            }
+            "textDocument/formatting" => {
+                let text = documents.get(&uri).map(String::as_str).unwrap_or_default();
+
+                respond(&mut output, &message["id"], formatting(text))?
+            }
+            "textDocument/codeAction" => {
+                let text = documents.get(&uri).map(String::as_str).unwrap_or_default();
+                let word = word_at(text, offset(text, &params["range"]["start"]));
+
+                respond(
+                    &mut output,
+                    &message["id"],
+                    code_actions(&documents, &uri, word, &params["context"]["diagnostics"]),
+                )?
+            }
+            "textDocument/rename" => {
+                let text = documents.get(&uri).map(String::as_str).unwrap_or_default();
+                let word = word_at(text, offset(text, &params["position"]));
+
+                respond(
+                    &mut output,
+                    &message["id"],
+                    rename(&documents, word, &params["newName"]),
+                )?
+            }
+            "textDocument/signatureHelp" => {
+                let text = documents.get(&uri).map(String::as_str).unwrap_or_default();
+
+                respond(
+                    &mut output,
+                    &message["id"],
+                    signature_help(text, offset(text, &params["position"])),
+                )?
+            }
+            "textDocument/inlayHint" => {
+                let text = documents.get(&uri).map(String::as_str).unwrap_or_default();
+
+                respond(&mut output, &message["id"], inlay_hints(text))?
+            }
+            "textDocument/semanticTokens/full" | "textDocument/semanticTokens/full/delta" => {
+                let text = documents.get(&uri).map(String::as_str).unwrap_or_default();
+                let data = semantic_tokens_data(text);
+                let previous = semantic_tokens
+                    .get(&uri)
+                    .filter(|(id, _)| params["previousResultId"].as_str() == Some(id.as_str()));
+
+                result_id += 1;
+
+                let result = match previous {
+                    Some((_, previous)) => {
+                        let start = previous
+                            .iter()
+                            .zip(&data)
+                            .take_while(|(a, b)| a == b)
+                            .count()
+                            / 5
+                            * 5;
+                        let end = previous[start..]
+                            .iter()
+                            .rev()
+                            .zip(data[start..].iter().rev())
+                            .take_while(|(a, b)| a == b)
+                            .count()
+                            / 5
+                            * 5;
+
+                        json!({
+                            "resultId": result_id.to_string(),
+                            "edits": [{
+                                "start": start,
+                                "deleteCount": previous.len() - start - end,
+                                "data": data[start..data.len() - end],
+                            }],
+                        })
+                    }
+                    None => json!({ "resultId": result_id.to_string(), "data": data }),
+                };
+
+                semantic_tokens.insert(uri.clone(), (result_id.to_string(), data));
+                respond(&mut output, &message["id"], result)?
+            }
+            "textDocument/documentSymbol" => {
+                let text = documents.get(&uri).map(String::as_str).unwrap_or_default();
+
+                respond(&mut output, &message["id"], document_symbols(text))?
+            }
+            "workspace/symbol" => {
+                let query = params["query"].as_str().unwrap_or_default().to_lowercase();
+                let mut uris: Vec<&String> = documents.keys().collect();
+
+                uris.sort();
+
+                let symbols: Vec<Value> = uris
+                    .into_iter()
+                    .flat_map(|uri| {
+                        declarations(&documents[uri])
+                            .into_iter()
+                            .filter(|(_, name, _, _)| name.to_lowercase().contains(&query))
+                            .map(move |(_, name, kind, range)| {
+                                json!({
+                                    "name": name,
+                                    "kind": kind,
+                                    "location": { "uri": uri, "range": range },
+                                })
+                            })
+                    })
+                    .collect();
+
+                respond(&mut output, &message["id"], json!(symbols))?
+            }
+            "workspace/executeCommand" => {
+                let uri = params["arguments"][0].as_str().unwrap_or_default();
+                let word = params["arguments"][1].as_str().unwrap_or_default();
+                let text = documents.get(uri).map(String::as_str).unwrap_or_default();
+                let lines: Vec<&str> = text.split('\n').collect();
+                let end = json!({
+                    "line": lines.len() - 1,
+                    "character": lines[lines.len() - 1].encode_utf16().count(),
+                });
+
+                write_message(
+                    &mut output,
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": 1,
+                        "method": "workspace/applyEdit",
+                        "params": {
+                            "edit": {
+                                "changes": {
+                                    uri: [{
+                                        "range": { "start": end, "end": end },
+                                        "newText": format!("\n{}", word),
+                                    }],
+                                },
+                            },
+                        },
+                    }),
+                )?;
+                respond(&mut output, &message["id"], Value::Null)?
+            }
+            "shutdown" => respond(&mut output, &message["id"], Value::Null)?,
+            _ => {}
+        }
+
+        if method.starts_with("textDocument/did") {
+            let diagnostics = documents
+                .get(&uri)
+                .map(|text| diagnostics(text))
+                .unwrap_or_default();
+
+            notify(
+                &mut output,
+                "textDocument/publishDiagnostics",
+                json!({
+                    "uri": uri,
+                    "version": params["textDocument"]["version"],
+                    "diagnostics": diagnostics,
+                }),
+            )?;
+        }
+
+        writeln!(
+            log,
+            "{}",
+            json!({ "method": method, "text": documents.get(&uri) })
+        )?;
+
+        if method == "exit" {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn diagnostics(text: &str) -> Vec<Value> {
+    let mut diagnostics = Vec::new();
+
+    for (line_idx, line) in text.split('\n').enumerate() {
+        for (word, severity) in [("error", 1), ("warning", 2)] {
+            for (idx, _) in line.match_indices(word) {
+                let character = line[..idx].encode_utf16().count();
+
+                diagnostics.push(json!({
+                    "range": {
+                        "start": { "line": line_idx, "character": character },
+                        "end": { "line": line_idx, "character": character + word.len() },
+                    },
+                    "severity": severity,
+                    "message": format!("found {}", word),
+                }));
+            }
+        }
+    }
+
+    diagnostics
+}
+
+fn completions(text: &str, position: &Value) -> Value {
+    let end = offset(text, position);
+    let word = text[..end]
+        .rsplit(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default();
+    let start = json!({
+        "line": position["line"],
+        "character": position["character"].as_u64().unwrap() - word.encode_utf16().count() as u64,
+    });
+
+    json!({
+        "isIncomplete": false,
+        "items": [
+            {
+                "label": "error_count",
+                "kind": 6,
+                "detail": "usize",
+                "documentation": { "kind": "markdown", "value": "How many errors were found." },
+                "textEdit": {
+                    "range": { "start": start, "end": position },
+                    "newText": "error_count",
+                },
+            },
+            {
+                "label": "format!",
+                "kind": 15,
+                "insertTextFormat": 2,
+                "insertText": "format!(\"${1:fmt}\", $0)",
+                "additionalTextEdits": [{
+                    "range": {
+                        "start": { "line": 0, "character": 0 },
+                        "end": { "line": 0, "character": 0 },
+                    },
+                    "newText": "use std::fmt;\n",
+                }],
+            },
+        ],
+    })
+}
+
+fn formatting(text: &str) -> Value {
+    let edits: Vec<Value> = text
+        .split('\n')
+        .enumerate()
+        .filter(|(_, line)| line.ends_with(' '))
+        .map(|(line_idx, line)| {
+            let trimmed = line.trim_end_matches(' ');
+
+            json!({
+                "range": {
+                    "start": { "line": line_idx, "character": trimmed.encode_utf16().count() },
+                    "end": { "line": line_idx, "character": line.encode_utf16().count() },
+                },
+                "newText": "",
+            })
+        })
+        .collect();
+
+    json!(edits)
+}
+
+fn code_actions(
+    documents: &HashMap<String, String>,
+    uri: &str,
+    word: &str,
+    diagnostics: &Value,
+) -> Value {
+    let mut actions = Vec::new();
+
+    if !word.is_empty() {
+        let mut document_changes: Vec<Value> = documents
+            .iter()
+            .map(|(uri, text)| {
+                let edits: Vec<Value> = occurrences(text, word)
+                    .map(|range| json!({ "range": range, "newText": word.to_uppercase() }))
+                    .collect();
+
+                json!({ "textDocument": { "uri": uri, "version": null }, "edits": edits })
+            })
+            .collect();
+        let todo = format!("{}/todo.txt", &uri[..uri.rfind('/').unwrap_or_default()]);
+        let start = json!({ "line": 0, "character": 0 });
+
+        document_changes.push(json!({
+            "textDocument": { "uri": todo, "version": null },
+            "edits": [{
+                "range": { "start": start, "end": start },
+                "newText": format!("{}\n", word),
+            }],
+        }));
+        actions.push(json!({
+            "title": format!("Uppercase {}", word),
+            "kind": "refactor.rewrite",
+            "edit": { "documentChanges": document_changes },
+        }));
+        actions.push(json!({
+            "title": format!("Append {}", word),
+            "command": "mock.append",
+            "arguments": [uri, word],
+        }));
+    }
+
+    for diagnostic in diagnostics.as_array().into_iter().flatten() {
+        actions.push(json!({
+            "title": format!("Fix {}", diagnostic["message"].as_str().unwrap_or_default()),
+            "kind": "quickfix",
+            "diagnostics": [diagnostic],
+            "edit": {
+                "changes": {
+                    uri: [{ "range": diagnostic["range"], "newText": "fixed" }],
+                },
+            },
+        }));
+    }
+
+    json!(actions)
+}
+
+/// The signature of the innermost call which is still open before the offset on its line.
+fn signature_help(text: &str, offset: usize) -> Value {
+    let line_start = text[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+    let before = &text[line_start..offset];
+    let mut depth = 0;
+    let mut open = None;
+
+    for (idx, c) in before.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' if depth > 0 => depth -= 1,
+            '(' => {
+                open = Some(idx);
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    let open = match open {
+        Some(open) => open,
+        None => return Value::Null,
+    };
+    let name = word_at(before, open);
+
+    if name.is_empty() {
+        return Value::Null;
+    }
+
+    let label = format!("fn {}(a: usize, b: usize)", name);
+    let first = label.find("(a:").unwrap_or_default() + 1;
+    let second = label.find("b:").unwrap_or_default();
+    let commas = before[open..].matches(',').count();
+
+    json!({
+        "signatures": [{
+            "label": label,
+            "parameters": [
+                { "label": [first, first + 8] },
+                { "label": [second, second + 8] },
+            ],
+        }],
+        "activeSignature": 0,
+        "activeParameter": commas.min(1),
+    })
+}
+
+fn inlay_hints(text: &str) -> Value {
+    let hints: Vec<Value> = text
+        .split('\n')
+        .enumerate()
+        .flat_map(|(line_idx, line)| {
+            line.match_indices("let ").filter_map(move |(idx, _)| {
+                let start = idx + 4;
+                let name = word_at(line, start);
+                let end = start + name.len();
+
+                if name.is_empty() || line[end..].trim_start().starts_with(':') {
+                    return None;
+                }
+
+                Some(json!({
+                    "position": {
+                        "line": line_idx,
+                        "character": line[..end].encode_utf16().count(),
+                    },
+                    "label": ": i32",
+                    "kind": 1,
+                }))
+            })
+        })
+        .collect();
+
+    json!(hints)
+}
+
+/// The indentation, name, kind and name range of every declaration, in order.
+fn declarations(text: &str) -> Vec<(usize, &str, u32, Value)> {
+    text.split('\n')
+        .enumerate()
+        .filter_map(|(line_idx, line)| {
+            let trimmed = line.trim_start();
+            let indent = line.len() - trimmed.len();
+            let (keyword, rest) = trimmed.split_once(' ')?;
+            let kind = match keyword {
+                "fn" => 12,
+                "struct" => 23,
+                "enum" => 10,
+                "impl" => 19,
+                "mod" => 2,
+                _ => return None,
+            };
+            let start = line.len() - rest.len();
+            let name = word_at(line, start);
+
+            if name.is_empty() {
+                return None;
+            }
+
+            let range = json!({
+                "start": { "line": line_idx, "character": line[..start].encode_utf16().count() },
+                "end": {
+                    "line": line_idx,
+                    "character": line[..start + name.len()].encode_utf16().count(),
+                },
+            });
+
+            Some((indent, name, kind, range))
+        })
+        .collect()
+}
+
+/// The declarations nested by their indentation.
+fn document_symbols(text: &str) -> Value {
+    let mut roots = Vec::new();
+    let mut open: Vec<(usize, Value)> = Vec::new();
+    let close = |open: &mut Vec<(usize, Value)>, roots: &mut Vec<Value>| {
+        let (_, symbol) = open.pop().unwrap();
+
+        match open.last_mut() {
+            Some((_, parent)) => parent["children"].as_array_mut().unwrap().push(symbol),
+            None => roots.push(symbol),
+        }
+    };
+
+    for (indent, name, kind, range) in declarations(text) {
+        while open
+            .last()
+            .is_some_and(|(open_indent, _)| *open_indent >= indent)
+        {
+            close(&mut open, &mut roots);
+        }
+
+        open.push((
+            indent,
+            json!({
+                "name": name,
+                "kind": kind,
+                "range": range,
+                "selectionRange": range,
+                "children": [],
+            }),
+        ));
+    }
+
+    while !open.is_empty() {
+        close(&mut open, &mut roots);
+    }
+
+    json!(roots)
+}
+
+/// The tokens as the integers they are sent as, each relative to the one before.
+fn semantic_tokens_data(text: &str) -> Vec<u32> {
+    let mut data = Vec::new();
+    let mut previous = (0, 0);
+
+    for (line_idx, line) in text.split('\n').enumerate() {
+        let mut start = 0;
+
+        while let Some(idx) = line[start..].find(is_word_char) {
+            let word_start = start + idx;
+            let word = word_at(line, word_start);
+            let word_end = word_start + word.len();
+            let token = if line[word_end..].starts_with('!') {
+                Some((1, 0))
+            } else if word.starts_with(|c: char| c.is_uppercase()) {
+                Some((2, 0))
+            } else if line[..word_start].trim_end().ends_with("let mut") {
+                Some((0, 1))
+            } else {
+                None
+            };
+
+            if let Some((token_type, modifiers)) = token {
+                let character = line[..word_start].encode_utf16().count() as u32;
+                let delta_start = if line_idx as u32 == previous.0 {
+                    character - previous.1
+                } else {
+                    character
+                };
+
+                data.extend([
+                    line_idx as u32 - previous.0,
+                    delta_start,
+                    word.encode_utf16().count() as u32,
+                    token_type,
+                    modifiers,
+                ]);
+                previous = (line_idx as u32, character);
+            }
+
+            start = word_end;
+        }
+    }
+
+    data
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn word_at(text: &str, offset: usize) -> &str {
+    let start = text[..offset]
+        .rfind(|c| !is_word_char(c))
+        .map_or(0, |idx| idx + 1);
+    let end = text[offset..]
+        .find(|c| !is_word_char(c))
+        .map_or(text.len(), |idx| offset + idx);
+
+    &text[start..end]
+}
+
+/// The ranges of the whole word wherever it occurs.
+fn occurrences<'a>(text: &'a str, word: &'a str) -> impl Iterator<Item = Value> + 'a {
+    text.split('\n')
+        .enumerate()
+        .flat_map(move |(line_idx, line)| {
+            line.match_indices(word)
+                .filter(move |(idx, _)| {
+                    !line[..*idx].ends_with(is_word_char)
+                        && !line[idx + word.len()..].starts_with(is_word_char)
+                })
+                .map(move |(idx, _)| {
+                    let character = line[..idx].encode_utf16().count();
+
+                    json!({
+                        "start": { "line": line_idx, "character": character },
+                        "end": { "line": line_idx, "character": character + word.len() },
+                    })
+                })
+        })
+        .filter(move |_| !word.is_empty())
+}
+
+/// Replaces the word wherever it occurs in the open documents.
+fn rename(documents: &HashMap<String, String>, word: &str, new_name: &Value) -> Value {
+    if word.is_empty() {
+        return Value::Null;
+    }
+
+    let changes: serde_json::Map<String, Value> = documents
+        .iter()
+        .map(|(uri, text)| {
+            let edits: Vec<Value> = occurrences(text, word)
+                .map(|range| json!({ "range": range, "newText": new_name }))
+                .collect();
+
+            (uri.clone(), json!(edits))
+        })
+        .filter(|(_, edits)| edits.as_array().is_some_and(|edits| !edits.is_empty()))
+        .collect();
+
+    json!({ "changes": changes })
+}
+
+/// The byte offset of an LSP position, whose character counts UTF-16 code units.
+fn offset(text: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap() as usize;
+    let character = position["character"].as_u64().unwrap() as usize;
+    let start: usize = text.split('\n').take(line).map(|line| line.len() + 1).sum();
+    let mut units = 0;
+
+    for (idx, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + idx;
+        }
+
+        units += c.len_utf16();
+    }
+
+    text.len()
+}
+
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut content_length = 0;
+
+    loop {
+        let mut line = String::new();
+
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        match line.trim_end().split_once(": ") {
+            Some(("Content-Length", len)) => content_length = len.parse().unwrap(),
+            Some(_) => {}
+            None => break,
+        }
+    }
+
+    let mut body = vec![0; content_length];
+
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn respond(output: &mut impl Write, id: &Value, result: Value) -> io::Result<()> {
+    write_message(
+        output,
+        json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+    )
+}
+
+fn notify(output: &mut impl Write, method: &str, params: Value) -> io::Result<()> {
+    write_message(
+        output,
+        json!({ "jsonrpc": "2.0", "method": method, "params": params }),
+    )
+}
+
+fn write_message(output: &mut impl Write, message: Value) -> io::Result<()> {
+    let body = message.to_string();
+
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
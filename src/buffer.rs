@@ -1,8 +1,28 @@
 use crate::frame::Cursor;
-use tui::style::Color;
-use tui::text::Spans;
+use crate::lsp::SemanticSpan;
+use crate::syntax::{input_edit, Language, Syntax};
+use crate::theme::Theme;
+use lsp_types::{
+    DiagnosticSeverity, InlayHint, InlayHintLabel, Location, Position, Range,
+    TextDocumentContentChangeEvent, TextEdit,
+};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::time::Instant;
+use tree_sitter::{InputEdit, Point};
+use tui::style::Style;
+use tui::text::{Span, Spans};
 
-pub const TAB: &'static str = "    ";
+pub const TAB: &str = "    ";
+pub const FOLD_MARKER: &str = " ...";
+/// Typing merges into one undo step until the step is this long.
+const UNDO_TYPING_LEN: usize = 20;
 
 #[derive(Debug, Clone)]
 pub enum FlexiblePosition {
@@ -24,36 +44,372 @@ impl std::ops::Add for FlexiblePosition {
     }
 }
 
+impl FlexiblePosition {
+    fn idx(&self, len: usize) -> usize {
+        match self {
+            FlexiblePosition::Idx(i) => (*i).min(len),
+            FlexiblePosition::Edge => len,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct StyleRange {
     pub line: usize,
     pub start: FlexiblePosition,
     pub end: FlexiblePosition,
-    pub color: Color,
-    pub foreground: bool,
+    pub style: Style,
+}
+
+impl StyleRange {
+    /// Splits a range between two cursors into one `StyleRange` per line.
+    pub fn between(start: Cursor, end: Cursor, style: Style) -> Vec<Self> {
+        (start.1..=end.1)
+            .map(|line| StyleRange {
+                line,
+                start: if line == start.1 {
+                    FlexiblePosition::Idx(start.0)
+                } else {
+                    FlexiblePosition::Idx(0)
+                },
+                end: if line == end.1 {
+                    FlexiblePosition::Idx(end.0)
+                } else {
+                    FlexiblePosition::Edge
+                },
+                style,
+            })
+            .collect()
+    }
+
+    fn apply(&self, styles: &mut [Style]) {
+        let len = styles.len();
+
+        for style in &mut styles[self.start.idx(len)..self.end.idx(len).max(self.start.idx(len))] {
+            *style = style.patch(self.style);
+        }
+    }
+}
+
+/// A mark shown in the sign column of the gutter. `style` is a key of the theme's `ui` table.
+#[derive(Debug, Clone)]
+pub struct Sign {
+    pub line: usize,
+    pub symbol: char,
+    pub style: &'static str,
+}
+
+/// What a line of a list buffer refers to, opened by pressing Enter on the line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListEntry {
+    Buffer(String),
+    Location(Location),
+}
+
+/// A problem found by a language server. Its range moves with the text as the buffer is edited.
+pub struct Diagnostic {
+    pub start: Marker,
+    pub end: Marker,
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+}
+
+/// A range of a line which a language server has classified, styled over the syntax.
+struct SemanticHighlight {
+    line: usize,
+    start: usize,
+    end: usize,
+    token_type: String,
+    modifiers: Vec<String>,
+}
+
+/// Text shown in a line without being part of it, before the character at its position.
+struct VirtualText {
+    position: Marker,
+    text: String,
+}
+
+impl Diagnostic {
+    /// The name of the severity, which is also its key in the theme.
+    pub fn severity_name(&self) -> &'static str {
+        severity_name(self.severity)
+    }
+}
+
+pub fn severity_name(severity: DiagnosticSeverity) -> &'static str {
+    match severity {
+        DiagnosticSeverity::WARNING => "warning",
+        DiagnosticSeverity::INFORMATION => "information",
+        DiagnosticSeverity::HINT => "hint",
+        _ => "error",
+    }
+}
+
+/// A position in a buffer which moves with the text around it as the buffer is edited.
+pub type Marker = Rc<Cell<Cursor>>;
+
+/// An edit, with what is needed to revert it.
+struct Change {
+    start: Cursor,
+    removed: String,
+    inserted: String,
+}
+
+/// Edits undone in one step. Steps of several buffers sealed with the same id are undone
+/// together.
+struct UndoStep {
+    id: u64,
+    changes: Vec<Change>,
 }
 
 // #[derive(PartialEq)]
 pub struct Buffer {
+    name: String,
+    path: Option<PathBuf>,
     lines: Vec<String>,
+    /// The byte offsets of the first lines, extended as far as they are asked for. An edit drops
+    /// the ones after its line.
+    line_starts: RefCell<Vec<usize>>,
+    modified: bool,
+    /// Counts the edits, so that others can tell whether they have seen the latest text.
+    version: i32,
+    /// When the last edit was made.
+    edited_at: Option<Instant>,
+    read_only: bool,
+    entries: Vec<Option<ListEntry>>,
+    syntax: Option<Syntax>,
+    highlights: Vec<(Cursor, Cursor, String)>,
+    folds: Vec<(usize, usize)>,
+    signs: BTreeMap<&'static str, Vec<Sign>>,
+    markers: Vec<Weak<Cell<Cursor>>>,
+    /// The edits since they were last taken, while a language server is told about them.
+    changes: Option<Vec<TextDocumentContentChangeEvent>>,
+    /// The most severe first.
+    diagnostics: Vec<Diagnostic>,
+    /// In the order of their positions.
+    inlay_hints: Vec<VirtualText>,
+    /// The version the inlay hints were given for.
+    inlay_hints_version: Option<i32>,
+    /// In the order of their positions.
+    semantic_highlights: Vec<SemanticHighlight>,
+    /// The version the semantic tokens were given for.
+    semantic_tokens_version: Option<i32>,
+    undo: Vec<UndoStep>,
+    /// The edits since the last step was sealed.
+    unsealed: Vec<Change>,
+    undoing: bool,
 }
 
 impl Buffer {
     pub fn new() -> Self {
+        Buffer::with_name("*scratch*")
+    }
+
+    pub fn with_name(name: &str) -> Self {
         Buffer {
+            name: name.to_owned(),
+            path: None,
             lines: vec![String::new()],
+            line_starts: RefCell::new(vec![0]),
+            modified: false,
+            version: 0,
+            edited_at: None,
+            read_only: false,
+            entries: Vec::new(),
+            syntax: None,
+            highlights: Vec::new(),
+            folds: Vec::new(),
+            signs: BTreeMap::new(),
+            markers: Vec::new(),
+            changes: None,
+            diagnostics: Vec::new(),
+            inlay_hints: Vec::new(),
+            inlay_hints_version: None,
+            semantic_highlights: Vec::new(),
+            semantic_tokens_version: None,
+            undo: Vec::new(),
+            unsealed: Vec::new(),
+            undoing: false,
         }
     }
 
-    #[allow(unused)]
+    /// Reads the file into a buffer named after it. A file which does not exist yet gives an
+    /// empty buffer.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+
+        let mut buffer = Buffer::with_name(&name);
+        buffer.path = Some(path.to_owned());
+        buffer.lines = text.split('\n').map(|line| line.to_owned()).collect();
+        buffer.set_language(Language::from_path(path));
+
+        Ok(buffer)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_owned();
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn edited_at(&self) -> Option<Instant> {
+        self.edited_at
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Writes the text to the file the buffer visits.
+    pub fn save(&mut self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Err(io::Error::other("the buffer visits no file")),
+        };
+
+        fs::write(path, self.text_full())?;
+        self.modified = false;
+        Ok(())
+    }
+
+    /// The size of the text in bytes.
+    pub fn size(&self) -> usize {
+        self.lines.iter().map(|line| line.len() + 1).sum::<usize>() - 1
+    }
+
+    /// Replaces the whole text with read-only lines, each of which may refer to something.
+    pub fn set_list(&mut self, lines: Vec<String>, entries: Vec<Option<ListEntry>>) {
+        let removed = self.text_full();
+
+        self.lines = lines;
+        self.entries = entries;
+        self.read_only = true;
+
+        let inserted = self.text_full();
+
+        self.edited(0, (0, 0), &removed, &inserted);
+        self.modified = false;
+        self.unsealed.clear();
+    }
+
+    pub fn entry(&self, line: usize) -> Option<&ListEntry> {
+        self.entries.get(line).and_then(|entry| entry.as_ref())
+    }
+
     pub fn text_full(&self) -> String {
         self.lines.join("\n")
     }
 
-    pub fn text_styled(&self) -> Vec<Spans> {
+    pub fn text_styled(&self, theme: &Theme, extra: &[StyleRange]) -> Vec<Spans<'_>> {
+        let syntax_ranges: Vec<StyleRange> = self
+            .highlights
+            .iter()
+            .filter_map(|(start, end, scope)| {
+                theme
+                    .syntax(scope)
+                    .map(|style| StyleRange::between(*start, *end, style))
+            })
+            .flatten()
+            .collect();
+        let semantic_ranges: Vec<StyleRange> = self
+            .semantic_highlights
+            .iter()
+            .filter_map(|highlight| {
+                theme
+                    .semantic(&highlight.token_type, &highlight.modifiers)
+                    .map(|style| StyleRange {
+                        line: highlight.line,
+                        start: FlexiblePosition::Idx(highlight.start),
+                        end: FlexiblePosition::Idx(highlight.end),
+                        style,
+                    })
+            })
+            .collect();
+        let diagnostic_ranges: Vec<StyleRange> = self
+            .diagnostics
+            .iter()
+            .rev()
+            .flat_map(|diagnostic| {
+                StyleRange::between(
+                    diagnostic.start.get(),
+                    diagnostic.end.get(),
+                    theme.diagnostic(diagnostic.severity_name()),
+                )
+            })
+            .collect();
+        let mut ranges: Vec<Vec<&StyleRange>> = vec![Vec::new(); self.lines.len()];
+
+        for range in syntax_ranges
+            .iter()
+            .chain(semantic_ranges.iter())
+            .chain(diagnostic_ranges.iter())
+            .chain(extra.iter())
+        {
+            if let Some(r) = ranges.get_mut(range.line) {
+                r.push(range);
+            }
+        }
+
         self.lines
             .iter()
-            .map(move |line| Spans::from(line.clone()))
+            .enumerate()
+            .filter(|(idx, _)| !self.is_hidden(*idx))
+            .map(|(idx, line)| {
+                let mut styles = vec![Style::default(); line.len()];
+
+                for range in &ranges[idx] {
+                    range.apply(&mut styles);
+                }
+
+                let mut spans = Vec::new();
+                let mut start = 0;
+                let mut hints = self.inlay_hints_on(idx).peekable();
+
+                for i in line
+                    .char_indices()
+                    .map(|(i, _)| i)
+                    .chain(std::iter::once(line.len()))
+                {
+                    let hinted = hints.peek().is_some_and(|(col, _)| *col == i);
+
+                    if i > start && (i == line.len() || hinted || styles[i] != styles[start]) {
+                        spans.push(Span::styled(&line[start..i], styles[start]));
+                        start = i;
+                    }
+
+                    while let Some((_, text)) = hints.next_if(|(col, _)| *col == i) {
+                        spans.push(Span::styled(text, theme.ui("inlay_hint")));
+                    }
+                }
+
+                if self.folds.iter().any(|(s, _)| *s == idx) {
+                    spans.push(Span::styled(FOLD_MARKER, theme.ui("fold_marker")));
+                }
+
+                Spans::from(spans)
+            })
             .collect::<Vec<Spans>>()
     }
 
@@ -69,36 +425,887 @@ impl Buffer {
         self.lines[idx].len()
     }
 
+    /// The nearest position which is inside the text, for positions kept from an older text.
+    pub fn clamp(&self, cursor: Cursor) -> Cursor {
+        let line_idx = cursor.1.min(self.lines.len() - 1);
+        let line = &self.lines[line_idx];
+        let mut col = cursor.0.min(line.len());
+
+        while !line.is_char_boundary(col) {
+            col -= 1;
+        }
+
+        (col, line_idx)
+    }
+
+    pub fn line(&self, idx: usize) -> &str {
+        &self.lines[idx]
+    }
+
+    /// The line as it is shown, with its inlay hints.
+    pub fn display_line(&self, idx: usize) -> Cow<'_, str> {
+        let line = &self.lines[idx];
+        let mut hints = self.inlay_hints_on(idx).peekable();
+
+        if hints.peek().is_none() {
+            return Cow::Borrowed(line);
+        }
+
+        let mut shown = String::new();
+        let mut start = 0;
+
+        for (col, text) in hints {
+            shown.push_str(&line[start..col]);
+            shown.push_str(text);
+            start = col;
+        }
+
+        shown.push_str(&line[start..]);
+        Cow::Owned(shown)
+    }
+
+    /// The column the position is shown at, after the inlay hints before it. The hints at the
+    /// position itself are shown after it.
+    pub fn display_col(&self, cursor: Cursor) -> usize {
+        cursor.0
+            + self
+                .inlay_hints_on(cursor.1)
+                .take_while(|(col, _)| *col < cursor.0)
+                .map(|(_, text)| text.len())
+                .sum::<usize>()
+    }
+
+    /// The position in the line shown at the column. The columns of an inlay hint give the
+    /// position it is shown before.
+    pub fn position_of_display_col(&self, line: usize, display_col: usize) -> usize {
+        let mut shift = 0;
+
+        for (col, text) in self.inlay_hints_on(line) {
+            if display_col < col + shift {
+                break;
+            }
+
+            if display_col < col + shift + text.len() {
+                return col;
+            }
+
+            shift += text.len();
+        }
+
+        (display_col - shift).min(self.lines[line].len())
+    }
+
+    pub fn language(&self) -> Option<Language> {
+        self.syntax.as_ref().map(|syntax| syntax.language())
+    }
+
+    pub fn set_language(&mut self, language: Option<Language>) {
+        self.syntax = language.map(|language| Syntax::new(language, &self.lines));
+        self.folds.clear();
+        self.highlights.clear();
+        self.refresh_highlights(0..self.lines.len());
+    }
+
     pub fn insert_char(&mut self, cursor: Cursor, c: char) {
+        let start_byte = self.byte_offset(cursor);
+
         self.lines[cursor.1].insert(cursor.0, c);
+        self.edited(start_byte, cursor, "", c.encode_utf8(&mut [0; 4]));
     }
 
+    /// Inserts the text at the cursor, breaking the line where it holds line breaks.
     pub fn insert_str(&mut self, cursor: Cursor, s: &str) {
-        self.lines[cursor.1].insert_str(cursor.0, s);
+        self.replace(cursor, cursor, s);
     }
 
-    pub fn insert_line(&mut self, cursor: Cursor, s: &str) {
-        self.lines.insert(cursor.1, s.to_owned());
+    pub fn remove_char(&mut self, cursor: Cursor) {
+        let start_byte = self.byte_offset(cursor);
+        let removed = self.lines[cursor.1].remove(cursor.0);
+
+        self.edited(start_byte, cursor, removed.encode_utf8(&mut [0; 4]), "");
     }
 
-    pub fn push_str(&mut self, cursor: Cursor, s: &str) {
-        self.lines[cursor.1] += s;
+    /// Replaces the text between the cursors, which may be on different lines, in one edit.
+    pub fn replace(&mut self, start: Cursor, end: Cursor, text: &str) {
+        let start_byte = self.byte_offset(start);
+        let removed = self.text_between(start, end);
+        let joined = format!(
+            "{}{}{}",
+            &self.lines[start.1][..start.0],
+            text,
+            &self.lines[end.1][end.0..]
+        );
+
+        self.lines.splice(
+            start.1..=end.1,
+            joined.split('\n').map(|line| line.to_owned()),
+        );
+        self.edited(start_byte, start, &removed, text);
     }
 
-    pub fn remove_char(&mut self, cursor: Cursor) {
-        self.lines[cursor.1].remove(cursor.0);
+    pub fn text_between(&self, start: Cursor, end: Cursor) -> String {
+        if start.1 == end.1 {
+            return self.lines[start.1][start.0..end.0].to_owned();
+        }
+
+        let mut text = self.lines[start.1][start.0..].to_owned();
+
+        for line in &self.lines[start.1 + 1..end.1] {
+            text.push('\n');
+            text.push_str(line);
+        }
+
+        text.push('\n');
+        text.push_str(&self.lines[end.1][..end.0]);
+        text
     }
 
-    pub fn delete_line(&mut self, cursor: Cursor) -> String {
-        self.lines.remove(cursor.1)
+    /// The start of the word which the cursor is in or right after.
+    pub fn word_start(&self, cursor: Cursor) -> Cursor {
+        let line = &self.lines[cursor.1][..cursor.0];
+        let col = line
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_word_char(*c))
+            .last()
+            .map_or(cursor.0, |(idx, _)| idx);
+
+        (col, cursor.1)
+    }
+
+    /// The word which the cursor is in or right after, empty if there is none.
+    pub fn word_at(&self, cursor: Cursor) -> &str {
+        let line = &self.lines[cursor.1];
+        let end = line[cursor.0..]
+            .find(|c| !is_word_char(c))
+            .map_or(line.len(), |idx| cursor.0 + idx);
+
+        &line[self.word_start(cursor).0..end]
+    }
+
+    /// Every word in the text, as often as it occurs.
+    pub fn words(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().flat_map(|line| {
+            line.split(|c: char| !is_word_char(c))
+                .filter(|word| !word.is_empty())
+        })
+    }
+
+    /// Applies edits whose ranges all refer to the text before any of them, as a language
+    /// server sends them.
+    pub fn apply_edits(&mut self, edits: &[TextEdit]) {
+        let mut edits: Vec<(Cursor, Cursor, usize, &str)> = edits
+            .iter()
+            .enumerate()
+            .map(|(idx, edit)| {
+                (
+                    self.clamp(self.cursor_of_position(edit.range.start)),
+                    self.clamp(self.cursor_of_position(edit.range.end)),
+                    idx,
+                    edit.new_text.as_str(),
+                )
+            })
+            .collect();
+
+        // From the last, so that the positions of the ones before stay right. Of insertions at
+        // the same place the later goes first, to end up after the earlier.
+        edits.sort_by_key(|(start, _, idx, _)| std::cmp::Reverse((start.1, start.0, *idx)));
+
+        for (start, end, _, text) in edits {
+            self.replace(start, end, text);
+        }
+    }
+
+    /// Ends the undo step holding the edits made since the last call, if there are any. Typing
+    /// goes on in the same step for a while. Returns whether a new step was made.
+    pub fn seal_undo(&mut self, id: u64) -> bool {
+        if self.unsealed.is_empty() {
+            return false;
+        }
+
+        let changes = std::mem::take(&mut self.unsealed);
+        let typing = |change: &Change| {
+            change.removed.is_empty() && !change.inserted.contains(char::is_whitespace)
+        };
+
+        if let ([change], Some(last)) = (&changes[..], self.undo.last_mut()) {
+            if let [typed] = &mut last.changes[..] {
+                if typing(change)
+                    && typing(typed)
+                    && change.inserted.chars().count() == 1
+                    && typed.inserted.len() < UNDO_TYPING_LEN
+                    && advance(typed.start, &typed.inserted) == change.start
+                {
+                    typed.inserted.push_str(&change.inserted);
+                    return false;
+                }
+            }
+        }
+
+        self.undo.push(UndoStep { id, changes });
+        true
+    }
+
+    /// The id of the step `undo` would revert.
+    pub fn undo_id(&self) -> Option<u64> {
+        self.undo.last().map(|step| step.id)
+    }
+
+    /// Reverts the last undo step. Returns where it started.
+    pub fn undo(&mut self) -> Option<Cursor> {
+        let step = self.undo.pop()?;
+
+        self.undoing = true;
+
+        for change in step.changes.iter().rev() {
+            let end = advance(change.start, &change.inserted);
+
+            self.replace(change.start, end, &change.removed);
+        }
+
+        self.undoing = false;
+        step.changes.first().map(|change| change.start)
+    }
+
+    /// Breaks the line at the cursor, moving the rest of it to a new line below.
+    pub fn break_line(&mut self, cursor: Cursor) {
+        let start_byte = self.byte_offset(cursor);
+        let rest = self.lines[cursor.1].split_off(cursor.0);
+
+        self.lines.insert(cursor.1 + 1, rest);
+        self.edited(start_byte, cursor, "", "\n");
+    }
+
+    /// Appends the next line to the given one.
+    pub fn join_line(&mut self, line: usize) {
+        let end = (self.line_len_idx(line), line);
+        let start_byte = self.byte_offset(end);
+        let next = self.lines.remove(line + 1);
+
+        self.lines[line] += &next;
+        self.edited(start_byte, end, "\n", "");
     }
 
     pub fn split_off(&mut self, cursor: Cursor) -> String {
-        self.lines[cursor.1].split_off(cursor.0)
+        let start_byte = self.byte_offset(cursor);
+        let removed = self.lines[cursor.1].split_off(cursor.0);
+
+        self.edited(start_byte, cursor, &removed, "");
+        removed
+    }
+
+    /// The smallest syntax node strictly enclosing `start..end`.
+    pub fn enclosing_range(&self, start: Cursor, end: Cursor) -> Option<(Cursor, Cursor)> {
+        let syntax = self.syntax.as_ref()?;
+        let (s, e) =
+            syntax.enclosing_range(Point::new(start.1, start.0), Point::new(end.1, end.0))?;
+
+        Some(((s.column, s.row), (e.column, e.row)))
+    }
+
+    /// Folds the innermost multi-line node around the cursor, or unfolds the fold starting at its line.
+    pub fn toggle_fold(&mut self, cursor: Cursor) {
+        if let Some(idx) = self.folds.iter().position(|(s, _)| *s == cursor.1) {
+            self.folds.remove(idx);
+        } else if let Some(syntax) = self.syntax.as_ref() {
+            if let Some(fold) = syntax.fold_range(Point::new(cursor.1, cursor.0)) {
+                self.folds.push(fold);
+            }
+        }
+    }
+
+    pub fn unfold_line(&mut self, line: usize) {
+        self.folds.retain(|(s, e)| !(*s < line && line <= *e));
+    }
+
+    pub fn is_hidden(&self, line: usize) -> bool {
+        self.folds.iter().any(|(s, e)| *s < line && line <= *e)
+    }
+
+    /// The row a line is rendered at once folded lines are taken out.
+    pub fn visual_line(&self, line: usize) -> usize {
+        (0..line).filter(|idx| !self.is_hidden(*idx)).count()
+    }
+
+    /// Lines which are not hidden in a fold, in order.
+    pub fn shown_lines(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.lines.len()).filter(move |idx| !self.is_hidden(*idx))
+    }
+
+    /// Replaces the signs put by one source, e.g. diagnostics or VCS.
+    pub fn set_signs(&mut self, source: &'static str, signs: Vec<Sign>) {
+        self.signs.insert(source, signs);
+    }
+
+    pub fn sign(&self, line: usize) -> Option<&Sign> {
+        self.signs
+            .values()
+            .flat_map(|signs| signs.iter())
+            .find(|sign| sign.line == line)
+    }
+
+    /// The nearest shown line above (`forward == false`) or below the given one.
+    pub fn next_shown_line(&self, line: usize, forward: bool) -> Option<usize> {
+        if forward {
+            (line + 1..self.lines.len()).find(|idx| !self.is_hidden(*idx))
+        } else {
+            (0..line).rev().find(|idx| !self.is_hidden(*idx))
+        }
+    }
+
+    fn byte_offset(&self, cursor: Cursor) -> usize {
+        let mut starts = self.line_starts.borrow_mut();
+
+        while starts.len() <= cursor.1 {
+            let line = starts.len() - 1;
+            let next = starts[line] + self.lines[line].len() + 1;
+
+            starts.push(next);
+        }
+
+        starts[cursor.1] + cursor.0
+    }
+
+    fn edited(&mut self, start_byte: usize, start: Cursor, removed: &str, inserted: &str) {
+        self.edited_at = Some(Instant::now());
+        self.line_starts.get_mut().truncate(start.1 + 1);
+
+        let edit = input_edit(start_byte, Point::new(start.1, start.0), removed, inserted);
+
+        if self.changes.is_some() {
+            // The text before the start is the same as before the edit.
+            let start = self.lsp_position(start);
+            let change = TextDocumentContentChangeEvent {
+                range: Some(Range::new(start, advance_utf16(start, removed))),
+                range_length: None,
+                text: inserted.to_owned(),
+            };
+
+            if let Some(changes) = self.changes.as_mut() {
+                changes.push(change);
+            }
+        }
+
+        if !self.undoing {
+            self.unsealed.push(Change {
+                start,
+                removed: removed.to_owned(),
+                inserted: inserted.to_owned(),
+            });
+        }
+
+        self.modified = true;
+        self.version += 1;
+
+        // The hints of the edited lines are wrong until the server gives new ones.
+        self.inlay_hints.retain(|hint| {
+            let line = hint.position.get().1;
+
+            line < edit.start_position.row || line > edit.old_end_position.row
+        });
+        self.shift_semantic_highlights(
+            edit.start_position.row,
+            edit.old_end_position.row,
+            edit.new_end_position.row,
+        );
+        self.shift_folds(
+            edit.start_position.row,
+            edit.old_end_position.row,
+            edit.new_end_position.row,
+        );
+        self.shift_signs(
+            edit.start_position.row,
+            edit.old_end_position.row,
+            edit.new_end_position.row,
+        );
+        self.shift_markers(&edit);
+        self.shift_highlights(
+            edit.start_position.row,
+            edit.old_end_position.row,
+            edit.new_end_position.row,
+        );
+
+        let rows = self
+            .syntax
+            .as_mut()
+            .map(|syntax| syntax.edit(&edit, &self.lines));
+
+        if let Some(rows) = rows {
+            self.refresh_highlights(rows);
+        }
+    }
+
+    /// Starts or stops keeping the edits for `take_changes`.
+    pub fn record_changes(&mut self, record: bool) {
+        self.changes = if record { Some(Vec::new()) } else { None };
+    }
+
+    /// The edits made since the last call, each relative to the text left by the one before.
+    pub fn take_changes(&mut self) -> Vec<TextDocumentContentChangeEvent> {
+        self.changes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Replaces the diagnostics with the ones a language server has published, and puts a sign
+    /// for the most severe one on each line.
+    pub fn set_diagnostics(&mut self, diagnostics: &[lsp_types::Diagnostic]) {
+        let mut diagnostics: Vec<Diagnostic> = diagnostics
+            .iter()
+            .map(|diagnostic| Diagnostic {
+                start: self.create_marker(self.cursor_of_position(diagnostic.range.start)),
+                end: self.create_marker(self.cursor_of_position(diagnostic.range.end)),
+                severity: diagnostic.severity.unwrap_or(DiagnosticSeverity::ERROR),
+                message: diagnostic.message.clone(),
+            })
+            .collect();
+
+        diagnostics.sort_by_key(|diagnostic| diagnostic.severity);
+
+        let mut signs: Vec<Sign> = Vec::new();
+
+        for diagnostic in &diagnostics {
+            let line = diagnostic.start.get().1;
+
+            if signs.iter().all(|sign| sign.line != line) {
+                let (symbol, style) = match diagnostic.severity {
+                    DiagnosticSeverity::WARNING => ('W', "sign_warning"),
+                    DiagnosticSeverity::INFORMATION => ('I', "sign_information"),
+                    DiagnosticSeverity::HINT => ('H', "sign_hint"),
+                    _ => ('E', "sign_error"),
+                };
+
+                signs.push(Sign {
+                    line,
+                    symbol,
+                    style,
+                });
+            }
+        }
+
+        self.diagnostics = diagnostics;
+        self.set_signs("diagnostics", signs);
+    }
+
+    /// Replaces the inlay hints with the ones a language server has given for the version.
+    pub fn set_inlay_hints(&mut self, hints: &[InlayHint], version: i32) {
+        let mut inlay_hints: Vec<VirtualText> = hints
+            .iter()
+            .map(|hint| {
+                let label = match &hint.label {
+                    InlayHintLabel::String(label) => label.clone(),
+                    InlayHintLabel::LabelParts(parts) => {
+                        parts.iter().map(|part| part.value.as_str()).collect()
+                    }
+                };
+                let pad = |padding: Option<bool>| if padding == Some(true) { " " } else { "" };
+
+                VirtualText {
+                    position: self
+                        .create_marker(self.clamp(self.cursor_of_position(hint.position))),
+                    text: format!(
+                        "{}{}{}",
+                        pad(hint.padding_left),
+                        label.replace('\n', " "),
+                        pad(hint.padding_right)
+                    ),
+                }
+            })
+            .collect();
+
+        inlay_hints.sort_by_key(|hint| (hint.position.get().1, hint.position.get().0));
+        self.inlay_hints = inlay_hints;
+        self.inlay_hints_version = Some(version);
+    }
+
+    pub fn inlay_hints_version(&self) -> Option<i32> {
+        self.inlay_hints_version
+    }
+
+    /// Replaces the semantic highlights with the tokens a language server has given for the
+    /// version.
+    pub fn set_semantic_tokens(&mut self, tokens: &[SemanticSpan], version: i32) {
+        self.semantic_highlights = tokens
+            .iter()
+            .map(|token| {
+                let start = self.clamp(self.cursor_of_position(token.range.start));
+                let end = self.clamp(self.cursor_of_position(token.range.end));
+
+                SemanticHighlight {
+                    line: start.1,
+                    start: start.0,
+                    end: if end.1 == start.1 {
+                        end.0
+                    } else {
+                        self.line_len(start)
+                    },
+                    token_type: token.token_type.clone(),
+                    modifiers: token.modifiers.clone(),
+                }
+            })
+            .collect();
+        self.semantic_tokens_version = Some(version);
+    }
+
+    pub fn semantic_tokens_version(&self) -> Option<i32> {
+        self.semantic_tokens_version
+    }
+
+    /// The inlay hints of the line with their columns, in order.
+    fn inlay_hints_on(&self, line: usize) -> impl Iterator<Item = (usize, &str)> {
+        self.inlay_hints
+            .iter()
+            .map(|hint| (hint.position.get(), hint.text.as_str()))
+            .filter(move |(position, _)| position.1 == line)
+            .map(|(position, text)| (position.0, text))
+    }
+
+    /// The most severe diagnostic whose range has the cursor.
+    pub fn diagnostic_at(&self, cursor: Cursor) -> Option<&Diagnostic> {
+        self.diagnostics.iter().find(|diagnostic| {
+            (diagnostic.start.get().1, diagnostic.start.get().0) <= (cursor.1, cursor.0)
+                && (cursor.1, cursor.0) <= (diagnostic.end.get().1, diagnostic.end.get().0)
+        })
+    }
+
+    /// The start of the nearest diagnostic after (`forward`) or before the cursor, going round
+    /// the buffer if there is none.
+    pub fn next_diagnostic(&self, cursor: Cursor, forward: bool) -> Option<Cursor> {
+        let mut starts: Vec<(usize, usize)> = self
+            .diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.start.get().1, diagnostic.start.get().0))
+            .collect();
+
+        starts.sort();
+        starts.dedup();
+
+        let position = (cursor.1, cursor.0);
+        let next = if forward {
+            starts
+                .iter()
+                .find(|start| **start > position)
+                .or(starts.first())
+        } else {
+            starts
+                .iter()
+                .rev()
+                .find(|start| **start < position)
+                .or(starts.last())
+        };
+
+        next.map(|(line, col)| (*col, *line))
+    }
+
+    /// The position of the cursor as language servers count it, in UTF-16 code units.
+    pub fn lsp_position(&self, cursor: Cursor) -> Position {
+        let character = self
+            .lines
+            .get(cursor.1)
+            .map_or(0, |line| utf16_len(&line[..cursor.0]));
+
+        Position::new(cursor.1 as u32, character)
+    }
+
+    /// The cursor at a position from a language server, clamped to the text.
+    pub fn cursor_of_position(&self, position: Position) -> Cursor {
+        let line_idx = (position.line as usize).min(self.lines.len() - 1);
+        let line = &self.lines[line_idx];
+        let mut units = 0;
+
+        for (idx, c) in line.char_indices() {
+            if units >= position.character {
+                return (idx, line_idx);
+            }
+
+            units += c.len_utf16() as u32;
+        }
+
+        (line.len(), line_idx)
     }
 
-    #[allow(unused)]
-    pub fn remove_front(&mut self, cursor: Cursor) -> String {
-        self.lines[cursor.1].drain(..cursor.0).collect()
+    /// Creates a marker at the cursor. It is adjusted on every edit for as long as it is held.
+    pub fn create_marker(&mut self, cursor: Cursor) -> Marker {
+        let marker = Rc::new(Cell::new(cursor));
+
+        self.markers.push(Rc::downgrade(&marker));
+        marker
+    }
+
+    fn shift_markers(&mut self, edit: &InputEdit) {
+        self.markers.retain(|marker| marker.strong_count() > 0);
+
+        for marker in self.markers.iter().filter_map(Weak::upgrade) {
+            marker.set(shift_marker(marker.get(), edit));
+        }
+    }
+
+    /// Drops the highlights of the edited lines, which are wrong until the server gives new
+    /// ones, and moves the ones below with their lines.
+    fn shift_semantic_highlights(
+        &mut self,
+        start_row: usize,
+        old_end_row: usize,
+        new_end_row: usize,
+    ) {
+        self.semantic_highlights
+            .retain(|highlight| highlight.line < start_row || highlight.line > old_end_row);
+
+        for highlight in &mut self.semantic_highlights {
+            if highlight.line > old_end_row {
+                highlight.line = highlight.line + new_end_row - old_end_row;
+            }
+        }
+    }
+
+    /// Drops the signs of the lines the edit joined into its first one, which are wrong until
+    /// their source puts new ones, and moves the ones below with their lines.
+    fn shift_signs(&mut self, start_row: usize, old_end_row: usize, new_end_row: usize) {
+        for signs in self.signs.values_mut() {
+            signs.retain(|sign| sign.line <= start_row || sign.line > old_end_row);
+
+            for sign in signs.iter_mut() {
+                if sign.line > old_end_row {
+                    sign.line = sign.line + new_end_row - old_end_row;
+                }
+            }
+        }
+    }
+
+    fn shift_folds(&mut self, start_row: usize, old_end_row: usize, new_end_row: usize) {
+        self.folds = self
+            .folds
+            .iter()
+            .filter_map(|&(s, e)| {
+                if start_row > e {
+                    Some((s, e))
+                } else if old_end_row < s {
+                    Some((s + new_end_row - old_end_row, e + new_end_row - old_end_row))
+                } else if start_row == s && old_end_row == s && new_end_row == s {
+                    Some((s, e))
+                } else {
+                    None
+                }
+            })
+            .collect();
+    }
+
+    /// Drops the syntax highlights touching the edited lines and moves the ones below with their
+    /// lines. The dropped ones come back from `refresh_highlights`.
+    fn shift_highlights(&mut self, start_row: usize, old_end_row: usize, new_end_row: usize) {
+        self.highlights
+            .retain(|(start, end, _)| (end.1, end.0) <= (start_row, 0) || start.1 > old_end_row);
+
+        for (start, end, _) in &mut self.highlights {
+            if start.1 > old_end_row {
+                start.1 = start.1 + new_end_row - old_end_row;
+                end.1 = end.1 + new_end_row - old_end_row;
+            }
+        }
+    }
+
+    /// Highlights the rows again, replacing the highlights touching them.
+    fn refresh_highlights(&mut self, rows: std::ops::Range<usize>) {
+        let syntax = match self.syntax {
+            Some(ref syntax) => syntax,
+            None => {
+                self.highlights.clear();
+                return;
+            }
+        };
+        let rows = rows.start.min(self.lines.len())..rows.end.min(self.lines.len());
+
+        if rows.is_empty() {
+            return;
+        }
+
+        // The highlights ending before the start or starting after the end are kept. The query
+        // may give some of them again along with a match reaching into the rows.
+        let outside = |start: &Cursor, end: &Cursor| {
+            (end.1, end.0) <= (rows.start, 0) || (start.1, start.0) >= (rows.end, 0)
+        };
+        let fresh: Vec<_> = syntax
+            .highlights(&self.lines, rows.clone())
+            .into_iter()
+            .map(|(s, e, scope)| ((s.column, s.row), (e.column, e.row), scope))
+            .filter(|(start, end, _)| !outside(start, end))
+            .collect();
+
+        self.highlights
+            .retain(|(start, end, _)| outside(start, end));
+        self.highlights.extend(fresh);
+        // Outer nodes first, so the nodes inside them are styled over them.
+        self.highlights
+            .sort_by_key(|(start, end, _)| (start.1, start.0, Reverse((end.1, end.0))));
+    }
+}
+
+/// Where a cursor ends up after the text is inserted at it.
+pub fn advance(cursor: Cursor, text: &str) -> Cursor {
+    match text.rsplit_once('\n') {
+        Some((before, last)) => (last.len(), cursor.1 + before.matches('\n').count() + 1),
+        None => (cursor.0 + text.len(), cursor.1),
+    }
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn utf16_len(s: &str) -> u32 {
+    s.encode_utf16().count() as u32
+}
+
+/// The position at the end of the text put at the given one.
+fn advance_utf16(position: Position, s: &str) -> Position {
+    match s.rfind('\n') {
+        Some(idx) => Position::new(
+            position.line + s.matches('\n').count() as u32,
+            utf16_len(&s[idx + 1..]),
+        ),
+        None => Position::new(position.line, position.character + utf16_len(s)),
+    }
+}
+
+/// Where a position ends up after the edit. Positions inside removed text move to its start and
+/// text inserted right at a position goes after it.
+fn shift_marker(cursor: Cursor, edit: &InputEdit) -> Cursor {
+    let (start, old_end, new_end) = (
+        edit.start_position,
+        edit.old_end_position,
+        edit.new_end_position,
+    );
+    let position = Point::new(cursor.1, cursor.0);
+
+    if position < start || (position == start && old_end == start) {
+        cursor
+    } else if position >= old_end {
+        if position.row == old_end.row {
+            (
+                new_end.column + position.column - old_end.column,
+                new_end.row,
+            )
+        } else {
+            (position.column, position.row + new_end.row - old_end.row)
+        }
+    } else {
+        (start.column, start.row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "fn main() {\n    let x = \"a\";\n    // one\n    let y = \"b\";\n}\n";
+
+    fn rust(text: &str) -> Buffer {
+        let mut buffer = Buffer::new();
+
+        buffer.lines = text.split('\n').map(|line| line.to_owned()).collect();
+        buffer.set_language(Some(Language::Rust));
+        buffer
+    }
+
+    #[test]
+    fn enclosing_range() {
+        let buffer = rust(SOURCE);
+
+        // `x`, then the `let` around it, then the block.
+        assert_eq!(
+            buffer.enclosing_range((8, 1), (8, 1)),
+            Some(((8, 1), (9, 1)))
+        );
+        assert_eq!(
+            buffer.enclosing_range((8, 1), (9, 1)),
+            Some(((4, 1), (16, 1)))
+        );
+        assert_eq!(
+            buffer.enclosing_range((4, 1), (16, 1)),
+            Some(((10, 0), (1, 4)))
+        );
+    }
+
+    #[test]
+    fn fold_range() {
+        let mut buffer = rust(SOURCE);
+
+        buffer.toggle_fold((4, 1));
+        assert_eq!(buffer.folds, vec![(0, 4)]);
+        assert!(!buffer.is_hidden(0) && buffer.is_hidden(1) && buffer.is_hidden(3));
+
+        buffer.toggle_fold((0, 0));
+        assert!(buffer.folds.is_empty());
+    }
+
+    #[test]
+    fn shift_folds() {
+        let mut buffer = rust(&format!("\n{}", SOURCE));
+
+        buffer.toggle_fold((4, 2));
+        buffer.insert_str((0, 0), "\n");
+        assert_eq!(buffer.folds, vec![(2, 6)]);
+
+        // Typing on the first line keeps it.
+        buffer.insert_str((0, 2), "pub ");
+        assert_eq!(buffer.folds, vec![(2, 6)]);
+
+        buffer.replace((0, 0), (0, 1), "");
+        assert_eq!(buffer.folds, vec![(1, 5)]);
+
+        // Editing the folded lines drops it.
+        buffer.insert_char((4, 2), 'y');
+        assert!(buffer.folds.is_empty());
+    }
+
+    #[test]
+    fn shift_marker() {
+        let marker = (4, 1);
+        let shift = |start: Cursor, removed: &str, inserted: &str| {
+            let edit = input_edit(0, Point::new(start.1, start.0), removed, inserted);
+
+            super::shift_marker(marker, &edit)
+        };
+
+        // Insertions before, at, and after the marker.
+        assert_eq!(shift((1, 1), "", "ab"), (6, 1));
+        assert_eq!(shift((0, 0), "", "x\ny"), (4, 2));
+        assert_eq!(shift((2, 1), "", "a\nbc"), (4, 2));
+        assert_eq!(shift((4, 1), "", "ab"), (4, 1));
+        assert_eq!(shift((6, 1), "", "ab"), (4, 1));
+
+        // Deletions before, at, around, and after the marker.
+        assert_eq!(shift((1, 1), "ab", ""), (2, 1));
+        assert_eq!(shift((5, 0), "\nab", ""), (7, 0));
+        assert_eq!(shift((2, 1), "ab", ""), (2, 1));
+        assert_eq!(shift((4, 1), "ab", ""), (4, 1));
+        assert_eq!(shift((2, 1), "abcd", ""), (2, 1));
+        assert_eq!(shift((3, 0), "a\nbcdef", ""), (3, 0));
+        assert_eq!(shift((5, 1), "ab", ""), (4, 1));
+    }
+
+    #[test]
+    fn incremental_highlights() {
+        let mut buffer = rust(SOURCE);
+        let edits: [&dyn Fn(&mut Buffer); 6] = [
+            // The string runs on to the next quote, two lines below.
+            &|buffer| buffer.remove_char((14, 1)),
+            &|buffer| buffer.insert_char((14, 1), '"'),
+            &|buffer| buffer.insert_str((4, 1), "/* "),
+            &|buffer| buffer.insert_str((19, 1), " */"),
+            &|buffer| buffer.replace((11, 0), (0, 2), "\n    x\n"),
+            &|buffer| buffer.insert_str((0, 0), "use a::b;\n\n"),
+        ];
+
+        for edit in edits {
+            edit(&mut buffer);
+            assert_eq!(buffer.highlights, rust(&buffer.text_full()).highlights);
+
+            let last = buffer.lines.len() - 1;
+            let text = buffer.text_full();
+
+            assert_eq!(buffer.byte_offset((0, last)), text.rfind('\n').unwrap() + 1);
+        }
     }
 }
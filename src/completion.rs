@@ -0,0 +1,478 @@
+use crate::buffer::{advance, Buffer};
+use crate::frame::Cursor;
+use crate::lsp::{markdown_lines, Ticket};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionTextEdit, Documentation, InsertTextFormat,
+    Position, Range,
+};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// The most items the popup shows at once.
+pub const COMPLETION_HEIGHT: usize = 10;
+
+/// The items offered to complete the word before the cursor, filtered by what has been typed
+/// since they were asked for.
+pub struct Completion {
+    /// Where the completed word starts.
+    start: Cursor,
+    /// Where the cursor was when the items were asked for. The positions in their edits are
+    /// relative to the text then.
+    origin: Cursor,
+    /// The words from the buffers come first, then what the server has sent.
+    items: Vec<CompletionItem>,
+    words: usize,
+    /// What the items have last been filtered by.
+    pattern: String,
+    /// The indices of the items matching the pattern, the best first.
+    matches: Vec<usize>,
+    selected: usize,
+    /// The request whose items are still to come.
+    ticket: Option<Ticket>,
+    incomplete: bool,
+}
+
+impl Completion {
+    /// The words are offered along with what the server sends for the ticket.
+    pub fn new(start: Cursor, origin: Cursor, words: Vec<String>, ticket: Option<Ticket>) -> Self {
+        Completion {
+            start,
+            origin,
+            words: words.len(),
+            pattern: String::new(),
+            items: words
+                .into_iter()
+                .map(|word| CompletionItem {
+                    label: word,
+                    kind: Some(CompletionItemKind::TEXT),
+                    ..Default::default()
+                })
+                .collect(),
+            matches: Vec::new(),
+            selected: 0,
+            ticket,
+            incomplete: false,
+        }
+    }
+
+    pub fn start(&self) -> Cursor {
+        self.start
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn ticket(&self) -> Option<Ticket> {
+        self.ticket
+    }
+
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+
+    /// Takes what the server has sent for the ticket in place of what it sent before. The
+    /// items take the place of words with the same label.
+    pub fn set_items(&mut self, items: Vec<CompletionItem>, incomplete: bool) {
+        self.items.truncate(self.words);
+        self.items
+            .retain(|word| !items.iter().any(|item| item.label == word.label));
+        self.words = self.items.len();
+        self.items.extend(items);
+        self.incomplete = incomplete;
+        self.ticket = None;
+    }
+
+    /// Waits for the items of a new request, sent with the cursor at the origin.
+    pub fn request_again(&mut self, origin: Cursor, ticket: Ticket) {
+        self.origin = origin;
+        self.ticket = Some(ticket);
+    }
+
+    /// Keeps the items matching the pattern, the best first.
+    pub fn filter(&mut self, pattern: &str) {
+        let mut matches: Vec<(i64, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, item)| {
+                let text = item.filter_text.as_deref().unwrap_or(&item.label);
+
+                fuzzy_score(pattern, text).map(|score| (score, idx))
+            })
+            .collect();
+
+        matches.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .cmp(a_score)
+                .then_with(|| sort_text(&self.items[*a]).cmp(sort_text(&self.items[*b])))
+        });
+        self.matches = matches.into_iter().map(|(_, idx)| idx).collect();
+        self.selected = 0;
+        self.pattern = pattern.to_owned();
+    }
+
+    pub fn matches(&self) -> impl Iterator<Item = &CompletionItem> {
+        self.matches.iter().map(|idx| &self.items[*idx])
+    }
+
+    pub fn has_matches(&self) -> bool {
+        !self.matches.is_empty()
+    }
+
+    /// The index among the matches of the selected item.
+    pub fn selected_idx(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected(&self) -> Option<&CompletionItem> {
+        self.matches.get(self.selected).map(|idx| &self.items[*idx])
+    }
+
+    /// Selects the next (`forward`) or previous match, wrapping round.
+    pub fn select(&mut self, forward: bool) {
+        let len = self.matches.len();
+
+        if len > 0 {
+            self.selected = if forward {
+                (self.selected + 1) % len
+            } else {
+                (self.selected + len - 1) % len
+            };
+        }
+    }
+
+    /// Inserts the selected item in place of the word before the cursor, along with the other
+    /// edits which come with it. Returns where the cursor goes: the first tab stop of a snippet,
+    /// or else the end of the insertion.
+    pub fn accept(&self, buffer: &mut Buffer, cursor: Cursor) -> Option<Cursor> {
+        let item = self.selected()?;
+        let (range, text) = match &item.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => (
+                self.edit_range(buffer, cursor, edit.range),
+                edit.new_text.as_str(),
+            ),
+            Some(CompletionTextEdit::InsertAndReplace(edit)) => (
+                self.edit_range(buffer, cursor, edit.replace),
+                edit.new_text.as_str(),
+            ),
+            None => (
+                (self.start, cursor),
+                item.insert_text.as_deref().unwrap_or(&item.label),
+            ),
+        };
+        let (text, stop) = if item.insert_text_format == Some(InsertTextFormat::SNIPPET) {
+            expand_snippet(text)
+        } else {
+            (text.to_owned(), None)
+        };
+        let stop = stop.unwrap_or(text.len());
+        let start = buffer.create_marker(range.0);
+        let mut edits: Vec<(Cursor, Cursor, &str)> = item
+            .additional_text_edits
+            .iter()
+            .flatten()
+            .map(|edit| {
+                let (start, end) = self.edit_range(buffer, cursor, edit.range);
+
+                (start, end, edit.new_text.as_str())
+            })
+            .collect();
+
+        edits.push((range.0, range.1, &text));
+        // From the last so that the edits before are not moved.
+        edits.sort_by_key(|(start, ..)| std::cmp::Reverse((start.1, start.0)));
+
+        for (start, end, text) in edits {
+            buffer.replace(start, end, text);
+        }
+
+        Some(advance(start.get(), &text[..stop]))
+    }
+
+    /// The cursors of a range sent for the origin. Positions after the origin on its line are
+    /// shifted by what has been typed since, so that the typed word is replaced too.
+    fn edit_range(&self, buffer: &Buffer, cursor: Cursor, range: Range) -> (Cursor, Cursor) {
+        let origin = buffer.lsp_position(self.origin);
+        let typed = buffer.lsp_position(cursor).character as i64 - origin.character as i64;
+        let shift = |position: Position| {
+            let position = if position.line == origin.line && position.character >= origin.character
+            {
+                Position {
+                    character: (position.character as i64 + typed).max(0) as u32,
+                    ..position
+                }
+            } else {
+                position
+            };
+
+            buffer.clamp(buffer.cursor_of_position(position))
+        };
+
+        (shift(range.start), shift(range.end))
+    }
+}
+
+fn sort_text(item: &CompletionItem) -> &str {
+    item.sort_text.as_deref().unwrap_or(&item.label)
+}
+
+/// Scores how well the pattern matches the text as a subsequence, ignoring case, or `None` if
+/// it does not. Matches at the start of the text or of a word within it, and runs of
+/// consecutive characters, score higher.
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<i64> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut score = 0;
+    let mut idx = 0;
+    let mut last = None;
+
+    for p in pattern.chars() {
+        loop {
+            let c = *chars.get(idx)?;
+
+            if c.to_lowercase().eq(p.to_lowercase()) {
+                score += 1;
+
+                if idx == 0 {
+                    score += 8;
+                } else if chars[idx - 1] == '_'
+                    || (chars[idx - 1].is_lowercase() && c.is_uppercase())
+                {
+                    score += 4;
+                }
+
+                if last.is_some() && last == idx.checked_sub(1) {
+                    score += 4;
+                }
+
+                if c == p {
+                    score += 1;
+                }
+
+                last = Some(idx);
+                idx += 1;
+                break;
+            }
+
+            idx += 1;
+        }
+    }
+
+    // Of two equally good matches the shorter text is the closer.
+    Some(score * 64 - chars.len() as i64)
+}
+
+/// Expands a snippet to its text, placeholders replaced by their defaults and choices by their
+/// first option. Returns it with the byte offset of the first tab stop, `$0` only coming last.
+pub fn expand_snippet(snippet: &str) -> (String, Option<usize>) {
+    let mut text = String::new();
+    let mut stops = Vec::new();
+
+    parse_snippet(
+        &mut snippet.chars().peekable(),
+        &mut text,
+        &mut stops,
+        false,
+    );
+
+    let stop = stops
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .min_by_key(|(n, _)| *n)
+        .or_else(|| stops.first())
+        .map(|(_, offset)| *offset);
+
+    (text, stop)
+}
+
+fn parse_snippet(
+    chars: &mut Peekable<Chars>,
+    text: &mut String,
+    stops: &mut Vec<(u32, usize)>,
+    nested: bool,
+) {
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            '}' if nested => return,
+            '$' => match chars.peek() {
+                Some(c) if c.is_ascii_digit() => stops.push((read_number(chars), text.len())),
+                Some(c) if c.is_alphabetic() || *c == '_' => {
+                    // A variable, which is left empty.
+                    read_name(chars);
+                }
+                Some('{') => {
+                    chars.next();
+
+                    let stop = match chars.peek() {
+                        Some(c) if c.is_ascii_digit() => Some(read_number(chars)),
+                        _ => {
+                            read_name(chars);
+                            None
+                        }
+                    };
+
+                    if let Some(n) = stop {
+                        stops.push((n, text.len()));
+                    }
+
+                    match chars.next() {
+                        Some(':') => parse_snippet(chars, text, stops, true),
+                        Some('|') => {
+                            let choices: String =
+                                chars.by_ref().take_while(|c| *c != '|').collect();
+
+                            text.push_str(choices.split(',').next().unwrap_or_default());
+                            chars.next();
+                        }
+                        _ => {}
+                    }
+                }
+                _ => text.push('$'),
+            },
+            c => text.push(c),
+        }
+    }
+}
+
+fn read_number(chars: &mut Peekable<Chars>) -> u32 {
+    let mut n = 0;
+
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        n = n * 10 + digit;
+        chars.next();
+    }
+
+    n
+}
+
+fn read_name(chars: &mut Peekable<Chars>) {
+    while chars
+        .peek()
+        .is_some_and(|c| c.is_alphanumeric() || *c == '_')
+    {
+        chars.next();
+    }
+}
+
+/// The detail and documentation of the item, as plain lines.
+pub fn documentation(item: &CompletionItem) -> Vec<String> {
+    let mut lines: Vec<String> = item.detail.iter().map(|detail| detail.to_owned()).collect();
+    let documentation = match &item.documentation {
+        Some(Documentation::String(text)) => text.as_str(),
+        Some(Documentation::MarkupContent(content)) => content.value.as_str(),
+        None => "",
+    };
+
+    if !lines.is_empty() && !documentation.is_empty() {
+        lines.push(String::new());
+    }
+
+    lines.extend(markdown_lines(documentation));
+    lines
+}
+
+/// A short name for the kind of the item.
+pub fn kind_name(item: &CompletionItem) -> &'static str {
+    match item.kind {
+        Some(CompletionItemKind::METHOD) => "method",
+        Some(CompletionItemKind::FUNCTION) => "fn",
+        Some(CompletionItemKind::CONSTRUCTOR) => "new",
+        Some(CompletionItemKind::FIELD) => "field",
+        Some(CompletionItemKind::VARIABLE) => "var",
+        Some(CompletionItemKind::CLASS) | Some(CompletionItemKind::STRUCT) => "struct",
+        Some(CompletionItemKind::INTERFACE) => "trait",
+        Some(CompletionItemKind::MODULE) => "mod",
+        Some(CompletionItemKind::PROPERTY) => "prop",
+        Some(CompletionItemKind::ENUM) => "enum",
+        Some(CompletionItemKind::ENUM_MEMBER) => "variant",
+        Some(CompletionItemKind::KEYWORD) => "keyword",
+        Some(CompletionItemKind::SNIPPET) => "snippet",
+        Some(CompletionItemKind::CONSTANT) => "const",
+        Some(CompletionItemKind::TYPE_PARAMETER) => "type",
+        Some(CompletionItemKind::TEXT) | None => "word",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::TextEdit;
+
+    #[test]
+    fn fuzzy_order() {
+        assert_eq!(fuzzy_score("xy", "yx"), None);
+        assert!(fuzzy_score("", "anything").is_some());
+
+        let mut completion = Completion::new(
+            (0, 0),
+            (0, 0),
+            ["set_text", "selected_text", "text_set", "st"]
+                .iter()
+                .map(|word| word.to_string())
+                .collect(),
+            None,
+        );
+
+        completion.filter("st");
+
+        let labels: Vec<&str> = completion
+            .matches()
+            .map(|item| item.label.as_str())
+            .collect();
+
+        assert_eq!(labels, vec!["st", "set_text", "selected_text", "text_set"]);
+
+        completion.select(false);
+        assert_eq!(completion.selected().unwrap().label, "text_set");
+        completion.select(true);
+        assert_eq!(completion.selected().unwrap().label, "st");
+    }
+
+    #[test]
+    fn snippets() {
+        assert_eq!(expand_snippet("plain"), ("plain".to_owned(), None));
+        assert_eq!(
+            expand_snippet("f(${1:a}, ${2:b${3:c}})$0"),
+            ("f(a, bc)".to_owned(), Some(2))
+        );
+        assert_eq!(
+            expand_snippet("${2:x} \\$${1|one,two|} ${TM_FILENAME:name}$0"),
+            ("x $one name".to_owned(), Some(3))
+        );
+        assert_eq!(expand_snippet("a$0b"), ("ab".to_owned(), Some(1)));
+    }
+
+    #[test]
+    fn accept_snippet() {
+        let mut buffer = Buffer::new();
+
+        buffer.insert_str((0, 0), "fn main() {");
+        buffer.break_line((11, 0));
+        buffer.insert_str((0, 1), "    fo");
+
+        let mut completion = Completion::new(buffer.word_start((6, 1)), (6, 1), vec![], None);
+
+        completion.set_items(
+            vec![CompletionItem {
+                label: "format!".to_owned(),
+                insert_text: Some("format!(\"${1:fmt}\")".to_owned()),
+                insert_text_format: Some(InsertTextFormat::SNIPPET),
+                additional_text_edits: Some(vec![TextEdit {
+                    range: Range::default(),
+                    new_text: "use std::fmt;\n\n".to_owned(),
+                }]),
+                ..Default::default()
+            }],
+            false,
+        );
+        completion.filter("fo");
+
+        assert_eq!(completion.accept(&mut buffer, (6, 1)), Some((13, 3)));
+        assert_eq!(
+            buffer.text_full(),
+            "use std::fmt;\n\nfn main() {\n    format!(\"fmt\")"
+        );
+    }
+}
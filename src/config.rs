@@ -0,0 +1,67 @@
+use crate::lsp::ServerConfig;
+use crate::mode_line::Segment;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub mode_line: Vec<Segment>,
+    /// The language server started for each language, by language name.
+    pub language_servers: HashMap<String, ServerConfig>,
+    /// Whether buffers are formatted by their language servers when they are saved.
+    pub format_on_save: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mode_line: vec![
+                Segment::Modified,
+                Segment::BufferName,
+                Segment::Position,
+                Segment::FileType,
+                Segment::XMode,
+            ],
+            language_servers: HashMap::from([(
+                "rust".to_owned(),
+                ServerConfig {
+                    command: "rust-analyzer".to_owned(),
+                    args: Vec::new(),
+                    roots: vec!["Cargo.toml".to_owned()],
+                },
+            )]),
+            format_on_save: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Loads `config.toml` from the config directory, falling back to the defaults.
+    pub fn load_default() -> Self {
+        let path = match config_dir() {
+            Some(dir) => dir.join("config.toml"),
+            None => return Config::default(),
+        };
+
+        if !path.exists() {
+            return Config::default();
+        }
+
+        Config::load(&path).unwrap_or_else(|e| {
+            warn!("failed to load config {:?}: {}", path, e);
+            Config::default()
+        })
+    }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/editor-like-tui"))
+}
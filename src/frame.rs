@@ -1,54 +1,190 @@
-use crate::buffer::{Buffer, TAB};
+use crate::buffer::{advance, Buffer, Marker, TAB};
+use crate::session::FrameState;
+use crate::syntax::Language;
+use crate::wrap::{line_rows, row_of, SoftWrap};
 use std::cell::RefCell;
 use std::rc::Rc;
-use tui::layout::{Constraint, Direction as LayoutDirection, Layout, Rect};
-use tui::widgets::Borders;
+use tui::layout::{Direction as LayoutDirection, Rect};
+use tui::widgets::{Block, Borders};
 
 pub type Direction = LayoutDirection;
 pub type Cursor = (usize, usize);
 pub type Offset = (usize, usize);
+pub type InnerFrames = (Rc<RefCell<Frame>>, Rc<RefCell<Frame>>);
+
+pub const MODE_LINE_HEIGHT: u16 = 1;
+pub const SIGN_COLUMN_WIDTH: u16 = 1;
+pub const SPLIT_RATIO_STEP: i16 = 5;
+const SPLIT_RATIO_MIN: i16 = 10;
+const SPLIT_RATIO_MAX: i16 = 90;
+/// The smallest frame showing a bit of text next to the gutter, with its border.
+pub const MIN_FRAME_WIDTH: u16 = 12;
+/// One row of text, the mode line and the border.
+pub const MIN_FRAME_HEIGHT: u16 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    Relative,
+}
+
+pub struct RenderedFrame {
+    pub rect: Rect,
+    pub text_area: Rect,
+    pub gutter: Rect,
+    pub mode_line: Rect,
+    pub buffer: Rc<RefCell<Buffer>>,
+    pub borders: Borders,
+    pub offset: Offset,
+    pub cursor: Option<Cursor>,
+    pub position: Cursor,
+    pub region: Option<(Cursor, Cursor)>,
+    pub focus: bool,
+    pub x_mode: bool,
+    pub line_numbers: LineNumbers,
+    pub soft_wrap: SoftWrap,
+}
 
 pub struct Frame {
-    inner_frames: Option<(Rc<RefCell<Frame>>, Rc<RefCell<Frame>>)>,
+    inner_frames: Option<InnerFrames>,
     split_direction: Direction,
+    split_ratio: u16,
     buffer: Rc<RefCell<Buffer>>,
     cursor: Cursor,
     offset: Offset,
+    mark: Option<Cursor>,
+    cursor_marker: Marker,
+    mark_marker: Marker,
+    top_marker: Marker,
+    line_numbers: LineNumbers,
+    soft_wrap: SoftWrap,
+    text_width: usize,
     border_flag: Borders,
     focus: bool,
     x_mode: bool,
     show: bool,
     has_parent: bool,
+    rect: Rect,
 }
 
 impl Frame {
     pub fn new(buffer: Rc<RefCell<Buffer>>) -> Self {
+        let cursor_marker = buffer.borrow_mut().create_marker((0, 0));
+        let mark_marker = buffer.borrow_mut().create_marker((0, 0));
+        let top_marker = buffer.borrow_mut().create_marker((0, 0));
+
         Frame {
             inner_frames: None,
             split_direction: Direction::Horizontal,
+            split_ratio: 50,
             buffer,
             cursor: (0, 0),
             offset: (0, 0),
+            mark: None,
+            cursor_marker,
+            mark_marker,
+            top_marker,
+            line_numbers: LineNumbers::Absolute,
+            soft_wrap: SoftWrap::Off,
+            text_width: 0,
             border_flag: Borders::NONE,
             focus: false,
             x_mode: false,
             show: true,
             has_parent: false,
+            rect: Rect::default(),
         }
     }
 
     pub fn inherit(frame: &Frame, additional_border_flag: Borders) -> Self {
+        let mut buffer = frame.buffer.borrow_mut();
+
         Frame {
             inner_frames: None,
             split_direction: Direction::Horizontal,
+            split_ratio: 50,
             buffer: frame.buffer.clone(),
             cursor: frame.cursor,
             offset: frame.offset,
+            mark: None,
+            cursor_marker: buffer.create_marker(frame.cursor),
+            mark_marker: buffer.create_marker(frame.cursor),
+            top_marker: buffer.create_marker(frame.top_marker.get()),
+            line_numbers: frame.line_numbers,
+            soft_wrap: frame.soft_wrap,
+            text_width: frame.text_width,
             border_flag: frame.border_flag | additional_border_flag,
             focus: false,
             x_mode: false,
             show: true,
             has_parent: true,
+            rect: Rect::default(),
+        }
+    }
+
+    /// The frame and the frames inside it as kept in a session.
+    pub fn state(&self) -> FrameState {
+        match self.inner_frames {
+            Some((ref f0, ref f1)) => FrameState::Split {
+                direction: (&self.split_direction).into(),
+                ratio: self.split_ratio,
+                frames: Box::new((f0.borrow().state(), f1.borrow().state())),
+            },
+            None => FrameState::Leaf {
+                buffer: self.buffer.borrow().name().to_owned(),
+                cursor: if self.focus {
+                    self.cursor
+                } else {
+                    self.cursor_marker.get()
+                },
+                offset: (self.offset.0, self.top_marker.get().1),
+            },
+        }
+    }
+
+    /// Builds a main frame back from a session, looking its buffers up by name. No frame has
+    /// the focus yet.
+    pub fn from_state(state: &FrameState, buffer: &dyn Fn(&str) -> Rc<RefCell<Buffer>>) -> Self {
+        let mut frame = Frame::restore(state, buffer);
+
+        frame.update_borders(Borders::NONE);
+        frame
+    }
+
+    fn restore(state: &FrameState, buffer: &dyn Fn(&str) -> Rc<RefCell<Buffer>>) -> Self {
+        match state {
+            FrameState::Split {
+                direction,
+                ratio,
+                frames,
+            } => {
+                let mut f0 = Frame::restore(&frames.0, buffer);
+                let mut f1 = Frame::restore(&frames.1, buffer);
+                let mut frame = Frame::new(f0.clone_buffer());
+
+                f0.has_parent = true;
+                f1.has_parent = true;
+                frame.inner_frames = Some((Rc::new(RefCell::new(f0)), Rc::new(RefCell::new(f1))));
+                frame.split_direction = (*direction).into();
+                frame.split_ratio = (*ratio).clamp(SPLIT_RATIO_MIN as u16, SPLIT_RATIO_MAX as u16);
+                frame
+            }
+            FrameState::Leaf {
+                buffer: name,
+                cursor,
+                offset,
+            } => {
+                let mut frame = Frame::new(buffer(name));
+                let cursor = frame.buffer.borrow().clamp(*cursor);
+                let top = frame.buffer.borrow().clamp((0, offset.1));
+
+                frame.cursor = cursor;
+                frame.cursor_marker.set(cursor);
+                frame.top_marker.set(top);
+                frame.offset.0 = offset.0;
+                frame
+            }
         }
     }
 
@@ -60,6 +196,14 @@ impl Frame {
         self.buffer.borrow().lines_len()
     }
 
+    fn is_read_only(&self) -> bool {
+        self.buffer.borrow().is_read_only()
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        self.cursor
+    }
+
     fn line_len(&self) -> usize {
         self.buffer.borrow().line_len(self.cursor)
     }
@@ -74,32 +218,143 @@ impl Frame {
         }
     }
 
+    fn next_shown_line(&self, forward: bool) -> Option<usize> {
+        self.buffer.borrow().next_shown_line(self.cursor.1, forward)
+    }
+
     pub fn move_right(&mut self) {
         if self.line_len() > self.cursor.0 {
             self.cursor.0 += 1;
-        } else if self.lines_len() - 1 > self.cursor.1 {
+        } else if let Some(line) = self.next_shown_line(true) {
             self.cursor.0 = 0;
-            self.cursor.1 += 1;
+            self.cursor.1 = line;
         }
     }
 
-    pub fn move_up(&mut self) {
-        if self.cursor.1 > 0 {
-            self.cursor.1 -= 1;
+    /// Where each visual row of the line starts, a single row unless the frame soft-wraps.
+    /// The cursor of a frame out of focus can be past the end of a shared buffer.
+    fn rows(&self, line: usize) -> Vec<usize> {
+        if line >= self.lines_len() {
+            return vec![0];
+        }
 
-            if self.cursor.0 > self.line_len() {
-                self.cursor.0 = self.line_len();
+        line_rows(&self.buffer.borrow(), line, self.soft_wrap, self.text_width)
+    }
+
+    /// The position `col` columns into the given visual row, kept inside that row.
+    fn row_position(&self, line: usize, points: &[usize], row: usize, col: usize) -> usize {
+        let end = match points.get(row + 1) {
+            Some(next) => next - 1,
+            None => self.display_col((self.line_len_idx(line), line)),
+        };
+
+        self.buffer
+            .borrow()
+            .position_of_display_col(line, (points[row] + col).min(end))
+    }
+
+    /// The column the position is shown at, which is further than its byte past inlay hints.
+    fn display_col(&self, cursor: Cursor) -> usize {
+        if cursor.1 >= self.lines_len() {
+            return cursor.0;
+        }
+
+        self.buffer.borrow().display_col(cursor)
+    }
+
+    /// The row of the cursor counted from the top of the buffer in visual rows.
+    fn visual_row(&self) -> usize {
+        self.visual_row_of(self.cursor)
+    }
+
+    fn visual_row_of(&self, cursor: Cursor) -> usize {
+        let buffer = self.buffer.borrow();
+        let rows_before: usize = match self.soft_wrap {
+            SoftWrap::Off => buffer.visual_line(cursor.1),
+            _ => buffer
+                .shown_lines()
+                .take_while(|line| *line < cursor.1)
+                .map(|line| line_rows(&buffer, line, self.soft_wrap, self.text_width).len())
+                .sum(),
+        };
+
+        rows_before + row_of(&self.rows(cursor.1), self.display_col(cursor))
+    }
+
+    /// The buffer line shown at the given visual row.
+    fn line_at_visual_row(&self, row: usize) -> usize {
+        let buffer = self.buffer.borrow();
+        let mut rows = 0;
+
+        for line in buffer.shown_lines() {
+            rows += line_rows(&buffer, line, self.soft_wrap, self.text_width).len();
+
+            if rows > row {
+                return line;
             }
         }
+
+        buffer.lines_len() - 1
+    }
+
+    /// Writes the cursor and the mark into the markers. The focused frame owns its positions,
+    /// every other frame takes them back from the markers which followed the edits in between.
+    fn store_positions(&self) {
+        self.cursor_marker.set(self.cursor);
+
+        if let Some(mark) = self.mark {
+            self.mark_marker.set(mark);
+        }
+    }
+
+    /// Makes the positions and the markers agree, whichever of them is up to date.
+    fn sync_positions(&mut self) {
+        if self.focus {
+            self.store_positions();
+        } else {
+            self.load_positions();
+        }
+    }
+
+    fn load_positions(&mut self) {
+        self.cursor = self.cursor_marker.get();
+
+        if self.mark.is_some() {
+            self.mark = Some(self.mark_marker.get());
+        }
+
+        self.offset.1 = self.visual_row_of((0, self.top_marker.get().1));
+    }
+
+    pub fn move_up(&mut self) {
+        let points = self.rows(self.cursor.1);
+        let display_col = self.display_col(self.cursor);
+        let row = row_of(&points, display_col);
+        let col = display_col - points[row];
+
+        if row > 0 {
+            self.cursor.0 = self.row_position(self.cursor.1, &points, row - 1, col);
+        } else if let Some(line) = self.next_shown_line(false) {
+            let points = self.rows(line);
+
+            self.cursor.1 = line;
+            self.cursor.0 = self.row_position(line, &points, points.len() - 1, col);
+        }
     }
 
     pub fn move_down(&mut self) {
-        if self.lines_len() - 1 > self.cursor.1 {
-            self.cursor.1 += 1;
+        let points = self.rows(self.cursor.1);
+        let display_col = self.display_col(self.cursor);
+        let row = row_of(&points, display_col);
+        let col = display_col - points[row];
 
-            if self.cursor.0 > self.line_len() {
-                self.cursor.0 = self.line_len();
-            }
+        if row + 1 < points.len() {
+            self.cursor.0 = self.row_position(self.cursor.1, &points, row + 1, col);
+        } else if let Some(line) = self.next_shown_line(true) {
+            let points = self.rows(line);
+
+            self.cursor.1 = line;
+            self.cursor.0 = self.row_position(line, &points, 0, col);
         }
     }
 
@@ -119,25 +374,120 @@ impl Frame {
     pub fn move_bottom(&mut self) {
         self.cursor.0 = self.line_len_idx(self.lines_len() - 1);
         self.cursor.1 = self.lines_len() - 1;
+        self.buffer.borrow_mut().unfold_line(self.cursor.1);
+    }
+
+    /// Moves the cursor to the position, unfolding its line. An unfocused frame keeps it in its
+    /// marker, which it takes the cursor from.
+    pub fn move_to(&mut self, cursor: Cursor) {
+        self.cursor = self.buffer.borrow().clamp(cursor);
+        self.buffer.borrow_mut().unfold_line(self.cursor.1);
+
+        if !self.focus {
+            self.store_positions();
+        }
+    }
+
+    /// Moves to the next (`forward`) or previous diagnostic.
+    pub fn move_to_diagnostic(&mut self, forward: bool) {
+        let next = self.buffer.borrow().next_diagnostic(self.cursor, forward);
+
+        if let Some(cursor) = next {
+            self.move_to(cursor);
+        }
+    }
+
+    pub fn expand_region(&mut self) {
+        let (start, end) = self.region().unwrap_or((self.cursor, self.cursor));
+        let expanded = self.buffer.borrow().enclosing_range(start, end);
+
+        if let Some((start, end)) = expanded {
+            self.mark = Some(start);
+            self.cursor = end;
+            self.buffer.borrow_mut().unfold_line(start.1);
+            self.buffer.borrow_mut().unfold_line(end.1);
+        }
+    }
+
+    pub fn clear_region(&mut self) {
+        self.mark = None;
+    }
+
+    /// The ordered range between the mark and the cursor, if the mark is set.
+    pub fn region(&self) -> Option<(Cursor, Cursor)> {
+        self.mark.map(|mark| {
+            if (mark.1, mark.0) < (self.cursor.1, self.cursor.0) {
+                (mark, self.cursor)
+            } else {
+                (self.cursor, mark)
+            }
+        })
+    }
+
+    pub fn toggle_fold(&mut self) {
+        self.buffer.borrow_mut().toggle_fold(self.cursor);
+
+        if self.buffer.borrow().is_hidden(self.cursor.1) {
+            if let Some(line) = self.next_shown_line(false) {
+                self.cursor.1 = line;
+                self.cursor.0 = self.cursor.0.min(self.line_len());
+            }
+        }
+    }
+
+    pub fn toggle_line_numbers(&mut self) {
+        self.line_numbers = match self.line_numbers {
+            LineNumbers::Off => LineNumbers::Absolute,
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Off,
+        };
+    }
+
+    pub fn toggle_soft_wrap(&mut self) {
+        self.soft_wrap = match self.soft_wrap {
+            SoftWrap::Off => SoftWrap::Char,
+            SoftWrap::Char => SoftWrap::Word,
+            SoftWrap::Word => SoftWrap::Off,
+        };
+        self.offset.0 = 0;
+    }
+
+    pub fn toggle_language(&mut self) {
+        let language = match self.buffer.borrow().language() {
+            Some(_) => None,
+            None => Some(Language::Rust),
+        };
+
+        self.buffer.borrow_mut().set_language(language);
     }
 
     pub fn new_char(&mut self, c: char) {
+        if self.is_read_only() {
+            return;
+        }
+
+        self.mark = None;
         self.buffer.borrow_mut().insert_char(self.cursor, c);
         self.cursor.0 += 1;
     }
 
     pub fn new_line(&mut self) {
-        let right = self.buffer.borrow_mut().split_off(self.cursor);
+        if self.is_read_only() {
+            return;
+        }
 
+        self.mark = None;
+        self.buffer.borrow_mut().break_line(self.cursor);
         self.cursor.0 = 0;
         self.cursor.1 += 1;
-
-        self.buffer
-            .borrow_mut()
-            .insert_line((0, self.cursor.1), &right);
     }
 
     pub fn tab(&mut self) {
+        if self.is_read_only() {
+            return;
+        }
+
+        self.mark = None;
         self.buffer.borrow_mut().insert_str((0, self.cursor.1), TAB);
         self.cursor.0 += TAB.len();
     }
@@ -147,40 +497,48 @@ impl Frame {
     }
 
     pub fn backspace(&mut self) {
+        if self.is_read_only() {
+            return;
+        }
+
+        self.mark = None;
+
         if self.cursor.0 > 0 {
             self.cursor.0 -= 1;
             self.buffer.borrow_mut().remove_char(self.cursor);
         } else if self.cursor.1 > 0 {
-            let deleted = self.buffer.borrow_mut().delete_line(self.cursor);
-
             self.cursor.1 -= 1;
             self.cursor.0 = self.line_len();
-            self.buffer.borrow_mut().push_str(self.cursor, &deleted);
+            self.buffer.borrow_mut().join_line(self.cursor.1);
         }
     }
 
     pub fn delete(&mut self) {
+        if self.is_read_only() {
+            return;
+        }
+
+        self.mark = None;
+
         if self.cursor.0 < self.line_len() {
             self.buffer.borrow_mut().remove_char(self.cursor);
         } else if self.lines_len() - 1 > self.cursor.1 {
-            let deleted = self
-                .buffer
-                .borrow_mut()
-                .delete_line((self.cursor.0, self.cursor.1 + 1));
-            self.buffer.borrow_mut().push_str(self.cursor, &deleted);
+            self.buffer.borrow_mut().join_line(self.cursor.1);
         }
     }
 
     pub fn kill(&mut self) -> Option<String> {
+        if self.is_read_only() {
+            return None;
+        }
+
+        self.mark = None;
+
         if self.cursor.0 < self.line_len() {
             let removed = self.buffer.borrow_mut().split_off(self.cursor);
             Some(removed)
         } else if self.lines_len() - 1 > self.cursor.1 {
-            let removed = self
-                .buffer
-                .borrow_mut()
-                .delete_line((self.cursor.0, self.cursor.1 + 1));
-            self.buffer.borrow_mut().push_str(self.cursor, &removed);
+            self.buffer.borrow_mut().join_line(self.cursor.1);
             Some("\n".to_owned())
         } else {
             None
@@ -188,8 +546,13 @@ impl Frame {
     }
 
     pub fn paste(&mut self, s: &str) {
+        if self.is_read_only() {
+            return;
+        }
+
+        self.mark = None;
         self.buffer.borrow_mut().insert_str(self.cursor, s);
-        self.cursor.0 += s.len();
+        self.cursor = advance(self.cursor, s);
     }
 
     pub fn is_x_mode(&self) -> bool {
@@ -197,13 +560,7 @@ impl Frame {
     }
 
     pub fn split(&mut self, direction: Direction) {
-        let additional_border_flag = if direction == Direction::Horizontal {
-            Borders::RIGHT
-        } else {
-            Borders::BOTTOM
-        };
-
-        let mut f0 = Frame::inherit(self, additional_border_flag);
+        let mut f0 = Frame::inherit(self, split_border(&direction));
         let f1 = Frame::inherit(self, Borders::NONE);
 
         if self.focus {
@@ -213,6 +570,117 @@ impl Frame {
 
         self.inner_frames = Some((Rc::new(RefCell::new(f0)), Rc::new(RefCell::new(f1))));
         self.split_direction = direction;
+        self.split_ratio = 50;
+    }
+
+    /// Turns the split holding the focused frame from side by side into one above the other or
+    /// back. Returns false if the focused frame is not split from anything.
+    pub fn flip_focus_split(&mut self) -> bool {
+        if let Some((ref f0, ref f1)) = self.inner_frames {
+            let child = if f0.borrow().has_focus() { f0 } else { f1 };
+
+            if child.borrow().has_inner_frames() && child.borrow_mut().flip_focus_split() {
+                return true;
+            }
+
+            self.split_direction = match self.split_direction {
+                Direction::Horizontal => Direction::Vertical,
+                Direction::Vertical => Direction::Horizontal,
+            };
+            self.update_borders(self.border_flag);
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Sets the borders of the frame and recomputes the ones of the frames inside it.
+    fn update_borders(&mut self, border_flag: Borders) {
+        self.border_flag = border_flag;
+
+        if let Some((ref f0, ref f1)) = self.inner_frames {
+            f0.borrow_mut()
+                .update_borders(border_flag | split_border(&self.split_direction));
+            f1.borrow_mut().update_borders(border_flag);
+        }
+    }
+
+    /// Exchanges the buffers of two leaves along with the cursors, marks and scroll positions.
+    pub fn swap_buffers(a: &Rc<RefCell<Frame>>, b: &Rc<RefCell<Frame>>) {
+        if Rc::ptr_eq(a, b) {
+            return;
+        }
+
+        let (mut a, mut b) = (a.borrow_mut(), b.borrow_mut());
+
+        a.sync_positions();
+        b.sync_positions();
+
+        std::mem::swap(&mut a.buffer, &mut b.buffer);
+        std::mem::swap(&mut a.cursor, &mut b.cursor);
+        std::mem::swap(&mut a.offset, &mut b.offset);
+        std::mem::swap(&mut a.mark, &mut b.mark);
+        std::mem::swap(&mut a.cursor_marker, &mut b.cursor_marker);
+        std::mem::swap(&mut a.mark_marker, &mut b.mark_marker);
+        std::mem::swap(&mut a.top_marker, &mut b.top_marker);
+    }
+
+    /// Grows (or shrinks with a negative delta) the focused frame by moving the border of the
+    /// nearest split in the given direction. Returns false if there is no such split.
+    pub fn resize_focus(&mut self, direction: Direction, delta: i16) -> bool {
+        if let Some((ref f0, ref f1)) = self.inner_frames {
+            let focus_in_f0 = f0.borrow().has_focus();
+            let child = if focus_in_f0 { f0 } else { f1 };
+
+            if child.borrow_mut().resize_focus(direction.clone(), delta) {
+                return true;
+            }
+
+            if self.split_direction == direction {
+                let delta = if focus_in_f0 { delta } else { -delta };
+
+                self.split_ratio = (self.split_ratio as i16 + delta)
+                    .clamp(SPLIT_RATIO_MIN, SPLIT_RATIO_MAX)
+                    as u16;
+
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Sets every split ratio so that the frames side by side get the same size, as far as the
+    /// ratio limits allow.
+    pub fn balance(&mut self) {
+        if let Some((ref f0, ref f1)) = self.inner_frames {
+            f0.borrow_mut().balance();
+            f1.borrow_mut().balance();
+
+            let w0 = f0.borrow().weight(&self.split_direction);
+            let w1 = f1.borrow().weight(&self.split_direction);
+
+            self.split_ratio = ((w0 * 100 / (w0 + w1)) as u16)
+                .clamp(SPLIT_RATIO_MIN as u16, SPLIT_RATIO_MAX as u16);
+        }
+    }
+
+    /// How many frames are lined up in the direction inside this one.
+    fn weight(&self, direction: &Direction) -> usize {
+        match self.inner_frames {
+            Some((ref f0, ref f1)) => {
+                let w0 = f0.borrow().weight(direction);
+                let w1 = f1.borrow().weight(direction);
+
+                if self.split_direction == *direction {
+                    w0 + w1
+                } else {
+                    w0.max(w1)
+                }
+            }
+            None => 1,
+        }
     }
 
     pub fn has_focus(&self) -> bool {
@@ -231,7 +699,7 @@ impl Frame {
         self.buffer.clone()
     }
 
-    pub fn clone_inner_frames(&self) -> (Rc<RefCell<Frame>>, Rc<RefCell<Frame>>) {
+    pub fn clone_inner_frames(&self) -> InnerFrames {
         self.inner_frames
             .as_ref()
             .map(|(f0, f1)| (f0.clone(), f1.clone()))
@@ -242,6 +710,10 @@ impl Frame {
         if let Some((ref mut f0, _)) = self.inner_frames {
             f0.borrow_mut().set_focus();
         } else {
+            if !self.focus {
+                self.load_positions();
+            }
+
             self.focus = true;
 
             if self.cursor.1 > self.lines_len() - 1 {
@@ -251,6 +723,8 @@ impl Frame {
             if self.cursor.0 > self.line_len() {
                 self.cursor.0 = self.line_len();
             }
+
+            self.buffer.borrow_mut().unfold_line(self.cursor.1);
         }
     }
 
@@ -273,7 +747,215 @@ impl Frame {
         }
     }
 
+    /// The frames without inner frames in the same order as `render` returns them.
+    pub fn leaves(frame: &Rc<RefCell<Frame>>) -> Vec<Rc<RefCell<Frame>>> {
+        match frame.borrow().inner_frames {
+            Some((ref f0, ref f1)) => {
+                let mut leaves = Frame::leaves(f0);
+                leaves.extend(Frame::leaves(f1));
+                leaves
+            }
+            None => vec![frame.clone()],
+        }
+    }
+
+    /// The area the frame was rendered into last time.
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// Where the cursor was drawn on screen last time, used to break ties between neighbours.
+    pub fn screen_cursor(&self) -> (u16, u16) {
+        let area = self.text_area(self.rect);
+
+        (
+            (area.x as usize + self.display_col(self.cursor).saturating_sub(self.offset.0)) as u16,
+            (area.y as usize + self.visual_row().saturating_sub(self.offset.1)) as u16,
+        )
+    }
+
+    /// The buffer position shown at the screen point. Points outside the text give the nearest
+    /// position, below the last line its end.
+    fn position_at(&self, x: u16, y: u16) -> Cursor {
+        let area = self.text_area(self.rect);
+        let row =
+            self.offset.1 + y.saturating_sub(area.y).min(area.height.saturating_sub(1)) as usize;
+        let col = x.saturating_sub(area.x) as usize;
+        let buffer = self.buffer.borrow();
+        let mut rows = 0;
+        let mut last = 0;
+
+        for line in buffer.shown_lines() {
+            let points = line_rows(&buffer, line, self.soft_wrap, self.text_width);
+
+            if row < rows + points.len() {
+                let col = match self.soft_wrap {
+                    SoftWrap::Off => self.offset.0 + col,
+                    _ => col,
+                };
+
+                return buffer.clamp((self.row_position(line, &points, row - rows, col), line));
+            }
+
+            rows += points.len();
+            last = line;
+        }
+
+        (buffer.line_len_idx(last), last)
+    }
+
+    /// Moves the cursor to the screen point and drops the mark.
+    pub fn click(&mut self, x: u16, y: u16) {
+        self.cursor = self.position_at(x, y);
+        self.mark = None;
+    }
+
+    /// Extends the region from where the dragging started to the screen point.
+    pub fn drag(&mut self, x: u16, y: u16) {
+        if self.mark.is_none() {
+            self.mark = Some(self.cursor);
+        }
+
+        self.cursor = self.position_at(x, y);
+    }
+
+    /// Scrolls the view by the number of rows, taking the cursor along so that it stays at the
+    /// same place on the screen.
+    pub fn scroll(&mut self, rows: usize, down: bool) {
+        self.sync_positions();
+
+        for _ in 0..rows {
+            if down {
+                self.move_down();
+            } else {
+                self.move_up();
+            }
+        }
+
+        self.offset.1 = if down {
+            (self.offset.1 + rows).min(self.visual_row())
+        } else {
+            self.offset.1.saturating_sub(rows)
+        };
+        self.top_marker
+            .set((0, self.line_at_visual_row(self.offset.1)));
+        self.store_positions();
+    }
+
+    /// The split whose border between its two frames is at the screen point.
+    pub fn split_at(frame: &Rc<RefCell<Frame>>, x: u16, y: u16) -> Option<Rc<RefCell<Frame>>> {
+        let (f0, f1) = match frame.borrow().inner_frames {
+            Some((ref f0, ref f1)) => (f0.clone(), f1.clone()),
+            None => return None,
+        };
+        let r = f0.borrow().rect;
+        let on_border = match frame.borrow().split_direction {
+            Direction::Horizontal => x + 1 == r.right() && (r.top()..r.bottom()).contains(&y),
+            Direction::Vertical => y + 1 == r.bottom() && (r.left()..r.right()).contains(&x),
+        };
+
+        if on_border {
+            return Some(frame.clone());
+        }
+
+        Frame::split_at(&f0, x, y).or_else(|| Frame::split_at(&f1, x, y))
+    }
+
+    /// The smallest size the frame can take along the direction without squeezing a frame in it
+    /// below the minimum.
+    pub fn min_size(&self, direction: &Direction) -> u16 {
+        match self.inner_frames {
+            Some((ref f0, ref f1)) => {
+                let (min0, min1) = (
+                    f0.borrow().min_size(direction),
+                    f1.borrow().min_size(direction),
+                );
+
+                if self.split_direction == *direction {
+                    min0 + min1
+                } else {
+                    min0.max(min1)
+                }
+            }
+            None => match direction {
+                Direction::Horizontal => MIN_FRAME_WIDTH,
+                Direction::Vertical => MIN_FRAME_HEIGHT,
+            },
+        }
+    }
+
+    /// Whether both halves would be at least of the minimum size. A frame which has not been
+    /// drawn yet can always be split.
+    pub fn can_split(&self, direction: &Direction) -> bool {
+        match direction {
+            _ if self.rect == Rect::default() => true,
+            Direction::Horizontal => self.rect.width >= MIN_FRAME_WIDTH * 2,
+            Direction::Vertical => self.rect.height >= MIN_FRAME_HEIGHT * 2,
+        }
+    }
+
+    /// Divides the area between the two frames by the ratio, keeping both of them at least of
+    /// their minimum size. When there is no room for that, the frame holding the focus, or the
+    /// first one, takes the whole area and the other is hidden.
+    fn split_rects(&self, r: Rect) -> (Rect, Rect) {
+        let (f0, f1) = self.clone_inner_frames();
+        let (min0, min1) = (
+            f0.borrow().min_size(&self.split_direction),
+            f1.borrow().min_size(&self.split_direction),
+        );
+        let len = match self.split_direction {
+            Direction::Horizontal => r.width,
+            Direction::Vertical => r.height,
+        };
+
+        let first = if len >= min0 + min1 {
+            (((len as u32 * self.split_ratio as u32 + 50) / 100) as u16).clamp(min0, len - min1)
+        } else if f1.borrow().has_focus() {
+            0
+        } else {
+            len
+        };
+
+        match self.split_direction {
+            Direction::Horizontal => (
+                Rect { width: first, ..r },
+                Rect {
+                    x: r.x + first,
+                    width: len - first,
+                    ..r
+                },
+            ),
+            Direction::Vertical => (
+                Rect { height: first, ..r },
+                Rect {
+                    y: r.y + first,
+                    height: len - first,
+                    ..r
+                },
+            ),
+        }
+    }
+
+    /// Moves the border between the two frames of the split to the screen point.
+    pub fn move_border(&mut self, x: u16, y: u16) {
+        let (position, start, len) = match self.split_direction {
+            Direction::Horizontal => (x, self.rect.x, self.rect.width),
+            Direction::Vertical => (y, self.rect.y, self.rect.height),
+        };
+
+        if len > 0 {
+            let ratio =
+                ((position.saturating_sub(start) + 1) as u32 * 100 + len as u32 / 2) / len as u32;
+
+            self.split_ratio = (ratio as u16).clamp(SPLIT_RATIO_MIN as u16, SPLIT_RATIO_MAX as u16);
+        }
+    }
+
     pub fn clear_focus(&mut self) {
+        if self.focus && !self.has_inner_frames() {
+            self.store_positions();
+        }
+
         self.focus = false;
 
         if let Some((ref mut f0, ref mut f1)) = self.inner_frames {
@@ -321,18 +1003,20 @@ impl Frame {
                 if f0.borrow().has_inner_frames() {
                     self.inner_frames = Some(f0.borrow().clone_inner_frames());
                     self.split_direction = f0.borrow().split_direction.clone();
+                    self.split_ratio = f0.borrow().split_ratio;
                 } else {
                     self.inner_frames = None;
-                    self.buffer = f0.borrow().clone_buffer();
+                    self.take_positions(&f0.borrow());
                 }
             }
             (false, true) => {
                 if f1.borrow().has_inner_frames() {
                     self.inner_frames = Some(f1.borrow().clone_inner_frames());
                     self.split_direction = f1.borrow().split_direction.clone();
+                    self.split_ratio = f1.borrow().split_ratio;
                 } else {
                     self.inner_frames = None;
-                    self.buffer = f1.borrow().clone_buffer();
+                    self.take_positions(&f1.borrow());
                 }
             }
             _ => {
@@ -343,79 +1027,144 @@ impl Frame {
         self.set_focus();
     }
 
+    /// Becomes the leaf `frame` was, showing its buffer where it was showing it.
+    fn take_positions(&mut self, frame: &Frame) {
+        frame.store_positions();
+
+        self.buffer = frame.clone_buffer();
+        self.mark = frame.mark;
+        self.cursor_marker = frame.cursor_marker.clone();
+        self.mark_marker = frame.mark_marker.clone();
+        self.top_marker = frame.top_marker.clone();
+        self.line_numbers = frame.line_numbers;
+        self.soft_wrap = frame.soft_wrap;
+        self.focus = false;
+    }
+
+    /// The sign column plus the line numbers and a space after them, if they are shown.
+    fn gutter_width(&self) -> u16 {
+        if self.line_numbers == LineNumbers::Off {
+            return SIGN_COLUMN_WIDTH;
+        }
+
+        let digits = self.lines_len().to_string().len().max(3) as u16;
+
+        SIGN_COLUMN_WIDTH + digits + 1
+    }
+
+    fn inner_area(&self, r: Rect) -> Rect {
+        Block::default().borders(self.border_flag).inner(r)
+    }
+
+    fn gutter_area(&self, r: Rect) -> Rect {
+        let inner = self.inner_area(r);
+
+        Rect {
+            width: self.gutter_width().min(inner.width),
+            height: inner.height.saturating_sub(MODE_LINE_HEIGHT),
+            ..inner
+        }
+    }
+
+    /// The area left for the text once the borders, the gutter and the mode line are taken out.
+    fn text_area(&self, r: Rect) -> Rect {
+        let inner = self.inner_area(r);
+        let gutter = self.gutter_area(r);
+
+        Rect {
+            x: gutter.right(),
+            width: inner.width - gutter.width,
+            ..gutter
+        }
+    }
+
+    fn mode_line_area(&self, r: Rect) -> Rect {
+        let inner = self.inner_area(r);
+        let height = inner.height.min(MODE_LINE_HEIGHT);
+
+        Rect {
+            y: inner.bottom() - height,
+            height,
+            ..inner
+        }
+    }
+
     fn adjust_offset(&mut self, rect: &Rect) {
-        if self.offset.0 > self.cursor.0 {
-            self.offset.0 = self.cursor.0;
-        } else {
-            if self.border_flag.intersects(Borders::RIGHT) {
-                if rect.width < 2 {
-                    self.offset.0 = self.cursor.0;
-                } else if self.cursor.0 > self.offset.0 + rect.width as usize - 2 {
-                    self.offset.0 = self.cursor.0 + 2 - rect.width as usize;
-                }
-            } else {
-                if rect.width < 1 {
-                    self.offset.0 = self.cursor.0;
-                } else if self.cursor.0 > self.offset.0 + rect.width as usize - 1 {
-                    self.offset.0 = self.cursor.0 + 1 - rect.width as usize;
-                }
-            }
+        let area = self.text_area(*rect);
+
+        self.text_width = area.width as usize;
+
+        let row = self.visual_row();
+        let col = self.display_col(self.cursor);
+
+        if self.soft_wrap != SoftWrap::Off {
+            self.offset.0 = 0;
+        } else if self.offset.0 > col || area.width < 1 {
+            self.offset.0 = col;
+        } else if col > self.offset.0 + area.width as usize - 1 {
+            self.offset.0 = col + 1 - area.width as usize;
         }
 
-        if self.offset.1 > self.cursor.1 {
-            self.offset.1 = self.cursor.1;
-        } else {
-            if self.border_flag.intersects(Borders::BOTTOM) {
-                if rect.height < 2 {
-                    self.offset.1 = self.cursor.1;
-                } else if self.cursor.1 > self.offset.1 + rect.height as usize - 2 {
-                    self.offset.1 = self.cursor.1 + 2 - rect.height as usize;
-                }
-            } else {
-                if rect.height < 1 {
-                    self.offset.1 = self.cursor.1;
-                } else if self.cursor.1 > self.offset.1 + rect.height as usize - 1 {
-                    self.offset.1 = self.cursor.1 + 1 - rect.height as usize;
-                }
-            }
+        if self.offset.1 > row || area.height < 1 {
+            self.offset.1 = row;
+        } else if row > self.offset.1 + area.height as usize - 1 {
+            self.offset.1 = row + 1 - area.height as usize;
         }
     }
 
-    pub fn render(
-        &mut self,
-        r: Rect,
-    ) -> Vec<(Rect, Rc<RefCell<Buffer>>, Borders, Offset, Option<Cursor>)> {
+    pub fn render(&mut self, r: Rect) -> Vec<RenderedFrame> {
+        self.rect = r;
+
+        if !self.has_inner_frames() {
+            self.sync_positions();
+        }
+
         self.adjust_offset(&r);
 
+        if !self.has_inner_frames() {
+            let top = self.line_at_visual_row(self.offset.1);
+            self.top_marker.set((0, top));
+        }
+
         if let Some((ref f0, ref f1)) = self.inner_frames {
-            let chunks = Layout::default()
-                .direction(self.split_direction.clone())
-                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-                .margin(0)
-                .split(r);
+            let chunks = self.split_rects(r);
 
-            let mut r0 = f0.borrow_mut().render(chunks[0]);
-            let r1 = f1.borrow_mut().render(chunks[1]);
+            let mut r0 = f0.borrow_mut().render(chunks.0);
+            let r1 = f1.borrow_mut().render(chunks.1);
 
             r0.extend(r1);
             r0
         } else {
+            let text_area = self.text_area(r);
             let cursor = if self.focus {
+                let points = self.rows(self.cursor.1);
+                let col = self.display_col(self.cursor);
+                let row_start = points[row_of(&points, col)];
+
                 Some((
-                    self.cursor.0 + r.x as usize - self.offset.0,
-                    self.cursor.1 + r.y as usize - self.offset.1,
+                    col - row_start + text_area.x as usize - self.offset.0,
+                    self.visual_row() + text_area.y as usize - self.offset.1,
                 ))
             } else {
                 None
             };
 
-            vec![(
-                r,
-                self.buffer.clone(),
-                self.border_flag,
-                self.offset,
+            vec![RenderedFrame {
+                rect: r,
+                text_area,
+                gutter: self.gutter_area(r),
+                mode_line: self.mode_line_area(r),
+                buffer: self.buffer.clone(),
+                borders: self.border_flag,
+                offset: self.offset,
                 cursor,
-            )]
+                position: self.cursor,
+                region: self.region(),
+                focus: self.focus,
+                x_mode: self.x_mode,
+                line_numbers: self.line_numbers,
+                soft_wrap: self.soft_wrap,
+            }]
         }
     }
 
@@ -423,6 +1172,11 @@ impl Frame {
         let old_buffer = self.buffer.clone();
         self.buffer = new_buffer;
         self.cursor = (0, 0);
+        self.offset = (0, 0);
+        self.mark = None;
+        self.cursor_marker = self.buffer.borrow_mut().create_marker((0, 0));
+        self.mark_marker = self.buffer.borrow_mut().create_marker((0, 0));
+        self.top_marker = self.buffer.borrow_mut().create_marker((0, 0));
         old_buffer
     }
 
@@ -436,3 +1190,12 @@ impl Frame {
         self.show
     }
 }
+
+/// The border a split draws on its first frame, between the two.
+fn split_border(direction: &Direction) -> Borders {
+    if *direction == Direction::Horizontal {
+        Borders::RIGHT
+    } else {
+        Borders::BOTTOM
+    }
+}
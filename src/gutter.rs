@@ -0,0 +1,57 @@
+use crate::frame::{LineNumbers, RenderedFrame, SIGN_COLUMN_WIDTH};
+use crate::theme::Theme;
+use crate::wrap::line_rows;
+use tui::text::{Span, Spans};
+
+/// One row per visual row in view: a sign (or a blank) and, if they are shown, the line number
+/// right-aligned on the first row of each line, blanks on the rows continuing a soft-wrapped line.
+pub fn gutter_text(rendered_frame: &RenderedFrame, theme: &Theme) -> Vec<Spans<'static>> {
+    let buffer = rendered_frame.buffer.borrow();
+    let width = (rendered_frame
+        .gutter
+        .width
+        .saturating_sub(SIGN_COLUMN_WIDTH + 1)) as usize;
+    let current = rendered_frame.position.1;
+
+    let text_width = rendered_frame.text_area.width as usize;
+
+    buffer
+        .shown_lines()
+        .flat_map(|line| {
+            let rows = line_rows(&buffer, line, rendered_frame.soft_wrap, text_width).len();
+
+            (0..rows).map(move |row| (line, row))
+        })
+        .skip(rendered_frame.offset.1)
+        .take(rendered_frame.gutter.height as usize)
+        .map(|(line, row)| {
+            if row > 0 {
+                return Spans::from(" ".repeat(rendered_frame.gutter.width as usize));
+            }
+
+            let sign = match buffer.sign(line) {
+                Some(sign) => Span::styled(sign.symbol.to_string(), theme.ui(sign.style)),
+                None => Span::raw(" "),
+            };
+
+            let number = match rendered_frame.line_numbers {
+                LineNumbers::Off => return Spans::from(sign),
+                LineNumbers::Relative if line != current => {
+                    (line as isize - current as isize).unsigned_abs()
+                }
+                _ => line + 1,
+            };
+
+            let style = if line == current {
+                theme.ui("line_number_current")
+            } else {
+                theme.ui("line_number")
+            };
+
+            Spans::from(vec![
+                sign,
+                Span::styled(format!("{:>width$} ", number, width = width), style),
+            ])
+        })
+        .collect()
+}
@@ -0,0 +1,2423 @@
+use crate::buffer::Buffer;
+use crate::frame::Cursor;
+use bytes::{Buf, BytesMut};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Exit, Initialized,
+    Notification, PublishDiagnostics,
+};
+use lsp_types::request::{
+    ApplyWorkspaceEdit, CodeActionRequest, Completion, DocumentSymbolRequest, ExecuteCommand,
+    Formatting, GotoDefinition, HoverRequest, Initialize, InlayHintRequest, References, Rename,
+    Request, SemanticTokensFullDeltaRequest, SemanticTokensFullRequest, Shutdown,
+    SignatureHelpRequest, WorkspaceSymbol,
+};
+use lsp_types::{
+    ApplyWorkspaceEditParams, ApplyWorkspaceEditResponse, ClientCapabilities, ClientInfo,
+    CodeActionClientCapabilities, CodeActionContext, CodeActionKind, CodeActionKindLiteralSupport,
+    CodeActionLiteralSupport, CodeActionOrCommand, CodeActionParams, CodeActionProviderCapability,
+    CompletionClientCapabilities, CompletionContext, CompletionItem, CompletionItemCapability,
+    CompletionParams, CompletionResponse, CompletionTriggerKind, Diagnostic,
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentFormattingParams, DocumentSymbol, DocumentSymbolClientCapabilities,
+    DocumentSymbolParams, DocumentSymbolResponse, ExecuteCommandParams, FormattingOptions,
+    GotoCapability, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverClientCapabilities,
+    HoverContents, HoverParams, HoverProviderCapability, InitializeParams, InitializeResult,
+    InitializedParams, InlayHint, InlayHintClientCapabilities, InlayHintParams, Location,
+    MarkedString, MarkupKind, OneOf, ParameterInformationSettings, ParameterLabel,
+    PartialResultParams, Position, PublishDiagnosticsClientCapabilities, PublishDiagnosticsParams,
+    Range, ReferenceContext, ReferenceParams, RenameParams, SemanticToken, SemanticTokenModifier,
+    SemanticTokenType, SemanticTokensClientCapabilities, SemanticTokensClientCapabilitiesRequests,
+    SemanticTokensDeltaParams, SemanticTokensEdit, SemanticTokensFullDeltaResult,
+    SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions, SemanticTokensParams,
+    SemanticTokensServerCapabilities, ServerCapabilities, SignatureHelp,
+    SignatureHelpClientCapabilities, SignatureHelpContext, SignatureHelpParams,
+    SignatureHelpTriggerKind, SignatureInformationSettings, SymbolInformation, SymbolKind,
+    TextDocumentClientCapabilities, TextDocumentContentChangeEvent, TextDocumentIdentifier,
+    TextDocumentItem, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncClientCapabilities, TextDocumentSyncKind, TextEdit, TokenFormat,
+    VersionedTextDocumentIdentifier, WorkDoneProgressParams, WorkspaceClientCapabilities,
+    WorkspaceEdit, WorkspaceEditClientCapabilities, WorkspaceFolder, WorkspaceSymbolParams,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::mpsc::{self, error::TryRecvError, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+use url::Url;
+
+const CONTENT_LENGTH: &str = "Content-Length";
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// How to start the language server of a language.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Files marking the root of a project, e.g. `Cargo.toml`. The nearest directory above a
+    /// file which has one of them is the root the server is started for.
+    #[serde(default)]
+    pub roots: Vec<String>,
+}
+
+/// Tells which request a response event answers.
+pub type Ticket = u64;
+
+/// Something a server has sent which the editor has to show.
+#[derive(Debug)]
+pub enum Event {
+    Diagnostics(PublishDiagnosticsParams),
+    Completion {
+        ticket: Ticket,
+        items: Vec<CompletionItem>,
+        /// Whether typing further should ask again rather than only filter the items.
+        incomplete: bool,
+    },
+    Definition {
+        ticket: Ticket,
+        locations: Vec<Location>,
+    },
+    References {
+        ticket: Ticket,
+        locations: Vec<Location>,
+    },
+    /// Markdown, empty if there is nothing to show.
+    Hover {
+        ticket: Ticket,
+        text: String,
+    },
+    Formatting {
+        ticket: Ticket,
+        edits: Vec<TextEdit>,
+    },
+    CodeActions {
+        ticket: Ticket,
+        actions: Vec<CodeActionOrCommand>,
+    },
+    /// `None` if there is nothing to rename.
+    Rename {
+        ticket: Ticket,
+        edit: Option<WorkspaceEdit>,
+    },
+    /// An edit the server wants made, e.g. when running a command.
+    ApplyEdit(WorkspaceEdit),
+    /// `None` once the cursor is out of any call.
+    SignatureHelp {
+        ticket: Ticket,
+        help: Option<SignatureHelp>,
+    },
+    InlayHints {
+        ticket: Ticket,
+        hints: Vec<InlayHint>,
+    },
+    /// All the tokens of the document, in order.
+    SemanticTokens {
+        ticket: Ticket,
+        tokens: Vec<SemanticSpan>,
+    },
+    DocumentSymbols {
+        ticket: Ticket,
+        symbols: Vec<Symbol>,
+    },
+    WorkspaceSymbols {
+        ticket: Ticket,
+        symbols: Vec<SymbolInformation>,
+    },
+}
+
+/// A symbol of a document with the ones inside it, e.g. the methods of an impl block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Where its name is.
+    pub position: Position,
+    pub children: Vec<Symbol>,
+}
+
+impl Symbol {
+    fn from_document_symbol(symbol: DocumentSymbol) -> Self {
+        Symbol {
+            name: symbol.name,
+            kind: symbol.kind,
+            position: symbol.selection_range.start,
+            children: symbol
+                .children
+                .into_iter()
+                .flatten()
+                .map(Symbol::from_document_symbol)
+                .collect(),
+        }
+    }
+}
+
+/// A range of a document which the server has classified, e.g. as a mutable variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticSpan {
+    pub range: Range,
+    pub token_type: String,
+    pub modifiers: Vec<String>,
+}
+
+/// What a request awaiting its response was sent for.
+#[derive(Debug, Clone)]
+enum Pending {
+    Completion,
+    Definition,
+    References,
+    Hover,
+    Formatting,
+    CodeActions,
+    Rename,
+    ExecuteCommand,
+    SignatureHelp,
+    InlayHints,
+    /// The tokens of the document, whole or as a delta.
+    SemanticTokens(Url),
+    DocumentSymbols,
+    WorkspaceSymbols,
+}
+
+impl Pending {
+    fn is_supported(&self, capabilities: &ServerCapabilities) -> bool {
+        match self {
+            Pending::Completion => capabilities.completion_provider.is_some(),
+            Pending::Definition => is_enabled(&capabilities.definition_provider),
+            Pending::References => is_enabled(&capabilities.references_provider),
+            Pending::Hover => !matches!(
+                capabilities.hover_provider,
+                None | Some(HoverProviderCapability::Simple(false))
+            ),
+            Pending::Formatting => is_enabled(&capabilities.document_formatting_provider),
+            Pending::CodeActions => !matches!(
+                capabilities.code_action_provider,
+                None | Some(CodeActionProviderCapability::Simple(false))
+            ),
+            Pending::Rename => is_enabled(&capabilities.rename_provider),
+            Pending::ExecuteCommand => capabilities.execute_command_provider.is_some(),
+            Pending::SignatureHelp => capabilities.signature_help_provider.is_some(),
+            Pending::InlayHints => is_enabled(&capabilities.inlay_hint_provider),
+            Pending::DocumentSymbols => is_enabled(&capabilities.document_symbol_provider),
+            Pending::WorkspaceSymbols => is_enabled(&capabilities.workspace_symbol_provider),
+            Pending::SemanticTokens(_) => !matches!(
+                semantic_tokens_options(capabilities).map(|options| &options.full),
+                None | Some(None | Some(SemanticTokensFullOptions::Bool(false)))
+            ),
+        }
+    }
+
+    fn event(self, ticket: Ticket, result: Value) -> Option<Event> {
+        let event = match self {
+            Pending::Completion => serde_json::from_value(result).map(|response| {
+                let (items, incomplete) = match response {
+                    Some(CompletionResponse::Array(items)) => (items, false),
+                    Some(CompletionResponse::List(list)) => (list.items, list.is_incomplete),
+                    None => (Vec::new(), false),
+                };
+
+                Event::Completion {
+                    ticket,
+                    items,
+                    incomplete,
+                }
+            }),
+            Pending::Definition => serde_json::from_value(result).map(|response| {
+                let locations = match response {
+                    Some(GotoDefinitionResponse::Scalar(location)) => vec![location],
+                    Some(GotoDefinitionResponse::Array(locations)) => locations,
+                    Some(GotoDefinitionResponse::Link(links)) => links
+                        .into_iter()
+                        .map(|link| Location::new(link.target_uri, link.target_selection_range))
+                        .collect(),
+                    None => Vec::new(),
+                };
+
+                Event::Definition { ticket, locations }
+            }),
+            Pending::References => {
+                serde_json::from_value(result).map(|locations: Option<Vec<Location>>| {
+                    Event::References {
+                        ticket,
+                        locations: locations.unwrap_or_default(),
+                    }
+                })
+            }
+            Pending::Hover => {
+                serde_json::from_value(result).map(|hover: Option<Hover>| Event::Hover {
+                    ticket,
+                    text: hover
+                        .map(|hover| hover_text(hover.contents))
+                        .unwrap_or_default(),
+                })
+            }
+            Pending::Formatting => {
+                serde_json::from_value(result).map(|edits: Option<Vec<TextEdit>>| {
+                    Event::Formatting {
+                        ticket,
+                        edits: edits.unwrap_or_default(),
+                    }
+                })
+            }
+            Pending::CodeActions => {
+                serde_json::from_value(result).map(|actions: Option<_>| Event::CodeActions {
+                    ticket,
+                    actions: actions.unwrap_or_default(),
+                })
+            }
+            Pending::Rename => {
+                serde_json::from_value(result).map(|edit| Event::Rename { ticket, edit })
+            }
+            // What a command does comes as edits to apply.
+            Pending::ExecuteCommand => return None,
+            Pending::SignatureHelp => {
+                serde_json::from_value(result).map(|help: Option<SignatureHelp>| {
+                    Event::SignatureHelp {
+                        ticket,
+                        help: help.filter(|help| !help.signatures.is_empty()),
+                    }
+                })
+            }
+            Pending::InlayHints => {
+                serde_json::from_value(result).map(|hints: Option<Vec<InlayHint>>| {
+                    Event::InlayHints {
+                        ticket,
+                        hints: hints.unwrap_or_default(),
+                    }
+                })
+            }
+            // The client decodes them, keeping the tokens which deltas are made against.
+            Pending::SemanticTokens(_) => return None,
+            Pending::DocumentSymbols => {
+                serde_json::from_value(result).map(|symbols: Option<DocumentSymbolResponse>| {
+                    let symbols = match symbols {
+                        Some(DocumentSymbolResponse::Nested(symbols)) => symbols
+                            .into_iter()
+                            .map(Symbol::from_document_symbol)
+                            .collect(),
+                        Some(DocumentSymbolResponse::Flat(symbols)) => symbols
+                            .into_iter()
+                            .map(|symbol| Symbol {
+                                name: symbol.name,
+                                kind: symbol.kind,
+                                position: symbol.location.range.start,
+                                children: Vec::new(),
+                            })
+                            .collect(),
+                        None => Vec::new(),
+                    };
+
+                    Event::DocumentSymbols { ticket, symbols }
+                })
+            }
+            Pending::WorkspaceSymbols => {
+                serde_json::from_value(result).map(|symbols: Option<_>| Event::WorkspaceSymbols {
+                    ticket,
+                    symbols: symbols.unwrap_or_default(),
+                })
+            }
+        };
+
+        event
+            .map_err(|e| warn!("invalid {:?} result: {}", self, e))
+            .ok()
+    }
+}
+
+/// A JSON-RPC message. Ids are kept as they came, since servers may use strings.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Request {
+        id: Value,
+        method: String,
+        params: Value,
+    },
+    Response {
+        id: Value,
+        result: Result<Value, Value>,
+    },
+    Notification {
+        method: String,
+        params: Value,
+    },
+}
+
+impl Message {
+    fn parse(mut value: Value) -> Option<Self> {
+        let id = value.get_mut("id").map(Value::take);
+        let method = value
+            .get("method")
+            .and_then(Value::as_str)
+            .map(|method| method.to_owned());
+        let params = value.get_mut("params").map(Value::take).unwrap_or_default();
+
+        match (id, method) {
+            (Some(id), Some(method)) => Some(Message::Request { id, method, params }),
+            (None, Some(method)) => Some(Message::Notification { method, params }),
+            (Some(id), None) => {
+                let result = match value.get_mut("error") {
+                    Some(error) => Err(error.take()),
+                    None => Ok(value.get_mut("result").map(Value::take).unwrap_or_default()),
+                };
+
+                Some(Message::Response { id, result })
+            }
+            (None, None) => None,
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Message::Request { id, method, params } => {
+                json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+            }
+            Message::Response {
+                id,
+                result: Ok(result),
+            } => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Message::Response {
+                id,
+                result: Err(error),
+            } => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+            Message::Notification { method, params } => {
+                json!({ "jsonrpc": "2.0", "method": method, "params": params })
+            }
+        }
+    }
+}
+
+/// Appends the message to the buffer, preceded by its header.
+pub fn encode(message: &Message, buf: &mut BytesMut) {
+    let body = message.to_value().to_string();
+
+    buf.extend_from_slice(format!("{}: {}\r\n\r\n", CONTENT_LENGTH, body.len()).as_bytes());
+    buf.extend_from_slice(body.as_bytes());
+}
+
+/// Takes the first message off the buffer, or returns `None` if it has not been read whole yet.
+pub fn decode(buf: &mut BytesMut) -> io::Result<Option<Message>> {
+    let header_len = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(idx) => idx + 4,
+        None => return Ok(None),
+    };
+    let invalid =
+        |e: &dyn std::fmt::Display| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+    let header = std::str::from_utf8(&buf[..header_len]).map_err(|e| invalid(&e))?;
+    let content_length = header
+        .split("\r\n")
+        .filter_map(|line| line.split_once(": "))
+        .find(|(name, _)| name.eq_ignore_ascii_case(CONTENT_LENGTH))
+        .ok_or_else(|| invalid(&"missing Content-Length"))?
+        .1
+        .trim()
+        .parse::<usize>()
+        .map_err(|e| invalid(&e))?;
+
+    if buf.len() < header_len + content_length {
+        return Ok(None);
+    }
+
+    buf.advance(header_len);
+
+    let body = buf.split_to(content_length);
+    let value = serde_json::from_slice(&body).map_err(|e| invalid(&e))?;
+
+    Message::parse(value)
+        .map(Some)
+        .ok_or_else(|| invalid(&"neither a request, a response nor a notification"))
+}
+
+fn is_enabled<T>(capability: &Option<OneOf<bool, T>>) -> bool {
+    !matches!(capability, None | Some(OneOf::Left(false)))
+}
+
+/// The markdown of hover contents, the parts separated by blank lines.
+fn hover_text(contents: HoverContents) -> String {
+    let marked = |marked: MarkedString| match marked {
+        MarkedString::String(text) => text,
+        MarkedString::LanguageString(code) => {
+            format!("```{}\n{}\n```", code.language, code.value)
+        }
+    };
+
+    match contents {
+        HoverContents::Scalar(text) => marked(text),
+        HoverContents::Array(texts) => texts
+            .into_iter()
+            .map(marked)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        HoverContents::Markup(content) => content.value,
+    }
+}
+
+/// The label of the active signature, with the byte range of its active parameter in it.
+pub fn active_signature(help: &SignatureHelp) -> Option<(&str, Option<std::ops::Range<usize>>)> {
+    let signature = help
+        .signatures
+        .get(help.active_signature.unwrap_or_default() as usize)
+        .or(help.signatures.first())?;
+    let label = signature.label.as_str();
+    let parameter = signature
+        .active_parameter
+        .or(help.active_parameter)
+        .and_then(|idx| signature.parameters.as_ref()?.get(idx as usize));
+    let range = parameter.and_then(|parameter| match &parameter.label {
+        ParameterLabel::Simple(name) => label
+            .find(name.as_str())
+            .map(|start| start..start + name.len()),
+        ParameterLabel::LabelOffsets([start, end]) => {
+            let byte = |offset: u32| {
+                let mut units = 0;
+
+                label
+                    .char_indices()
+                    .find(|(_, c)| {
+                        units += c.len_utf16() as u32;
+                        units > offset
+                    })
+                    .map_or(label.len(), |(idx, _)| idx)
+            };
+
+            Some(byte(*start)..byte(*end))
+        }
+    });
+
+    Some((label, range))
+}
+
+fn semantic_tokens_options(capabilities: &ServerCapabilities) -> Option<&SemanticTokensOptions> {
+    match capabilities.semantic_tokens_provider.as_ref()? {
+        SemanticTokensServerCapabilities::SemanticTokensOptions(options) => Some(options),
+        SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(options) => {
+            Some(&options.semantic_tokens_options)
+        }
+    }
+}
+
+/// Applies the edits of a delta to the tokens it was made against. Their offsets count the
+/// integers the tokens are sent as, five to a token. Returns `None` if they do not fit.
+fn apply_token_edits(
+    mut tokens: Vec<SemanticToken>,
+    mut edits: Vec<SemanticTokensEdit>,
+) -> Option<Vec<SemanticToken>> {
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.start));
+
+    for edit in edits {
+        let (start, count) = (edit.start as usize, edit.delete_count as usize);
+
+        if start % 5 != 0 || count % 5 != 0 || (start + count) / 5 > tokens.len() {
+            return None;
+        }
+
+        tokens.splice(
+            start / 5..(start + count) / 5,
+            edit.data.unwrap_or_default(),
+        );
+    }
+
+    Some(tokens)
+}
+
+/// The tokens with the positions they are sent relative to each other made absolute, and the
+/// indices into the legend turned into names.
+fn decode_tokens(tokens: &[SemanticToken], legend: &SemanticTokensLegend) -> Vec<SemanticSpan> {
+    let mut line = 0;
+    let mut start = 0;
+
+    tokens
+        .iter()
+        .filter_map(|token| {
+            if token.delta_line > 0 {
+                line += token.delta_line;
+                start = token.delta_start;
+            } else {
+                start += token.delta_start;
+            }
+
+            let token_type = legend.token_types.get(token.token_type as usize)?;
+            let modifiers = legend
+                .token_modifiers
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| *idx < 32 && token.token_modifiers_bitset & (1 << idx) != 0)
+                .map(|(_, modifier)| modifier.as_str().to_owned())
+                .collect();
+
+            Some(SemanticSpan {
+                range: Range::new(
+                    Position::new(line, start),
+                    Position::new(line, start + token.length),
+                ),
+                token_type: token_type.as_str().to_owned(),
+                modifiers,
+            })
+        })
+        .collect()
+}
+
+/// A short name of the kind of a symbol, like `fn`.
+pub fn symbol_kind_name(kind: SymbolKind) -> &'static str {
+    match kind {
+        SymbolKind::FILE => "file",
+        SymbolKind::MODULE | SymbolKind::NAMESPACE | SymbolKind::PACKAGE => "mod",
+        SymbolKind::CLASS | SymbolKind::STRUCT => "struct",
+        SymbolKind::OBJECT => "impl",
+        SymbolKind::METHOD | SymbolKind::FUNCTION | SymbolKind::CONSTRUCTOR => "fn",
+        SymbolKind::PROPERTY | SymbolKind::FIELD => "field",
+        SymbolKind::ENUM => "enum",
+        SymbolKind::INTERFACE => "trait",
+        SymbolKind::VARIABLE => "let",
+        SymbolKind::CONSTANT => "const",
+        SymbolKind::ENUM_MEMBER => "variant",
+        SymbolKind::TYPE_PARAMETER => "type",
+        _ => "symbol",
+    }
+}
+
+/// The lines of markdown to show as plain text, without the fences of code blocks.
+pub fn markdown_lines(text: &str) -> Vec<String> {
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .map(|line| line.to_owned())
+        .collect()
+}
+
+/// A server process, talked to over its stdin and stdout by two tasks.
+pub struct LanguageServer {
+    child: Child,
+    outgoing: UnboundedSender<Message>,
+    incoming: UnboundedReceiver<Message>,
+    next_id: i64,
+}
+
+impl LanguageServer {
+    /// The messages notify `messages` as they come, and once more when the server has exited.
+    pub fn spawn(config: &ServerConfig, root: &Path, messages: Arc<Notify>) -> io::Result<Self> {
+        if tokio::runtime::Handle::try_current().is_err() {
+            return Err(io::Error::other("language servers need a tokio runtime"));
+        }
+
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+
+        tokio::spawn(write_messages(child.stdin.take().unwrap(), outgoing_rx));
+        let stdout = child.stdout.take().unwrap();
+
+        tokio::spawn(async move {
+            read_messages(stdout, incoming_tx, &messages).await;
+            messages.notify_one();
+        });
+
+        Ok(LanguageServer {
+            child,
+            outgoing,
+            incoming,
+            next_id: 0,
+        })
+    }
+
+    /// Sends the request and returns its id, which the response will carry.
+    pub fn request<R: Request>(&mut self, params: R::Params) -> Value {
+        let id = Value::from(self.next_id);
+
+        self.next_id += 1;
+        self.send(Message::Request {
+            id: id.clone(),
+            method: R::METHOD.to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        });
+
+        id
+    }
+
+    pub fn notify<N: Notification>(&self, params: N::Params) {
+        self.send(Message::Notification {
+            method: N::METHOD.to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        });
+    }
+
+    pub fn send(&self, message: Message) {
+        // The reader notices when the server has gone, so a failed send needs no handling.
+        let _ = self.outgoing.send(message);
+    }
+
+    /// The next message from the server if one has come, or an error once it has exited.
+    pub fn try_recv(&mut self) -> Result<Option<Message>, TryRecvError> {
+        match self.incoming.try_recv() {
+            Ok(message) => Ok(Some(message)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Asks the server to shut down and exit, killing it if it takes too long.
+    pub async fn shutdown(mut self) {
+        let id = self.request::<Shutdown>(());
+        let response = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+            while let Some(message) = self.incoming.recv().await {
+                if matches!(&message, Message::Response { id: i, .. } if *i == id) {
+                    return true;
+                }
+            }
+
+            false
+        });
+
+        if let Ok(true) = response.await {
+            self.notify::<Exit>(());
+
+            if tokio::time::timeout(SHUTDOWN_TIMEOUT, self.child.wait())
+                .await
+                .is_ok()
+            {
+                return;
+            }
+        }
+
+        if let Err(e) = self.child.kill().await {
+            warn!("failed to kill language server: {}", e);
+        }
+    }
+}
+
+async fn write_messages(mut stdin: ChildStdin, mut outgoing: UnboundedReceiver<Message>) {
+    let mut buf = BytesMut::new();
+
+    while let Some(message) = outgoing.recv().await {
+        encode(&message, &mut buf);
+
+        if let Err(e) = stdin.write_all_buf(&mut buf).await {
+            warn!("failed to write to language server: {}", e);
+            return;
+        }
+    }
+}
+
+async fn read_messages(
+    mut stdout: ChildStdout,
+    incoming: UnboundedSender<Message>,
+    messages: &Notify,
+) {
+    let mut buf = BytesMut::with_capacity(4096);
+
+    loop {
+        loop {
+            match decode(&mut buf) {
+                Ok(Some(message)) => {
+                    if incoming.send(message).is_err() {
+                        return;
+                    }
+
+                    messages.notify_one();
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("failed to read from language server: {}", e);
+                    return;
+                }
+            }
+        }
+
+        match stdout.read_buf(&mut buf).await {
+            Ok(0) => return,
+            Ok(_) => {}
+            Err(e) => {
+                warn!("failed to read from language server: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// A server started for one language and root, with the documents opened in it.
+struct Client {
+    language: String,
+    root: PathBuf,
+    server: LanguageServer,
+    /// The id of the initialize request until it is answered.
+    initialize: Option<Value>,
+    capabilities: ServerCapabilities,
+    /// Notifications held back until the server is initialized.
+    queue: Vec<Message>,
+    documents: HashMap<Url, Document>,
+    /// Requests awaiting their responses, by id.
+    pending: HashMap<i64, (Ticket, Pending)>,
+}
+
+/// A buffer opened in a server.
+struct Document {
+    buffer: Weak<RefCell<Buffer>>,
+    /// The version which the server has last been told about.
+    version: i32,
+    /// The last semantic tokens sent with a result id, which the next ones may be a delta to.
+    semantic_tokens: Option<(String, Vec<SemanticToken>)>,
+}
+
+impl Client {
+    fn start(
+        language: &str,
+        root: &Path,
+        config: &ServerConfig,
+        messages: Arc<Notify>,
+    ) -> io::Result<Self> {
+        let mut server = LanguageServer::spawn(config, root, messages)?;
+        let root_uri = Url::from_directory_path(root).ok();
+
+        #[allow(deprecated)]
+        let initialize = server.request::<Initialize>(InitializeParams {
+            process_id: Some(std::process::id()),
+            root_path: None,
+            root_uri: root_uri.clone(),
+            initialization_options: None,
+            capabilities: client_capabilities(),
+            trace: None,
+            workspace_folders: root_uri.map(|uri| {
+                vec![WorkspaceFolder {
+                    uri,
+                    name: root
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                }]
+            }),
+            client_info: Some(ClientInfo {
+                name: env!("CARGO_PKG_NAME").to_owned(),
+                version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            }),
+            locale: None,
+        });
+
+        Ok(Client {
+            language: language.to_owned(),
+            root: root.to_owned(),
+            server,
+            initialize: Some(initialize),
+            capabilities: ServerCapabilities::default(),
+            queue: Vec::new(),
+            documents: HashMap::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    fn notify<N: Notification>(&mut self, params: N::Params) {
+        let message = Message::Notification {
+            method: N::METHOD.to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        };
+
+        if self.initialize.is_some() {
+            self.queue.push(message);
+        } else {
+            self.server.send(message);
+        }
+    }
+
+    /// Handles what the server has sent, adding what is to be shown to the events. Returns
+    /// false once it has exited.
+    fn poll(&mut self, events: &mut Vec<Event>) -> bool {
+        loop {
+            match self.server.try_recv() {
+                Ok(Some(message)) => events.extend(self.handle(message)),
+                Ok(None) => return true,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    fn handle(&mut self, message: Message) -> Option<Event> {
+        match message {
+            Message::Response { id, result } if Some(&id) == self.initialize.as_ref() => {
+                self.initialize = None;
+
+                match result.map(serde_json::from_value::<InitializeResult>) {
+                    Ok(Ok(result)) => self.capabilities = result.capabilities,
+                    Ok(Err(e)) => warn!("invalid initialize result: {}", e),
+                    Err(e) => warn!("failed to initialize language server: {}", e),
+                }
+
+                self.server.notify::<Initialized>(InitializedParams {});
+
+                for message in self.queue.drain(..) {
+                    self.server.send(message);
+                }
+            }
+            Message::Response { id, result } => {
+                match id.as_i64().and_then(|id| self.pending.remove(&id)) {
+                    Some((ticket, pending)) => {
+                        // A failed request is answered as if there were nothing, so that whoever
+                        // waits for it goes on.
+                        let result = result.unwrap_or_else(|e| {
+                            warn!("{:?} request failed: {}", pending, e);
+                            Value::Null
+                        });
+
+                        if let Pending::SemanticTokens(uri) = pending {
+                            return Some(self.semantic_tokens(ticket, &uri, result));
+                        }
+
+                        return pending.event(ticket, result);
+                    }
+                    None => debug!("unexpected response {}: {:?}", id, result),
+                }
+            }
+            Message::Request { id, method, params } if method == ApplyWorkspaceEdit::METHOD => {
+                let params = serde_json::from_value::<ApplyWorkspaceEditParams>(params);
+                let response = ApplyWorkspaceEditResponse {
+                    applied: params.is_ok(),
+                    failure_reason: None,
+                    failed_change: None,
+                };
+
+                self.server.send(Message::Response {
+                    id,
+                    result: Ok(serde_json::to_value(response).unwrap()),
+                });
+
+                match params {
+                    Ok(params) => return Some(Event::ApplyEdit(params.edit)),
+                    Err(e) => warn!("invalid edit: {}", e),
+                }
+            }
+            Message::Request { id, method, .. } => {
+                let result = match method.as_str() {
+                    "client/registerCapability" | "window/workDoneProgress/create" => {
+                        Ok(Value::Null)
+                    }
+                    _ => Err(json!({
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("unsupported request {}", method),
+                    })),
+                };
+
+                self.server.send(Message::Response { id, result });
+            }
+            Message::Notification { method, params } if method == PublishDiagnostics::METHOD => {
+                match serde_json::from_value::<PublishDiagnosticsParams>(params) {
+                    // Made for text which has changed since, newer ones are on their way.
+                    Ok(params) if self.is_outdated(&params.uri, params.version) => {
+                        debug!("dropping outdated diagnostics for {}", params.uri)
+                    }
+                    Ok(params) => return Some(Event::Diagnostics(params)),
+                    Err(e) => warn!("invalid diagnostics: {}", e),
+                }
+            }
+            Message::Notification { method, params } => {
+                debug!(
+                    "{} ({}): {} {}",
+                    self.language,
+                    self.root.display(),
+                    method,
+                    params
+                )
+            }
+        }
+
+        None
+    }
+
+    /// Decodes the semantic tokens of the document, applying a delta to the ones it was made
+    /// against. Tokens which cannot be made out are taken as none.
+    fn semantic_tokens(&mut self, ticket: Ticket, uri: &Url, result: Value) -> Event {
+        let previous = self
+            .documents
+            .get_mut(uri)
+            .and_then(|document| document.semantic_tokens.take());
+        let (result_id, tokens) = match serde_json::from_value(result) {
+            Ok(Some(SemanticTokensFullDeltaResult::Tokens(tokens))) => {
+                (tokens.result_id, tokens.data)
+            }
+            Ok(Some(SemanticTokensFullDeltaResult::TokensDelta(delta))) => {
+                match previous.and_then(|(_, tokens)| apply_token_edits(tokens, delta.edits)) {
+                    Some(tokens) => (delta.result_id, tokens),
+                    None => {
+                        warn!("semantic tokens delta without the tokens it was made against");
+                        (None, Vec::new())
+                    }
+                }
+            }
+            Ok(Some(SemanticTokensFullDeltaResult::PartialTokensDelta { .. })) | Ok(None) => {
+                (None, Vec::new())
+            }
+            Err(e) => {
+                warn!("invalid semantic tokens: {}", e);
+                (None, Vec::new())
+            }
+        };
+        let tokens = match semantic_tokens_options(&self.capabilities) {
+            Some(options) => {
+                let spans = decode_tokens(&tokens, &options.legend);
+
+                if let (Some(document), Some(result_id)) = (self.documents.get_mut(uri), result_id)
+                {
+                    document.semantic_tokens = Some((result_id, tokens));
+                }
+
+                spans
+            }
+            None => Vec::new(),
+        };
+
+        Event::SemanticTokens { ticket, tokens }
+    }
+
+    fn request<R: Request>(&mut self, ticket: Ticket, pending: Pending, params: R::Params) {
+        let id = self.server.request::<R>(params);
+
+        self.pending.insert(id.as_i64().unwrap(), (ticket, pending));
+    }
+
+    /// How the server wants changes, and whether it wants open and close notifications.
+    fn sync(&self) -> (TextDocumentSyncKind, bool) {
+        match &self.capabilities.text_document_sync {
+            Some(TextDocumentSyncCapability::Kind(kind)) => (*kind, true),
+            Some(TextDocumentSyncCapability::Options(options)) => (
+                options.change.unwrap_or(TextDocumentSyncKind::NONE),
+                options.open_close.unwrap_or(false),
+            ),
+            None => (TextDocumentSyncKind::NONE, false),
+        }
+    }
+
+    /// Opens the buffer, whose edits are recorded from now on to be sent as changes.
+    fn open(&mut self, uri: Url, buffer: &Rc<RefCell<Buffer>>) {
+        let document = Document {
+            buffer: Rc::downgrade(buffer),
+            version: buffer.borrow().version(),
+            semantic_tokens: None,
+        };
+        let mut buffer = buffer.borrow_mut();
+
+        buffer.record_changes(true);
+
+        if self.sync().1 || self.initialize.is_some() {
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem {
+                    uri: uri.clone(),
+                    language_id: self.language.clone(),
+                    version: buffer.version(),
+                    text: buffer.text_full(),
+                },
+            });
+        }
+
+        self.documents.insert(uri, document);
+    }
+
+    /// Sends the edits made since the last change in one notification, or the whole text if
+    /// the server does not take incremental changes.
+    fn change(&mut self, uri: Url, buffer: &mut Buffer) {
+        let changes = buffer.take_changes();
+        let content_changes = match self.sync().0 {
+            TextDocumentSyncKind::INCREMENTAL => changes,
+            TextDocumentSyncKind::FULL => vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: buffer.text_full(),
+            }],
+            _ => Vec::new(),
+        };
+
+        if !content_changes.is_empty() {
+            self.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: buffer.version(),
+                },
+                content_changes,
+            });
+        }
+
+        if let Some(document) = self.documents.get_mut(&uri) {
+            document.version = buffer.version();
+        }
+    }
+
+    /// Whether the version is older than the one of the document the server was told about last.
+    fn is_outdated(&self, uri: &Url, version: Option<i32>) -> bool {
+        match (self.documents.get(uri), version) {
+            (Some(document), Some(version)) => version < document.version,
+            _ => false,
+        }
+    }
+
+    fn close(&mut self, uri: Url) {
+        if self.sync().1 || self.initialize.is_some() {
+            self.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+            });
+        }
+
+        if let Some(buffer) = self
+            .documents
+            .remove(&uri)
+            .and_then(|document| document.buffer.upgrade())
+        {
+            buffer.borrow_mut().record_changes(false);
+        }
+    }
+}
+
+fn client_capabilities() -> ClientCapabilities {
+    ClientCapabilities {
+        text_document: Some(TextDocumentClientCapabilities {
+            synchronization: Some(TextDocumentSyncClientCapabilities {
+                dynamic_registration: Some(false),
+                will_save: Some(false),
+                will_save_wait_until: Some(false),
+                did_save: Some(false),
+            }),
+            publish_diagnostics: Some(PublishDiagnosticsClientCapabilities {
+                version_support: Some(true),
+                ..Default::default()
+            }),
+            completion: Some(CompletionClientCapabilities {
+                completion_item: Some(CompletionItemCapability {
+                    snippet_support: Some(true),
+                    documentation_format: Some(vec![MarkupKind::PlainText, MarkupKind::Markdown]),
+                    insert_replace_support: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            hover: Some(HoverClientCapabilities {
+                dynamic_registration: Some(false),
+                content_format: Some(vec![MarkupKind::PlainText, MarkupKind::Markdown]),
+            }),
+            definition: Some(GotoCapability {
+                dynamic_registration: Some(false),
+                link_support: Some(true),
+            }),
+            references: Some(Default::default()),
+            rename: Some(Default::default()),
+            document_symbol: Some(DocumentSymbolClientCapabilities {
+                hierarchical_document_symbol_support: Some(true),
+                ..Default::default()
+            }),
+            signature_help: Some(SignatureHelpClientCapabilities {
+                signature_information: Some(SignatureInformationSettings {
+                    documentation_format: Some(vec![MarkupKind::PlainText]),
+                    parameter_information: Some(ParameterInformationSettings {
+                        label_offset_support: Some(true),
+                    }),
+                    active_parameter_support: Some(true),
+                }),
+                context_support: Some(true),
+                ..Default::default()
+            }),
+            inlay_hint: Some(InlayHintClientCapabilities::default()),
+            semantic_tokens: Some(SemanticTokensClientCapabilities {
+                dynamic_registration: Some(false),
+                requests: SemanticTokensClientCapabilitiesRequests {
+                    range: Some(false),
+                    full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+                },
+                token_types: vec![
+                    SemanticTokenType::NAMESPACE,
+                    SemanticTokenType::TYPE,
+                    SemanticTokenType::CLASS,
+                    SemanticTokenType::ENUM,
+                    SemanticTokenType::INTERFACE,
+                    SemanticTokenType::STRUCT,
+                    SemanticTokenType::TYPE_PARAMETER,
+                    SemanticTokenType::PARAMETER,
+                    SemanticTokenType::VARIABLE,
+                    SemanticTokenType::PROPERTY,
+                    SemanticTokenType::ENUM_MEMBER,
+                    SemanticTokenType::EVENT,
+                    SemanticTokenType::FUNCTION,
+                    SemanticTokenType::METHOD,
+                    SemanticTokenType::MACRO,
+                    SemanticTokenType::KEYWORD,
+                    SemanticTokenType::MODIFIER,
+                    SemanticTokenType::COMMENT,
+                    SemanticTokenType::STRING,
+                    SemanticTokenType::NUMBER,
+                    SemanticTokenType::REGEXP,
+                    SemanticTokenType::OPERATOR,
+                ],
+                token_modifiers: vec![
+                    SemanticTokenModifier::DECLARATION,
+                    SemanticTokenModifier::DEFINITION,
+                    SemanticTokenModifier::READONLY,
+                    SemanticTokenModifier::STATIC,
+                    SemanticTokenModifier::DEPRECATED,
+                    SemanticTokenModifier::ABSTRACT,
+                    SemanticTokenModifier::ASYNC,
+                    SemanticTokenModifier::MODIFICATION,
+                    SemanticTokenModifier::DOCUMENTATION,
+                    SemanticTokenModifier::DEFAULT_LIBRARY,
+                ],
+                formats: vec![TokenFormat::RELATIVE],
+                overlapping_token_support: Some(false),
+                multiline_token_support: Some(false),
+                ..Default::default()
+            }),
+            formatting: Some(Default::default()),
+            code_action: Some(CodeActionClientCapabilities {
+                code_action_literal_support: Some(CodeActionLiteralSupport {
+                    code_action_kind: CodeActionKindLiteralSupport {
+                        value_set: [
+                            CodeActionKind::QUICKFIX,
+                            CodeActionKind::REFACTOR,
+                            CodeActionKind::REFACTOR_EXTRACT,
+                            CodeActionKind::REFACTOR_INLINE,
+                            CodeActionKind::REFACTOR_REWRITE,
+                            CodeActionKind::SOURCE,
+                            CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+                        ]
+                        .iter()
+                        .map(|kind| kind.as_str().to_owned())
+                        .collect(),
+                    },
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        workspace: Some(WorkspaceClientCapabilities {
+            apply_edit: Some(true),
+            symbol: Some(Default::default()),
+            workspace_edit: Some(WorkspaceEditClientCapabilities {
+                document_changes: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// The language servers, one per language and project root, started as buffers visiting files
+/// of their language are opened.
+#[derive(Default)]
+pub struct LanguageServers {
+    clients: Vec<Client>,
+    /// Servers which could not be started or have exited, not to be started again.
+    failed: HashSet<(String, PathBuf)>,
+    next_ticket: Ticket,
+    messages: Arc<Notify>,
+}
+
+impl LanguageServers {
+    pub fn new() -> Self {
+        LanguageServers::default()
+    }
+
+    /// Notified whenever a server has sent something, for the event loop to wait on before
+    /// calling `poll`.
+    pub fn messages(&self) -> Arc<Notify> {
+        self.messages.clone()
+    }
+
+    /// Handles what the servers have sent since the last call, returning what is to be shown.
+    pub fn poll(&mut self) -> Vec<Event> {
+        let failed = &mut self.failed;
+        let mut events = Vec::new();
+
+        self.clients.retain_mut(|client| {
+            let running = client.poll(&mut events);
+
+            if !running {
+                warn!(
+                    "language server for {} in {:?} exited",
+                    client.language, client.root
+                );
+                failed.insert((client.language.clone(), client.root.clone()));
+            }
+
+            running
+        });
+
+        events
+    }
+
+    /// Tells the servers about buffers which have been opened, edited or killed since the last
+    /// call, starting servers as needed. The edits of a buffer go in one notification, so it is
+    /// called once per turn of the event loop.
+    pub fn update(
+        &mut self,
+        configs: &HashMap<String, ServerConfig>,
+        buffers: &[Rc<RefCell<Buffer>>],
+    ) {
+        let mut documents = HashMap::new();
+
+        for buffer in buffers {
+            let b = buffer.borrow();
+            let (path, language) = match (b.path(), b.language()) {
+                (Some(path), Some(language)) => (path, language.name()),
+                _ => continue,
+            };
+
+            if let (Some(config), Ok(uri)) = (configs.get(language), Url::from_file_path(path)) {
+                documents.insert(uri, (language, path.to_owned(), config, buffer));
+            }
+        }
+
+        for client in &mut self.clients {
+            let closed: Vec<Url> = client
+                .documents
+                .keys()
+                .filter(|uri| {
+                    documents
+                        .get(*uri)
+                        .is_none_or(|(language, ..)| *language != client.language)
+                })
+                .cloned()
+                .collect();
+
+            for uri in closed {
+                client.close(uri);
+            }
+        }
+
+        for (uri, (language, path, config, buffer)) in documents {
+            let open = self
+                .clients
+                .iter_mut()
+                .find(|client| client.documents.contains_key(&uri));
+
+            if let Some(client) = open {
+                // Edits made while the server is starting wait until it has said how it wants
+                // them.
+                if client.initialize.is_none()
+                    && client.documents[&uri].version != buffer.borrow().version()
+                {
+                    client.change(uri, &mut buffer.borrow_mut());
+                }
+
+                continue;
+            }
+
+            let root = project_root(&path, &config.roots);
+
+            if let Some(client) = self.client(language, &root, config) {
+                client.open(uri, buffer);
+            }
+        }
+    }
+
+    /// The client for the language and root, started if there is none yet.
+    fn client(
+        &mut self,
+        language: &str,
+        root: &Path,
+        config: &ServerConfig,
+    ) -> Option<&mut Client> {
+        let key = (language.to_owned(), root.to_owned());
+
+        if self.failed.contains(&key) {
+            return None;
+        }
+
+        let idx = match self
+            .clients
+            .iter()
+            .position(|client| client.language == key.0 && client.root == key.1)
+        {
+            Some(idx) => idx,
+            None => match Client::start(language, root, config, self.messages.clone()) {
+                Ok(client) => {
+                    self.clients.push(client);
+                    self.clients.len() - 1
+                }
+                Err(e) => {
+                    warn!("failed to start {:?} for {:?}: {}", config.command, root, e);
+                    self.failed.insert(key);
+                    return None;
+                }
+            },
+        };
+
+        Some(&mut self.clients[idx])
+    }
+
+    /// The initialized client which has the buffer open, and the buffer's uri. Edits the
+    /// server has not been told about are sent first, so that requests see the current text.
+    fn document(&mut self, buffer: &Rc<RefCell<Buffer>>) -> Option<(&mut Client, Url)> {
+        let uri = Url::from_file_path(buffer.borrow().path()?).ok()?;
+        let client = self
+            .clients
+            .iter_mut()
+            .find(|client| client.initialize.is_none() && client.documents.contains_key(&uri))?;
+
+        if client.documents[&uri].version != buffer.borrow().version() {
+            client.change(uri.clone(), &mut buffer.borrow_mut());
+        }
+
+        Some((client, uri))
+    }
+
+    fn next_ticket(&mut self) -> Ticket {
+        self.next_ticket += 1;
+        self.next_ticket
+    }
+
+    /// Sends a request about the buffer to the server which has it open, if the server
+    /// supports it. Returns the ticket of the event the response becomes.
+    fn document_request<R: Request>(
+        &mut self,
+        buffer: &Rc<RefCell<Buffer>>,
+        pending: Pending,
+        params: impl FnOnce(TextDocumentIdentifier) -> R::Params,
+    ) -> Option<Ticket> {
+        let ticket = self.next_ticket();
+        let (client, uri) = self.document(buffer)?;
+
+        if !pending.is_supported(&client.capabilities) {
+            return None;
+        }
+
+        client.request::<R>(ticket, pending, params(TextDocumentIdentifier { uri }));
+        Some(ticket)
+    }
+
+    /// Sends a request about the cursor in the buffer, like `document_request`.
+    fn position_request<R: Request>(
+        &mut self,
+        buffer: &Rc<RefCell<Buffer>>,
+        cursor: Cursor,
+        pending: Pending,
+        params: impl FnOnce(TextDocumentPositionParams) -> R::Params,
+    ) -> Option<Ticket> {
+        let position = buffer.borrow().lsp_position(cursor);
+
+        self.document_request::<R>(buffer, pending, |text_document| {
+            params(TextDocumentPositionParams {
+                text_document,
+                position,
+            })
+        })
+    }
+
+    /// Asks for completions at the cursor, typed or triggered by the character. Returns the
+    /// ticket of the `Event::Completion` to come, or `None` if no server completes the buffer.
+    pub fn completion(
+        &mut self,
+        buffer: &Rc<RefCell<Buffer>>,
+        cursor: Cursor,
+        trigger: Option<char>,
+    ) -> Option<Ticket> {
+        self.position_request::<Completion>(buffer, cursor, Pending::Completion, |position| {
+            CompletionParams {
+                text_document_position: position,
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+                context: Some(CompletionContext {
+                    trigger_kind: match trigger {
+                        Some(_) => CompletionTriggerKind::TRIGGER_CHARACTER,
+                        None => CompletionTriggerKind::INVOKED,
+                    },
+                    trigger_character: trigger.map(|c| c.to_string()),
+                }),
+            }
+        })
+    }
+
+    /// Asks for the signature of the call the cursor is in, for an `Event::SignatureHelp`. The
+    /// help shown, if any, is asked to be updated.
+    pub fn signature_help(
+        &mut self,
+        buffer: &Rc<RefCell<Buffer>>,
+        cursor: Cursor,
+        trigger: Option<char>,
+        shown: Option<SignatureHelp>,
+    ) -> Option<Ticket> {
+        self.position_request::<SignatureHelpRequest>(
+            buffer,
+            cursor,
+            Pending::SignatureHelp,
+            |position| SignatureHelpParams {
+                context: Some(SignatureHelpContext {
+                    trigger_kind: match trigger {
+                        Some(_) => SignatureHelpTriggerKind::TRIGGER_CHARACTER,
+                        None => SignatureHelpTriggerKind::CONTENT_CHANGE,
+                    },
+                    trigger_character: trigger.map(|c| c.to_string()),
+                    is_retrigger: shown.is_some(),
+                    active_signature_help: shown,
+                }),
+                text_document_position_params: position,
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            },
+        )
+    }
+
+    /// Asks for the symbols of the buffer, for an `Event::DocumentSymbols`.
+    pub fn document_symbols(&mut self, buffer: &Rc<RefCell<Buffer>>) -> Option<Ticket> {
+        self.document_request::<DocumentSymbolRequest>(
+            buffer,
+            Pending::DocumentSymbols,
+            |text_document| DocumentSymbolParams {
+                text_document,
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            },
+        )
+    }
+
+    /// Asks the server which has the buffer open for the symbols of its whole workspace which
+    /// match the query, for an `Event::WorkspaceSymbols`.
+    pub fn workspace_symbols(
+        &mut self,
+        buffer: &Rc<RefCell<Buffer>>,
+        query: &str,
+    ) -> Option<Ticket> {
+        self.document_request::<WorkspaceSymbol>(buffer, Pending::WorkspaceSymbols, |_| {
+            WorkspaceSymbolParams {
+                query: query.to_owned(),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            }
+        })
+    }
+
+    /// Asks for the inlay hints of the whole buffer, for an `Event::InlayHints`.
+    pub fn inlay_hints(&mut self, buffer: &Rc<RefCell<Buffer>>) -> Option<Ticket> {
+        let end = {
+            let buffer = buffer.borrow();
+            let last = buffer.lines_len() - 1;
+
+            buffer.lsp_position((buffer.line_len_idx(last), last))
+        };
+
+        self.document_request::<InlayHintRequest>(buffer, Pending::InlayHints, |text_document| {
+            InlayHintParams {
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                text_document,
+                range: Range::new(Default::default(), end),
+            }
+        })
+    }
+
+    /// Asks for the semantic tokens of the whole buffer, for an `Event::SemanticTokens`. They
+    /// come as a delta to the last ones if the server can send that.
+    pub fn semantic_tokens(&mut self, buffer: &Rc<RefCell<Buffer>>) -> Option<Ticket> {
+        let ticket = self.next_ticket();
+        let (client, uri) = self.document(buffer)?;
+        let pending = Pending::SemanticTokens(uri.clone());
+
+        if !pending.is_supported(&client.capabilities) {
+            return None;
+        }
+
+        let delta = matches!(
+            semantic_tokens_options(&client.capabilities).and_then(|options| options.full.as_ref()),
+            Some(SemanticTokensFullOptions::Delta { delta: Some(true) })
+        );
+        let previous = client.documents[&uri]
+            .semantic_tokens
+            .as_ref()
+            .filter(|_| delta)
+            .map(|(result_id, _)| result_id.clone());
+        let text_document = TextDocumentIdentifier { uri };
+
+        match previous {
+            Some(previous_result_id) => client.request::<SemanticTokensFullDeltaRequest>(
+                ticket,
+                pending,
+                SemanticTokensDeltaParams {
+                    work_done_progress_params: WorkDoneProgressParams::default(),
+                    partial_result_params: PartialResultParams::default(),
+                    text_document,
+                    previous_result_id,
+                },
+            ),
+            None => client.request::<SemanticTokensFullRequest>(
+                ticket,
+                pending,
+                SemanticTokensParams {
+                    work_done_progress_params: WorkDoneProgressParams::default(),
+                    partial_result_params: PartialResultParams::default(),
+                    text_document,
+                },
+            ),
+        }
+
+        Some(ticket)
+    }
+
+    /// Asks where the symbol under the cursor is defined, for an `Event::Definition`.
+    pub fn definition(&mut self, buffer: &Rc<RefCell<Buffer>>, cursor: Cursor) -> Option<Ticket> {
+        self.position_request::<GotoDefinition>(buffer, cursor, Pending::Definition, |position| {
+            GotoDefinitionParams {
+                text_document_position_params: position,
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            }
+        })
+    }
+
+    /// Asks where the symbol under the cursor is used, its declaration included, for an
+    /// `Event::References`.
+    pub fn references(&mut self, buffer: &Rc<RefCell<Buffer>>, cursor: Cursor) -> Option<Ticket> {
+        self.position_request::<References>(buffer, cursor, Pending::References, |position| {
+            ReferenceParams {
+                text_document_position: position,
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+                context: ReferenceContext {
+                    include_declaration: true,
+                },
+            }
+        })
+    }
+
+    /// Asks for what to show about the symbol under the cursor, for an `Event::Hover`.
+    pub fn hover(&mut self, buffer: &Rc<RefCell<Buffer>>, cursor: Cursor) -> Option<Ticket> {
+        self.position_request::<HoverRequest>(buffer, cursor, Pending::Hover, |position| {
+            HoverParams {
+                text_document_position_params: position,
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            }
+        })
+    }
+
+    /// Asks for the edits formatting the whole buffer, for an `Event::Formatting`.
+    pub fn formatting(&mut self, buffer: &Rc<RefCell<Buffer>>, tab_size: u32) -> Option<Ticket> {
+        self.document_request::<Formatting>(buffer, Pending::Formatting, |text_document| {
+            DocumentFormattingParams {
+                text_document,
+                options: FormattingOptions {
+                    tab_size,
+                    insert_spaces: true,
+                    ..Default::default()
+                },
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            }
+        })
+    }
+
+    /// Asks for the actions which can be taken on the range, where the diagnostics are, for an
+    /// `Event::CodeActions`.
+    pub fn code_actions(
+        &mut self,
+        buffer: &Rc<RefCell<Buffer>>,
+        range: (Cursor, Cursor),
+        diagnostics: Vec<Diagnostic>,
+    ) -> Option<Ticket> {
+        let range = Range::new(
+            buffer.borrow().lsp_position(range.0),
+            buffer.borrow().lsp_position(range.1),
+        );
+
+        self.document_request::<CodeActionRequest>(buffer, Pending::CodeActions, |text_document| {
+            CodeActionParams {
+                text_document,
+                range,
+                context: CodeActionContext {
+                    diagnostics,
+                    only: None,
+                },
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            }
+        })
+    }
+
+    /// Asks for the edits renaming the symbol under the cursor everywhere, for an
+    /// `Event::Rename`.
+    pub fn rename(
+        &mut self,
+        buffer: &Rc<RefCell<Buffer>>,
+        cursor: Cursor,
+        new_name: &str,
+    ) -> Option<Ticket> {
+        self.position_request::<Rename>(buffer, cursor, Pending::Rename, |position| RenameParams {
+            text_document_position: position,
+            new_name: new_name.to_owned(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        })
+    }
+
+    /// Runs the command on the server which has the buffer open. What it changes comes as an
+    /// `Event::ApplyEdit`.
+    pub fn execute_command(&mut self, buffer: &Rc<RefCell<Buffer>>, command: lsp_types::Command) {
+        self.document_request::<ExecuteCommand>(buffer, Pending::ExecuteCommand, |_| {
+            ExecuteCommandParams {
+                command: command.command,
+                arguments: command.arguments.unwrap_or_default(),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            }
+        });
+    }
+
+    /// The capabilities of the servers which have the buffer open.
+    fn capabilities_of(&self, buffer: &Buffer) -> impl Iterator<Item = &ServerCapabilities> {
+        let uri = buffer
+            .path()
+            .and_then(|path| Url::from_file_path(path).ok());
+
+        self.clients
+            .iter()
+            .filter(move |client| {
+                uri.as_ref()
+                    .is_some_and(|uri| client.documents.contains_key(uri))
+            })
+            .map(|client| &client.capabilities)
+    }
+
+    /// Whether typing the character in the buffer should ask for completions.
+    pub fn is_trigger_character(&self, buffer: &Buffer, c: char) -> bool {
+        self.capabilities_of(buffer)
+            .filter_map(|capabilities| capabilities.completion_provider.as_ref())
+            .flat_map(|options| options.trigger_characters.iter().flatten())
+            .any(|trigger| trigger.starts_with(c))
+    }
+
+    /// Whether typing the character in the buffer should ask for signature help.
+    pub fn is_signature_trigger(&self, buffer: &Buffer, c: char) -> bool {
+        self.capabilities_of(buffer)
+            .filter_map(|capabilities| capabilities.signature_help_provider.as_ref())
+            .flat_map(|options| {
+                options
+                    .trigger_characters
+                    .iter()
+                    .chain(options.retrigger_characters.iter())
+                    .flatten()
+            })
+            .any(|trigger| trigger.starts_with(c))
+    }
+
+    /// Whether every started server has answered the initialize request.
+    #[cfg(test)]
+    pub fn is_initialized(&self) -> bool {
+        self.clients
+            .iter()
+            .all(|client| client.initialize.is_none())
+    }
+
+    pub async fn shutdown(&mut self) {
+        for client in self.clients.drain(..) {
+            client.server.shutdown().await;
+        }
+    }
+}
+
+/// The nearest directory above the file which has one of the root files, or the file's own
+/// directory if none has.
+fn project_root(path: &Path, roots: &[String]) -> PathBuf {
+    let dir = path.parent().unwrap_or(path);
+
+    dir.ancestors()
+        .find(|dir| roots.iter().any(|root| dir.join(root).exists()))
+        .unwrap_or(dir)
+        .to_owned()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::completion::Completion as CompletionPopup;
+    use crate::syntax::Language;
+    use crate::theme::Theme;
+    use std::fs;
+    use tui::style::Modifier;
+
+    /// `examples/mock_lsp.rs`, which `cargo test` builds next to the test binary.
+    pub(crate) fn mock_server(args: &[&str]) -> ServerConfig {
+        let exe = std::env::current_exe().unwrap();
+        let dir = exe.parent().unwrap().parent().unwrap();
+
+        ServerConfig {
+            command: dir.join("examples/mock_lsp").display().to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            roots: vec!["Cargo.toml".to_owned()],
+        }
+    }
+
+    /// A project with a root file and a source file in a subdirectory.
+    pub(crate) fn project(name: &str, text: &str) -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let file = root.join("src/main.rs");
+
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(root.join("Cargo.toml"), "").unwrap();
+        fs::write(&file, text).unwrap();
+
+        (root, file)
+    }
+
+    /// The messages the mock server has logged, each with the text of the document after it.
+    fn read_log(root: &Path) -> Vec<(String, Option<String>)> {
+        fs::read_to_string(root.join("mock_lsp.log"))
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let value: Value = serde_json::from_str(line).unwrap();
+
+                (
+                    value["method"].as_str().unwrap().to_owned(),
+                    value["text"].as_str().map(|text| text.to_owned()),
+                )
+            })
+            .collect()
+    }
+
+    async fn wait_event(servers: &mut LanguageServers) -> Event {
+        for _ in 0..500 {
+            if let Some(event) = servers.poll().pop() {
+                return event;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("the mock server sent nothing");
+    }
+
+    /// Waits for the event, skipping diagnostics.
+    async fn wait_response(servers: &mut LanguageServers) -> Event {
+        loop {
+            match wait_event(servers).await {
+                Event::Diagnostics(_) => {}
+                event => return event,
+            }
+        }
+    }
+
+    async fn wait_initialized(servers: &mut LanguageServers) {
+        for _ in 0..500 {
+            servers.poll();
+
+            if servers.is_initialized() {
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("the mock server did not answer initialize");
+    }
+
+    #[tokio::test]
+    async fn sync_documents() {
+        let (root, file) = project("editor-lsp-sync", "fn main() {}");
+        let configs = HashMap::from([("rust".to_owned(), mock_server(&[]))]);
+        let mut servers = LanguageServers::new();
+        let buffer = Rc::new(RefCell::new(Buffer::open(&file).unwrap()));
+        let buffers = vec![buffer.clone()];
+
+        assert_eq!(buffer.borrow().language(), Some(Language::Rust));
+        assert_eq!(project_root(&file, &["Cargo.toml".to_owned()]), root);
+
+        servers.update(&configs, &buffers);
+        wait_initialized(&mut servers).await;
+
+        // Characters outside the BMP take two UTF-16 code units.
+        buffer.borrow_mut().break_line((11, 0));
+        buffer.borrow_mut().break_line((11, 0));
+        buffer
+            .borrow_mut()
+            .insert_str((0, 1), "    let é = \"😀\";");
+        buffer.borrow_mut().insert_char((13, 1), 'x');
+        buffer.borrow_mut().remove_char((12, 1));
+        servers.update(&configs, &buffers);
+
+        let first = buffer.borrow().text_full();
+
+        buffer.borrow_mut().break_line((4, 0));
+        buffer.borrow_mut().join_line(1);
+        buffer.borrow_mut().split_off((18, 1));
+        buffer.borrow_mut().insert_str((18, 1), "🦀é");
+        // Deletes the third line with the line break before it.
+        let ends = (
+            buffer.borrow().line_len_idx(1),
+            buffer.borrow().line_len_idx(2),
+        );
+        buffer.borrow_mut().replace((ends.0, 1), (ends.1, 2), "");
+        servers.update(&configs, &buffers);
+
+        let second = buffer.borrow().text_full();
+
+        servers.update(&configs, &buffers);
+        servers.update(&configs, &[]);
+        servers.shutdown().await;
+
+        assert_eq!(
+            read_log(&root),
+            vec![
+                ("initialize".to_owned(), None),
+                ("initialized".to_owned(), None),
+                (
+                    "textDocument/didOpen".to_owned(),
+                    Some("fn main() {}".to_owned())
+                ),
+                ("textDocument/didChange".to_owned(), Some(first)),
+                ("textDocument/didChange".to_owned(), Some(second)),
+                ("textDocument/didClose".to_owned(), None),
+                ("shutdown".to_owned(), None),
+                ("exit".to_owned(), None),
+            ]
+        );
+        assert!(buffer.borrow_mut().take_changes().is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sync_full_documents() {
+        let (root, file) = project("editor-lsp-sync-full", "fn main() {}");
+        let configs = HashMap::from([("rust".to_owned(), mock_server(&["--full"]))]);
+        let mut servers = LanguageServers::new();
+        let buffer = Rc::new(RefCell::new(Buffer::open(&file).unwrap()));
+        let buffers = vec![buffer.clone()];
+
+        servers.update(&configs, &buffers);
+
+        // Made before the server has said how it wants changes.
+        buffer.borrow_mut().insert_str((11, 0), " ");
+        servers.update(&configs, &buffers);
+        wait_initialized(&mut servers).await;
+        buffer.borrow_mut().break_line((11, 0));
+        servers.update(&configs, &buffers);
+        servers.shutdown().await;
+
+        let log = read_log(&root);
+
+        assert_eq!(log.len(), 6);
+        assert_eq!(log[3].1.as_deref(), Some("fn main() {\n }"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn publish_diagnostics() {
+        let (root, file) = project("editor-lsp-diagnostics", "let é = error;\n// warning");
+        let configs = HashMap::from([("rust".to_owned(), mock_server(&[]))]);
+        let mut servers = LanguageServers::new();
+        let buffer = Rc::new(RefCell::new(Buffer::open(&file).unwrap()));
+        let buffers = vec![buffer.clone()];
+
+        servers.update(&configs, &buffers);
+
+        let Event::Diagnostics(params) = wait_event(&mut servers).await else {
+            panic!("expected diagnostics");
+        };
+
+        assert_eq!(params.uri, Url::from_file_path(&file).unwrap());
+        assert_eq!(params.diagnostics.len(), 2);
+
+        buffer.borrow_mut().set_diagnostics(&params.diagnostics);
+        buffer.borrow_mut().insert_char((0, 0), ' ');
+
+        {
+            let buffer = buffer.borrow();
+            let error = buffer.diagnostic_at((10, 0)).unwrap();
+
+            assert_eq!((error.start.get(), error.end.get()), ((10, 0), (15, 0)));
+            assert_eq!(error.message, "found error");
+            assert!(buffer.diagnostic_at((9, 0)).is_none());
+            assert_eq!(buffer.sign(0).unwrap().symbol, 'E');
+            assert_eq!(buffer.sign(1).unwrap().symbol, 'W');
+            assert_eq!(buffer.next_diagnostic((0, 0), true), Some((10, 0)));
+            assert_eq!(buffer.next_diagnostic((10, 0), true), Some((3, 1)));
+            assert_eq!(buffer.next_diagnostic((3, 1), true), Some((10, 0)));
+            assert_eq!(buffer.next_diagnostic((10, 0), false), Some((3, 1)));
+        }
+
+        servers.update(&configs, &buffers);
+        buffer.borrow_mut().split_off((0, 0));
+        buffer.borrow_mut().split_off((0, 1));
+        servers.update(&configs, &buffers);
+
+        // The ones for the first change are dropped once the second has been sent.
+        let mut cleared = false;
+
+        for _ in 0..500 {
+            for event in servers.poll() {
+                if let Event::Diagnostics(params) = event {
+                    assert_eq!(params.version, Some(buffer.borrow().version()));
+                    cleared |= params.diagnostics.is_empty();
+                }
+            }
+
+            if cleared {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(cleared);
+
+        servers.shutdown().await;
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn complete() {
+        let (root, file) = project("editor-lsp-completion", "fn main() {\n    err\n}");
+        let configs = HashMap::from([("rust".to_owned(), mock_server(&[]))]);
+        let mut servers = LanguageServers::new();
+        let buffer = Rc::new(RefCell::new(Buffer::open(&file).unwrap()));
+        let buffers = vec![buffer.clone()];
+
+        servers.update(&configs, &buffers);
+        wait_initialized(&mut servers).await;
+
+        assert!(servers.is_trigger_character(&buffer.borrow(), '.'));
+        assert!(!servers.is_trigger_character(&buffer.borrow(), ':'));
+
+        // Not yet sent edits go before the request.
+        buffer.borrow_mut().insert_char((7, 1), 'o');
+
+        let ticket = servers.completion(&buffer, (8, 1), None).unwrap();
+        let (items, incomplete) = match wait_response(&mut servers).await {
+            Event::Completion {
+                ticket: t,
+                items,
+                incomplete,
+            } if t == ticket => (items, incomplete),
+            event => panic!("unexpected {:?}", event),
+        };
+
+        assert!(!incomplete);
+        assert_eq!(items.len(), 2);
+
+        let mut completion = CompletionPopup::new((4, 1), (8, 1), vec![], Some(ticket));
+
+        buffer.borrow_mut().insert_char((8, 1), 'r');
+        completion.set_items(items, incomplete);
+        completion.filter("errr");
+
+        assert_eq!(completion.selected().unwrap().label, "error_count");
+
+        let cursor = completion.accept(&mut buffer.borrow_mut(), (9, 1));
+
+        assert_eq!(cursor, Some((15, 1)));
+        assert_eq!(
+            buffer.borrow().text_full(),
+            "fn main() {\n    error_count\n}"
+        );
+
+        servers.shutdown().await;
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn navigate() {
+        let (root, file) = project(
+            "editor-lsp-navigate",
+            "fn count() {}\n\nfn main() {\n    count();\n    count();\n}",
+        );
+        let configs = HashMap::from([("rust".to_owned(), mock_server(&[]))]);
+        let mut servers = LanguageServers::new();
+        let buffer = Rc::new(RefCell::new(Buffer::open(&file).unwrap()));
+        let buffers = vec![buffer.clone()];
+        let uri = Url::from_file_path(&file).unwrap();
+        let range = |line, character| {
+            lsp_types::Range::new(
+                lsp_types::Position::new(line, character),
+                lsp_types::Position::new(line, character + 5),
+            )
+        };
+
+        servers.update(&configs, &buffers);
+        wait_initialized(&mut servers).await;
+
+        let ticket = servers.definition(&buffer, (6, 4)).unwrap();
+
+        match wait_response(&mut servers).await {
+            Event::Definition {
+                ticket: t,
+                locations,
+            } if t == ticket => {
+                assert_eq!(locations, vec![Location::new(uri.clone(), range(0, 3))])
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+
+        let ticket = servers.references(&buffer, (3, 0)).unwrap();
+
+        match wait_response(&mut servers).await {
+            Event::References {
+                ticket: t,
+                locations,
+            } if t == ticket => assert_eq!(
+                locations
+                    .into_iter()
+                    .map(|location| location.range)
+                    .collect::<Vec<_>>(),
+                vec![range(0, 3), range(3, 4), range(4, 4)]
+            ),
+            event => panic!("unexpected {:?}", event),
+        }
+
+        let ticket = servers.hover(&buffer, (4, 3)).unwrap();
+
+        match wait_response(&mut servers).await {
+            Event::Hover { ticket: t, text } if t == ticket => {
+                assert_eq!(markdown_lines(&text), vec!["count", "A word."])
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+
+        servers.shutdown().await;
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn edit() {
+        let (root, file) = project("editor-lsp-edit", "fn main() {  \n    let error = 1;\n}");
+        let configs = HashMap::from([("rust".to_owned(), mock_server(&[]))]);
+        let mut servers = LanguageServers::new();
+        let buffer = Rc::new(RefCell::new(Buffer::open(&file).unwrap()));
+        let buffers = vec![buffer.clone()];
+        let uri = Url::from_file_path(&file).unwrap();
+
+        servers.update(&configs, &buffers);
+        wait_initialized(&mut servers).await;
+
+        let ticket = servers.formatting(&buffer, 4).unwrap();
+
+        match wait_response(&mut servers).await {
+            Event::Formatting { ticket: t, edits } if t == ticket => {
+                buffer.borrow_mut().apply_edits(&edits)
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+
+        assert_eq!(
+            buffer.borrow().text_full(),
+            "fn main() {\n    let error = 1;\n}"
+        );
+
+        let diagnostic = Diagnostic {
+            range: lsp_types::Range::new(
+                lsp_types::Position::new(1, 8),
+                lsp_types::Position::new(1, 13),
+            ),
+            message: "found error".to_owned(),
+            ..Default::default()
+        };
+        let ticket = servers
+            .code_actions(&buffer, ((9, 1), (9, 1)), vec![diagnostic])
+            .unwrap();
+        let actions = match wait_response(&mut servers).await {
+            Event::CodeActions { ticket: t, actions } if t == ticket => actions,
+            event => panic!("unexpected {:?}", event),
+        };
+        let titles: Vec<&str> = actions
+            .iter()
+            .map(|action| match action {
+                CodeActionOrCommand::Command(command) => command.title.as_str(),
+                CodeActionOrCommand::CodeAction(action) => action.title.as_str(),
+            })
+            .collect();
+
+        assert_eq!(
+            titles,
+            vec!["Uppercase error", "Append error", "Fix found error"]
+        );
+
+        let command = match &actions[1] {
+            CodeActionOrCommand::Command(command) => command.clone(),
+            action => panic!("unexpected {:?}", action),
+        };
+
+        servers.execute_command(&buffer, command);
+
+        match wait_response(&mut servers).await {
+            Event::ApplyEdit(edit) => {
+                let edits = &edit.changes.unwrap()[&uri];
+
+                assert_eq!(edits[0].range.start, lsp_types::Position::new(2, 1));
+                assert_eq!(edits[0].new_text, "\nerror");
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+
+        servers.shutdown().await;
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rename() {
+        let (root, file) = project(
+            "editor-lsp-rename",
+            "fn main() {\n    let error = 1;\n    error + 1\n}",
+        );
+        let configs = HashMap::from([("rust".to_owned(), mock_server(&[]))]);
+        let mut servers = LanguageServers::new();
+        let buffer = Rc::new(RefCell::new(Buffer::open(&file).unwrap()));
+        let buffers = vec![buffer.clone()];
+        let uri = Url::from_file_path(&file).unwrap();
+
+        servers.update(&configs, &buffers);
+        wait_initialized(&mut servers).await;
+
+        let ticket = servers.rename(&buffer, (6, 2), "fault").unwrap();
+
+        match wait_response(&mut servers).await {
+            Event::Rename {
+                ticket: t,
+                edit: Some(edit),
+            } if t == ticket => buffer
+                .borrow_mut()
+                .apply_edits(&edit.changes.unwrap()[&uri]),
+            event => panic!("unexpected {:?}", event),
+        }
+
+        assert_eq!(
+            buffer.borrow().text_full(),
+            "fn main() {\n    let fault = 1;\n    fault + 1\n}"
+        );
+
+        let ticket = servers.rename(&buffer, (15, 1), "x").unwrap();
+
+        match wait_response(&mut servers).await {
+            Event::Rename { ticket: t, edit } if t == ticket => assert!(edit.is_none()),
+            event => panic!("unexpected {:?}", event),
+        }
+
+        servers.shutdown().await;
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn hints() {
+        let (root, file) = project(
+            "editor-lsp-hints",
+            "fn main() {\n    let count = add(1, 2);\n}",
+        );
+        let configs = HashMap::from([("rust".to_owned(), mock_server(&[]))]);
+        let mut servers = LanguageServers::new();
+        let buffer = Rc::new(RefCell::new(Buffer::open(&file).unwrap()));
+        let buffers = vec![buffer.clone()];
+
+        servers.update(&configs, &buffers);
+        wait_initialized(&mut servers).await;
+
+        assert!(servers.is_signature_trigger(&buffer.borrow(), '('));
+        assert!(servers.is_signature_trigger(&buffer.borrow(), ','));
+        assert!(!servers.is_signature_trigger(&buffer.borrow(), ')'));
+
+        let ticket = servers
+            .signature_help(&buffer, (20, 1), Some('('), None)
+            .unwrap();
+        let help = match wait_response(&mut servers).await {
+            Event::SignatureHelp { ticket: t, help } if t == ticket => help.unwrap(),
+            event => panic!("unexpected {:?}", event),
+        };
+
+        assert_eq!(
+            active_signature(&help),
+            Some(("fn add(a: usize, b: usize)", Some(7..15)))
+        );
+
+        let ticket = servers
+            .signature_help(&buffer, (23, 1), None, Some(help))
+            .unwrap();
+        let help = match wait_response(&mut servers).await {
+            Event::SignatureHelp { ticket: t, help } if t == ticket => help.unwrap(),
+            event => panic!("unexpected {:?}", event),
+        };
+
+        assert_eq!(
+            active_signature(&help),
+            Some(("fn add(a: usize, b: usize)", Some(17..25)))
+        );
+
+        let ticket = servers
+            .signature_help(&buffer, (26, 1), None, None)
+            .unwrap();
+
+        match wait_response(&mut servers).await {
+            Event::SignatureHelp { ticket: t, help } if t == ticket => assert!(help.is_none()),
+            event => panic!("unexpected {:?}", event),
+        }
+
+        let ticket = servers.inlay_hints(&buffer).unwrap();
+
+        match wait_response(&mut servers).await {
+            Event::InlayHints { ticket: t, hints } if t == ticket => {
+                let version = buffer.borrow().version();
+
+                buffer.borrow_mut().set_inlay_hints(&hints, version)
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+
+        {
+            let buffer = buffer.borrow();
+
+            assert_eq!(buffer.display_line(1), "    let count: i32 = add(1, 2);");
+            assert_eq!(buffer.display_col((13, 1)), 13);
+            assert_eq!(buffer.display_col((14, 1)), 19);
+            assert_eq!(buffer.position_of_display_col(1, 15), 13);
+            assert_eq!(buffer.position_of_display_col(1, 19), 14);
+        }
+
+        buffer.borrow_mut().insert_str((0, 1), "  ");
+
+        assert_eq!(
+            buffer.borrow().display_line(1),
+            "      let count = add(1, 2);"
+        );
+
+        servers.shutdown().await;
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn semantic_tokens() {
+        let (root, file) = project(
+            "editor-lsp-semantic-tokens",
+            "fn main() {\n    let mut total = Vec::new();\n    println!();\n}",
+        );
+        let configs = HashMap::from([("rust".to_owned(), mock_server(&[]))]);
+        let mut servers = LanguageServers::new();
+        let buffer = Rc::new(RefCell::new(Buffer::open(&file).unwrap()));
+        let buffers = vec![buffer.clone()];
+        let span = |line, start, end, token_type: &str, modifiers: &[&str]| SemanticSpan {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            token_type: token_type.to_owned(),
+            modifiers: modifiers
+                .iter()
+                .map(|modifier| modifier.to_string())
+                .collect(),
+        };
+
+        servers.update(&configs, &buffers);
+        wait_initialized(&mut servers).await;
+
+        let ticket = servers.semantic_tokens(&buffer).unwrap();
+
+        match wait_response(&mut servers).await {
+            Event::SemanticTokens { ticket: t, tokens } if t == ticket => assert_eq!(
+                tokens,
+                vec![
+                    span(1, 12, 17, "variable", &["mutable"]),
+                    span(1, 20, 23, "type", &[]),
+                    span(2, 4, 11, "macro", &[]),
+                ]
+            ),
+            event => panic!("unexpected {:?}", event),
+        }
+
+        buffer.borrow_mut().insert_str((4, 2), "Some(1);\n    ");
+
+        let ticket = servers.semantic_tokens(&buffer).unwrap();
+
+        let tokens = match wait_response(&mut servers).await {
+            Event::SemanticTokens { ticket: t, tokens } if t == ticket => tokens,
+            event => panic!("unexpected {:?}", event),
+        };
+
+        assert_eq!(
+            tokens,
+            vec![
+                span(1, 12, 17, "variable", &["mutable"]),
+                span(1, 20, 23, "type", &[]),
+                span(2, 4, 8, "type", &[]),
+                span(3, 4, 11, "macro", &[]),
+            ]
+        );
+
+        let version = buffer.borrow().version();
+        let theme = &Theme::builtin()[0];
+
+        buffer.borrow_mut().set_semantic_tokens(&tokens, version);
+        buffer.borrow_mut().insert_str((0, 0), "\n");
+
+        {
+            let buffer = buffer.borrow();
+            let lines = buffer.text_styled(theme, &[]);
+            let total = lines[2]
+                .0
+                .iter()
+                .find(|span| span.content == "total")
+                .unwrap();
+
+            assert!(total.style.add_modifier.contains(Modifier::UNDERLINED));
+        }
+
+        assert!(read_log(&root)
+            .iter()
+            .any(|(method, _)| method == "textDocument/semanticTokens/full/delta"));
+
+        servers.shutdown().await;
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn symbols() {
+        let (root, file) = project(
+            "editor-lsp-symbols",
+            "struct Point {\n    x: i32,\n}\n\nimpl Point {\n    fn new() -> Self {}\n    fn norm(&self) {}\n}\n\nfn main() {}",
+        );
+        let configs = HashMap::from([("rust".to_owned(), mock_server(&[]))]);
+        let mut servers = LanguageServers::new();
+        let buffer = Rc::new(RefCell::new(Buffer::open(&file).unwrap()));
+        let buffers = vec![buffer.clone()];
+
+        servers.update(&configs, &buffers);
+        wait_initialized(&mut servers).await;
+
+        let ticket = servers.document_symbols(&buffer).unwrap();
+        let symbols = match wait_response(&mut servers).await {
+            Event::DocumentSymbols { ticket: t, symbols } if t == ticket => symbols,
+            event => panic!("unexpected {:?}", event),
+        };
+        let symbol = |name: &str, kind, line, character, children| Symbol {
+            name: name.to_owned(),
+            kind,
+            position: Position::new(line, character),
+            children,
+        };
+
+        assert_eq!(
+            symbols,
+            vec![
+                symbol("Point", SymbolKind::STRUCT, 0, 7, vec![]),
+                symbol(
+                    "Point",
+                    SymbolKind::OBJECT,
+                    4,
+                    5,
+                    vec![
+                        symbol("new", SymbolKind::FUNCTION, 5, 7, vec![]),
+                        symbol("norm", SymbolKind::FUNCTION, 6, 7, vec![]),
+                    ]
+                ),
+                symbol("main", SymbolKind::FUNCTION, 9, 3, vec![]),
+            ]
+        );
+        assert_eq!(symbol_kind_name(SymbolKind::OBJECT), "impl");
+        assert_eq!(symbol_kind_name(SymbolKind::FUNCTION), "fn");
+
+        let ticket = servers.workspace_symbols(&buffer, "NO").unwrap();
+        let symbols = match wait_response(&mut servers).await {
+            Event::WorkspaceSymbols { ticket: t, symbols } if t == ticket => symbols,
+            event => panic!("unexpected {:?}", event),
+        };
+
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].name, "norm");
+        assert_eq!(symbols[0].location.uri, Url::from_file_path(&file).unwrap());
+        assert_eq!(symbols[0].location.range.start, Position::new(6, 7));
+
+        servers.shutdown().await;
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -2,23 +2,56 @@
 extern crate log;
 
 mod buffer;
+mod completion;
+mod config;
 mod frame;
+mod gutter;
+mod lsp;
+mod mode_line;
+mod prompt;
+mod session;
+mod syntax;
+mod theme;
 mod window;
+mod wrap;
 
-use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use buffer::StyleRange;
+use completion::{documentation, kind_name, Completion, COMPLETION_HEIGHT};
+use config::Config;
+use crossterm::event::{
+    DisableMouseCapture, EnableMouseCapture, Event, EventStream, KeyCode, KeyEvent, KeyModifiers,
+    MouseButton, MouseEvent, MouseEventKind,
+};
 use crossterm::execute;
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
-use frame::Direction;
+use frame::{Cursor, Direction, SPLIT_RATIO_STEP};
+use futures::{Stream, StreamExt};
+use gutter::gutter_text;
 use log::LevelFilter;
+use mode_line::mode_line_text;
+use prompt::{PromptKind, MINIBUFFER_HEIGHT};
+use session::Session;
 use simplelog::{Config as LogConfig, WriteLogger};
 use std::fs::File;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Instant;
 use std::{error::Error, io};
+use theme::Theme;
+use tokio::time;
 use tui::backend::{Backend, CrosstermBackend};
-use tui::widgets::{Block, BorderType, Paragraph};
+use tui::layout::Rect;
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Clear, Paragraph, Wrap};
 use tui::{Frame as TerminalFrame, Terminal};
-use window::Window;
+use window::{Side, Window};
+use wrap::{line_rows, wrap_spans, SoftWrap};
+
+const TAB_BAR_HEIGHT: u16 = 1;
+const DOCUMENTATION_WIDTH: u16 = 60;
+const DOCUMENTATION_HEIGHT: u16 = 12;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -33,17 +66,53 @@ async fn main() -> Result<(), Box<dyn Error>> {
     debug!("hello");
 
     let mut terminal = init_terminal()?;
-    let window = Window::new();
+    let mut window = Window::new();
+
+    window.set_config(Config::load_default());
+
+    if let Some(dir) = config::config_dir() {
+        window.add_themes(Theme::load_dir(&dir.join("themes")));
+    }
+
+    let restore = session_arg();
+
+    if let Some(path) = &restore {
+        match Session::load(path) {
+            Ok(session) => window.restore_session(&session),
+            Err(e) => warn!("failed to load session {:?}: {}", path, e),
+        }
+    }
 
-    if let Err(e) = run_app(&mut terminal, window).await {
+    if let Err(e) = run_app(&mut terminal, &mut window, EventStream::new()).await {
         eprintln!("{:?}", e);
     }
 
     reset_terminal()?;
+    window.shutdown_language_servers().await;
+
+    if let Some(path) = restore.or_else(session::default_path) {
+        if let Err(e) = window.session().save(&path) {
+            warn!("failed to save session {:?}: {}", path, e);
+        }
+    }
 
     Ok(())
 }
 
+/// The session to restore: the file after `--session`, or the one saved on the last exit if
+/// the flag comes alone.
+fn session_arg() -> Option<PathBuf> {
+    let mut args = std::env::args().skip(1);
+
+    match args.next().as_deref() {
+        Some("--session") => args
+            .next()
+            .map(PathBuf::from)
+            .or_else(session::default_path),
+        _ => None,
+    }
+}
+
 fn hook_panic() {
     let hook = std::panic::take_hook();
 
@@ -58,7 +127,7 @@ fn init_terminal() -> Result<Terminal<CrosstermBackend<io::Stdout>>, Box<dyn Err
 
     let mut stdout = io::stdout();
 
-    execute!(stdout, EnterAlternateScreen)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
 
     let backend = CrosstermBackend::new(stdout);
     let terminal = Terminal::new(backend)?;
@@ -69,123 +138,725 @@ fn init_terminal() -> Result<Terminal<CrosstermBackend<io::Stdout>>, Box<dyn Err
 fn reset_terminal() -> Result<(), Box<dyn Error>> {
     disable_raw_mode()?;
 
-    execute!(io::stdout(), LeaveAlternateScreen)?;
+    execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
 
     Ok(())
 }
 
-async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut window: Window) -> io::Result<()> {
+/// Draws the window and handles the events from the terminal, the language servers and the jobs
+/// until the events end or the app is quit.
+async fn run_app<B: Backend>(
+    terminal: &mut Terminal<B>,
+    window: &mut Window,
+    mut events: impl Stream<Item = io::Result<Event>> + Unpin,
+) -> io::Result<()> {
+    let messages = window.language_server_messages();
+    let mut redraw = true;
+
     loop {
-        terminal.draw(|f| ui(f, &window))?;
-
-        let frame = window.focus_frame();
-
-        if let Event::Key(key) = event::read()? {
-            if frame.borrow().is_x_mode() {
-                match key.modifiers {
-                    KeyModifiers::CONTROL => match key.code {
-                        KeyCode::Char('c') => return Ok(()),
-                        KeyCode::Char('g') => {}
-                        _ => {}
-                    },
-                    KeyModifiers::SHIFT => match key.code {
-                        _ => {}
-                    },
-                    KeyModifiers::ALT => match key.code {
-                        _ => {}
-                    },
-                    KeyModifiers::NONE => match key.code {
-                        KeyCode::Char('[') => frame.borrow_mut().move_top(),
-                        KeyCode::Char(']') => frame.borrow_mut().move_bottom(),
-                        KeyCode::Char('2') => frame.borrow_mut().split(Direction::Vertical),
-                        KeyCode::Char('3') => frame.borrow_mut().split(Direction::Horizontal),
-                        KeyCode::Char('o') => window.move_focus(),
-                        KeyCode::Char('b') => {
-                            // TODO
-                            use buffer::Buffer;
-                            use std::cell::RefCell;
-                            use std::rc::Rc;
-                            frame
-                                .borrow_mut()
-                                .replace_buffer(Rc::new(RefCell::new(Buffer::new())));
-                        }
-                        KeyCode::Char('0') => window.remove_focus_frame(),
-                        _ => {}
-                    },
-                    _ => {}
+        redraw |= window.update_language_servers();
+        redraw |= window.run_finished_jobs();
+        window.update_completion();
+        window.update_signature_help();
+        window.update_outline();
+        window.update_symbol_search();
+        window.seal_undo();
+
+        if redraw {
+            terminal.draw(|f| ui(f, window))?;
+            redraw = false;
+        }
+
+        let deadline = window.deadline();
+
+        tokio::select! {
+            event = events.next() => match event {
+                Some(event) => match handle_event(terminal, window, event?)? {
+                    Handled::Redraw => redraw = true,
+                    Handled::Nothing => {}
+                    Handled::Quit => return Ok(()),
+                },
+                None => return Ok(()),
+            },
+            _ = messages.notified() => {}
+            // Wakes up for the requests of a buffer which has been left alone.
+            _ = time::sleep_until(deadline.unwrap_or_else(Instant::now).into()),
+                if deadline.is_some() => {}
+            job = window.next_job() => {
+                job(window);
+                redraw = true;
+            }
+        }
+    }
+}
+
+/// What handling an event from the terminal has come to.
+enum Handled {
+    Redraw,
+    Nothing,
+    Quit,
+}
+
+fn handle_event<B: Backend>(
+    terminal: &mut Terminal<B>,
+    window: &mut Window,
+    event: Event,
+) -> io::Result<Handled> {
+    match event {
+        Event::Resize(width, height) => terminal.resize(Rect::new(0, 0, width, height))?,
+        Event::Mouse(mouse) if mouse.kind == MouseEventKind::Moved => return Ok(Handled::Nothing),
+        Event::Mouse(mouse) => {
+            window.clear_message();
+            window.cancel_code_actions();
+
+            if window.prompt().is_none() && !window.is_selecting_frame() {
+                handle_mouse(window, mouse);
+            }
+        }
+        Event::Key(key) => {
+            window.clear_message();
+
+            if !handle_key(window, key) {
+                return Ok(Handled::Quit);
+            }
+        }
+    }
+
+    Ok(Handled::Redraw)
+}
+
+/// Returns false if the key closes the app.
+fn handle_key(window: &mut Window, key: KeyEvent) -> bool {
+    let frame = window.focus_frame();
+
+    if window.is_selecting_frame() {
+        match key.code {
+            KeyCode::Char(c) if c.is_ascii_digit() && c != '0' => {
+                window.focus_number(c as usize - '0' as usize)
+            }
+            _ => window.stop_selecting_frame(),
+        }
+    } else if window.prompt().is_some() {
+        handle_prompt_key(window, key);
+    } else if window.code_actions().is_some() {
+        handle_code_action_key(window, key);
+    } else if window
+        .completion()
+        .is_some_and(|completion| completion.has_matches())
+        && handle_completion_key(window, key)
+    {
+    } else if frame.borrow().is_x_mode() {
+        match key.modifiers {
+            KeyModifiers::CONTROL => match key.code {
+                KeyCode::Char('c') => return false,
+                KeyCode::Char('g') => {}
+                KeyCode::Char('b') => window.list_buffers(),
+                KeyCode::Char('f') => window.start_prompt(PromptKind::FindFile),
+                KeyCode::Char('t') => window.new_workspace(),
+                KeyCode::Char('w') => window.close_workspace(),
+                KeyCode::Char('r') => window.start_prompt(PromptKind::RenameWorkspace),
+                KeyCode::Char('n') => window.next_workspace(true),
+                KeyCode::Char('p') => window.next_workspace(false),
+                KeyCode::Char('s') => window.save_buffer(),
+                _ => {}
+            },
+            KeyModifiers::NONE => match key.code {
+                KeyCode::Char('[') => frame.borrow_mut().move_top(),
+                KeyCode::Char(']') => frame.borrow_mut().move_bottom(),
+                KeyCode::Char('2') => window.split(Direction::Vertical),
+                KeyCode::Char('3') => window.split(Direction::Horizontal),
+                KeyCode::Char('z') => window.toggle_zoom(),
+                KeyCode::Char('o') => window.move_focus(),
+                KeyCode::Char('b') => window.start_prompt(PromptKind::SwitchBuffer),
+                KeyCode::Char('k') => window.start_prompt(PromptKind::KillBuffer),
+                KeyCode::Char('0') => window.remove_focus_frame(),
+                KeyCode::Char('e') => frame.borrow_mut().expand_region(),
+                KeyCode::Char('f') => window.toggle_fold(),
+                KeyCode::Char('l') => frame.borrow_mut().toggle_language(),
+                KeyCode::Char('t') => window.next_theme(),
+                KeyCode::Char('n') => frame.borrow_mut().toggle_line_numbers(),
+                KeyCode::Char('w') => frame.borrow_mut().toggle_soft_wrap(),
+                KeyCode::Char('}') => {
+                    window.resize_focus_frame(Direction::Horizontal, SPLIT_RATIO_STEP)
+                }
+                KeyCode::Char('{') => {
+                    window.resize_focus_frame(Direction::Horizontal, -SPLIT_RATIO_STEP)
+                }
+                KeyCode::Char('^') => {
+                    window.resize_focus_frame(Direction::Vertical, SPLIT_RATIO_STEP)
+                }
+                KeyCode::Char('-') => {
+                    window.resize_focus_frame(Direction::Vertical, -SPLIT_RATIO_STEP)
                 }
+                KeyCode::Char('+') => window.balance(),
+                KeyCode::Left => window.move_focus_to(Side::Left),
+                KeyCode::Right => window.move_focus_to(Side::Right),
+                KeyCode::Up => window.move_focus_to(Side::Up),
+                KeyCode::Down => window.move_focus_to(Side::Down),
+                KeyCode::Char('#') => window.start_selecting_frame(),
+                KeyCode::Char('r') => window.rotate_frames(),
+                KeyCode::Char('|') => window.flip_split(),
+                KeyCode::Char('d') => window.list_diagnostics(),
+                KeyCode::Char('u') => window.undo(),
+                _ => {}
+            },
+            KeyModifiers::SHIFT => match key.code {
+                KeyCode::Left => window.swap_frame(Side::Left),
+                KeyCode::Right => window.swap_frame(Side::Right),
+                KeyCode::Up => window.swap_frame(Side::Up),
+                KeyCode::Down => window.swap_frame(Side::Down),
+                _ => {}
+            },
+            _ => {}
+        }
 
-                frame.borrow_mut().toggle_x_mode(false);
-            } else {
-                match key.modifiers {
-                    KeyModifiers::CONTROL => match key.code {
-                        KeyCode::Char('b') => frame.borrow_mut().move_left(),
-                        KeyCode::Char('f') => frame.borrow_mut().move_right(),
-                        KeyCode::Char('p') => frame.borrow_mut().move_up(),
-                        KeyCode::Char('n') => frame.borrow_mut().move_down(),
-                        KeyCode::Char('a') => frame.borrow_mut().move_front(),
-                        KeyCode::Char('e') => frame.borrow_mut().move_end(),
-                        KeyCode::Char('j') => frame.borrow_mut().new_line(),
-                        KeyCode::Char('x') => frame.borrow_mut().toggle_x_mode(true),
-                        KeyCode::Char('h') => frame.borrow_mut().backspace(),
-                        KeyCode::Char('d') => frame.borrow_mut().delete(),
-                        KeyCode::Char('k') => window.kill(),
-                        KeyCode::Char('y') => window.paste(),
-                        _ => {}
-                    },
-                    KeyModifiers::ALT => match key.code {
-                        _ => {}
-                    },
-                    KeyModifiers::SHIFT => match key.code {
-                        KeyCode::Char(c) => frame.borrow_mut().new_char(c),
-                        _ => {}
-                    },
-                    KeyModifiers::NONE => match key.code {
-                        KeyCode::Char(c) => frame.borrow_mut().new_char(c),
-                        KeyCode::Left => frame.borrow_mut().move_left(),
-                        KeyCode::Right => frame.borrow_mut().move_right(),
-                        KeyCode::Up => frame.borrow_mut().move_up(),
-                        KeyCode::Down => frame.borrow_mut().move_down(),
-                        KeyCode::Home => frame.borrow_mut().move_front(),
-                        KeyCode::End => frame.borrow_mut().move_end(),
-                        KeyCode::Enter => frame.borrow_mut().new_line(),
-                        KeyCode::Tab => frame.borrow_mut().tab(),
-                        KeyCode::Backspace => frame.borrow_mut().backspace(),
-                        KeyCode::Delete => frame.borrow_mut().delete(),
-                        _ => {}
-                    },
-                    _ => {}
+        frame.borrow_mut().toggle_x_mode(false);
+    } else {
+        match key.modifiers {
+            KeyModifiers::CONTROL => match key.code {
+                KeyCode::Char('b') => frame.borrow_mut().move_left(),
+                KeyCode::Char('f') => frame.borrow_mut().move_right(),
+                KeyCode::Char('p') => frame.borrow_mut().move_up(),
+                KeyCode::Char('n') => frame.borrow_mut().move_down(),
+                KeyCode::Char('a') => frame.borrow_mut().move_front(),
+                KeyCode::Char('e') => frame.borrow_mut().move_end(),
+                KeyCode::Char('j') => window.new_line(),
+                KeyCode::Char('x') => frame.borrow_mut().toggle_x_mode(true),
+                KeyCode::Char('h') => frame.borrow_mut().backspace(),
+                KeyCode::Char('d') => frame.borrow_mut().delete(),
+                KeyCode::Char('k') => window.kill(),
+                KeyCode::Char('y') => window.paste(),
+                KeyCode::Char('g') => {
+                    frame.borrow_mut().clear_region();
+                    window.cancel_signature_help();
+                }
+                // Terminals send Ctrl-/ and Ctrl-_ as Ctrl-7.
+                KeyCode::Char('/' | '_' | '7') => window.undo(),
+                _ => {}
+            },
+            KeyModifiers::ALT => match key.code {
+                KeyCode::Char('n') => frame.borrow_mut().move_to_diagnostic(true),
+                KeyCode::Char('p') => frame.borrow_mut().move_to_diagnostic(false),
+                KeyCode::Char('/') => window.start_completion(None),
+                KeyCode::Char('.') => window.goto_definition(),
+                KeyCode::Char(',') => window.jump_back(),
+                KeyCode::Char('r') => window.find_references(),
+                KeyCode::Char('h') => window.show_hover(),
+                KeyCode::Char('q') => window.format_buffer(),
+                KeyCode::Char('a') => window.request_code_actions(),
+                KeyCode::Char('m') => window.start_prompt(PromptKind::RenameSymbol),
+                KeyCode::Char('o') => window.show_outline(),
+                KeyCode::Char('s') => window.start_prompt(PromptKind::WorkspaceSymbol),
+                _ => {}
+            },
+            KeyModifiers::SHIFT => {
+                if let KeyCode::Char(c) = key.code {
+                    window.new_char(c);
                 }
             }
+            KeyModifiers::NONE => match key.code {
+                KeyCode::Char(c) => window.new_char(c),
+                KeyCode::Left => frame.borrow_mut().move_left(),
+                KeyCode::Right => frame.borrow_mut().move_right(),
+                KeyCode::Up => frame.borrow_mut().move_up(),
+                KeyCode::Down => frame.borrow_mut().move_down(),
+                KeyCode::Home => frame.borrow_mut().move_front(),
+                KeyCode::End => frame.borrow_mut().move_end(),
+                KeyCode::Enter => window.new_line(),
+                KeyCode::Tab => frame.borrow_mut().tab(),
+                KeyCode::Backspace => frame.borrow_mut().backspace(),
+                KeyCode::Delete => frame.borrow_mut().delete(),
+                _ => {}
+            },
+            _ => {}
         }
     }
+
+    true
+}
+
+/// Handles the keys of the code action menu. Any other key closes it.
+fn handle_code_action_key(window: &mut Window, key: KeyEvent) {
+    match (key.modifiers, key.code) {
+        (KeyModifiers::CONTROL, KeyCode::Char('n')) | (KeyModifiers::NONE, KeyCode::Down) => {
+            window.select_code_action(true)
+        }
+        (KeyModifiers::CONTROL, KeyCode::Char('p')) | (KeyModifiers::NONE, KeyCode::Up) => {
+            window.select_code_action(false)
+        }
+        (KeyModifiers::CONTROL, KeyCode::Char('j')) | (KeyModifiers::NONE, KeyCode::Enter) => {
+            window.accept_code_action()
+        }
+        _ => window.cancel_code_actions(),
+    }
+}
+
+/// Handles the keys of the completion popup. Returns false for the ones it leaves to the frame.
+fn handle_completion_key(window: &mut Window, key: KeyEvent) -> bool {
+    match (key.modifiers, key.code) {
+        (KeyModifiers::CONTROL, KeyCode::Char('n')) | (KeyModifiers::NONE, KeyCode::Down) => {
+            window.select_completion(true)
+        }
+        (KeyModifiers::CONTROL, KeyCode::Char('p')) | (KeyModifiers::NONE, KeyCode::Up) => {
+            window.select_completion(false)
+        }
+        (KeyModifiers::CONTROL, KeyCode::Char('j'))
+        | (KeyModifiers::NONE, KeyCode::Enter | KeyCode::Tab) => window.accept_completion(),
+        (KeyModifiers::CONTROL, KeyCode::Char('g')) | (KeyModifiers::NONE, KeyCode::Esc) => {
+            window.cancel_completion()
+        }
+        _ => return false,
+    }
+
+    true
+}
+
+fn handle_mouse(window: &mut Window, mouse: MouseEvent) {
+    match mouse.kind {
+        MouseEventKind::Down(MouseButton::Left) => window.mouse_down(mouse.column, mouse.row),
+        MouseEventKind::Drag(MouseButton::Left) => window.mouse_drag(mouse.column, mouse.row),
+        MouseEventKind::Up(MouseButton::Left) => window.mouse_up(),
+        MouseEventKind::ScrollDown => window.scroll(mouse.column, mouse.row, true),
+        MouseEventKind::ScrollUp => window.scroll(mouse.column, mouse.row, false),
+        _ => {}
+    }
+}
+
+fn handle_prompt_key(window: &mut Window, key: KeyEvent) {
+    match key.modifiers {
+        KeyModifiers::CONTROL => match key.code {
+            KeyCode::Char('g') => window.cancel_prompt(),
+            KeyCode::Char('j') => window.finish_prompt(),
+            KeyCode::Char('h') => {
+                if let Some(prompt) = window.prompt_mut() {
+                    prompt.backspace();
+                }
+            }
+            _ => {}
+        },
+        KeyModifiers::NONE | KeyModifiers::SHIFT => match key.code {
+            KeyCode::Enter => window.finish_prompt(),
+            KeyCode::Tab => {
+                if let Some(prompt) = window.prompt_mut() {
+                    prompt.complete();
+                }
+            }
+            KeyCode::Backspace => {
+                if let Some(prompt) = window.prompt_mut() {
+                    prompt.backspace();
+                }
+            }
+            KeyCode::Char(c) => {
+                if let Some(prompt) = window.prompt_mut() {
+                    prompt.insert(c);
+                }
+            }
+            _ => {}
+        },
+        _ => {}
+    }
 }
 
 fn ui<B: Backend>(f: &mut TerminalFrame<B>, window: &Window) {
-    let main_frame = window.main_frame();
-    let rendered = main_frame.borrow_mut().render(f.size());
+    let size = f.size();
+    let minibuffer = Rect {
+        y: size.bottom().saturating_sub(MINIBUFFER_HEIGHT),
+        height: size.height.min(MINIBUFFER_HEIGHT),
+        ..size
+    };
+    let (workspaces, current) = window.workspaces();
+    let tab_bar = Rect {
+        height: if workspaces.len() > 1 {
+            TAB_BAR_HEIGHT.min(size.height - minibuffer.height)
+        } else {
+            0
+        },
+        ..size
+    };
+    let shown_frame = window.shown_frame();
+    let rendered = shown_frame.borrow_mut().render(Rect {
+        y: size.y + tab_bar.height,
+        height: size.height - minibuffer.height - tab_bar.height,
+        ..size
+    });
+    let theme = window.theme();
+
+    let mut focus_cursor = None;
+
+    for (idx, rendered_frame) in rendered.into_iter().enumerate() {
+        let (offset_x, offset_y) = rendered_frame.offset;
+        let text_area = rendered_frame.text_area;
+        let buffer = rendered_frame.buffer.borrow();
 
-    for (rect, buffer, borders, (offset_x, offset_y), cursor) in rendered {
         f.render_widget(
-            Paragraph::new(buffer.borrow().text_styled())
-                .block(
-                    Block::default()
-                        .borders(borders)
-                        .border_type(BorderType::Thick),
-                )
-                .scroll((offset_y as u16, offset_x as u16)),
-            rect,
+            Block::default()
+                .style(theme.ui("text"))
+                .borders(rendered_frame.borders)
+                .border_type(theme.border_type())
+                .border_style(theme.ui("border")),
+            rendered_frame.rect,
         );
 
-        if let Some(cursor) = cursor {
+        if let Some(cursor) = rendered_frame.cursor {
+            if (text_area.y..text_area.bottom()).contains(&(cursor.1 as u16)) {
+                f.render_widget(
+                    Block::default().style(theme.ui("cursor_line")),
+                    Rect::new(text_area.x, cursor.1 as u16, text_area.width, 1),
+                );
+            }
+        }
+
+        let region = rendered_frame
+            .region
+            .map(|(start, end)| StyleRange::between(start, end, theme.ui("selection")))
+            .unwrap_or_default();
+
+        let text = buffer.text_styled(theme, &region);
+        let text = match rendered_frame.soft_wrap {
+            SoftWrap::Off => text,
+            soft_wrap => buffer
+                .shown_lines()
+                .zip(text)
+                .flat_map(|(line, spans)| {
+                    let points = line_rows(&buffer, line, soft_wrap, text_area.width as usize);
+                    wrap_spans(spans, &points)
+                })
+                .collect(),
+        };
+
+        f.render_widget(
+            Paragraph::new(text).scroll((offset_y as u16, offset_x as u16)),
+            text_area,
+        );
+
+        f.render_widget(
+            Paragraph::new(gutter_text(&rendered_frame, theme)),
+            rendered_frame.gutter,
+        );
+
+        let mode_line_style = if rendered_frame.focus {
+            theme.ui("status_line")
+        } else {
+            theme.ui("status_line_inactive")
+        };
+
+        f.render_widget(
+            Paragraph::new(mode_line_text(&window.config().mode_line, &rendered_frame))
+                .style(mode_line_style),
+            rendered_frame.mode_line,
+        );
+
+        if window.is_selecting_frame() {
+            let label = format!(" {} ", idx + 1);
+            let label_area = Rect {
+                width: (label.len() as u16).min(text_area.width),
+                height: text_area.height.min(1),
+                ..text_area
+            };
+
+            f.render_widget(
+                Paragraph::new(label).style(theme.ui("frame_label")),
+                label_area,
+            );
+        }
+
+        if let Some(cursor) = rendered_frame.cursor {
             f.set_cursor(cursor.0 as u16, cursor.1 as u16);
+
+            if rendered_frame.focus {
+                focus_cursor = Some(cursor);
+            }
         }
     }
+
+    let tabs: Vec<Span> = workspaces
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            let style = if idx == current {
+                theme.ui("tab_active")
+            } else {
+                theme.ui("tab_bar")
+            };
+
+            Span::styled(format!(" {} ", name), style)
+        })
+        .collect();
+
+    f.render_widget(
+        Paragraph::new(Spans::from(tabs)).style(theme.ui("tab_bar")),
+        tab_bar,
+    );
+
+    f.render_widget(Block::default().style(theme.ui("text")), minibuffer);
+
+    if let Some(prompt) = window.prompt() {
+        f.render_widget(Paragraph::new(prompt.text()), minibuffer);
+        f.set_cursor(
+            minibuffer.x + (prompt.cursor() as u16).min(minibuffer.width.saturating_sub(1)),
+            minibuffer.y,
+        );
+    } else if let Some(message) = window.message() {
+        f.render_widget(Paragraph::new(message), minibuffer);
+    } else if let Some(message) = window.diagnostic_message() {
+        f.render_widget(Paragraph::new(message), minibuffer);
+    }
+
+    if let (Some((label, active)), Some(cursor)) = (window.signature_help(), focus_cursor) {
+        let screen = Rect {
+            height: size.height - minibuffer.height,
+            ..size
+        };
+
+        render_signature(f, label, active, cursor, screen, theme);
+    }
+
+    if let (Some(completion), Some(cursor)) = (window.completion(), focus_cursor) {
+        let screen = Rect {
+            height: size.height - minibuffer.height,
+            ..size
+        };
+
+        render_completion(f, completion, cursor, screen, theme);
+    }
+
+    if let (Some((titles, selected)), Some(cursor)) = (window.code_actions(), focus_cursor) {
+        let screen = Rect {
+            height: size.height - minibuffer.height,
+            ..size
+        };
+
+        render_menu(f, &titles, selected, cursor, screen, theme);
+    }
+
+    if let (Some(lines), Some(cursor)) = (window.hover(), focus_cursor) {
+        let screen = Rect {
+            height: size.height - minibuffer.height,
+            ..size
+        };
+        let (width, height) = text_size(lines);
+
+        render_text(
+            f,
+            lines,
+            popup_area((cursor.0 as u16, cursor.1 as u16), width, height, screen),
+            theme,
+        );
+    }
+}
+
+/// Draws the completion popup under the word before the cursor, with the documentation of the
+/// selected item beside it.
+fn render_completion<B: Backend>(
+    f: &mut TerminalFrame<B>,
+    completion: &Completion,
+    cursor: Cursor,
+    screen: Rect,
+    theme: &Theme,
+) {
+    let matches: Vec<_> = completion.matches().collect();
+
+    if matches.is_empty() {
+        return;
+    }
+
+    let height = matches.len().min(COMPLETION_HEIGHT);
+    let first = completion.selected_idx().saturating_sub(height - 1);
+    let shown = &matches[first..first + height];
+    let label_width = shown
+        .iter()
+        .map(|item| item.label.chars().count())
+        .max()
+        .unwrap_or_default();
+    let kind_width = shown
+        .iter()
+        .map(|item| kind_name(item).len())
+        .max()
+        .unwrap_or_default();
+    let anchor = (
+        cursor
+            .0
+            .saturating_sub(completion.pattern().chars().count()) as u16,
+        cursor.1 as u16,
+    );
+    let area = popup_area(
+        anchor,
+        (label_width + kind_width + 3) as u16,
+        height as u16,
+        screen,
+    );
+    let text: Vec<Spans> = shown
+        .iter()
+        .enumerate()
+        .map(|(idx, item)| {
+            let style = if first + idx == completion.selected_idx() {
+                theme.ui("popup_selected")
+            } else {
+                theme.ui("popup")
+            };
+
+            Spans::from(Span::styled(
+                format!(
+                    " {:label_width$} {:>kind_width$} ",
+                    item.label,
+                    kind_name(item)
+                ),
+                style,
+            ))
+        })
+        .collect();
+
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(text).style(theme.ui("popup")), area);
+
+    let lines = completion.selected().map(documentation).unwrap_or_default();
+
+    if lines.is_empty() {
+        return;
+    }
+
+    let (width, height) = text_size(&lines);
+    let x = if area.right() + width <= screen.right() {
+        area.right()
+    } else if area.x >= screen.x + width {
+        area.x - width
+    } else {
+        return;
+    };
+    let documentation_area = Rect {
+        x,
+        y: area.y.min(screen.bottom().saturating_sub(height)),
+        width,
+        height: height.min(screen.height),
+    };
+
+    render_text(f, &lines, documentation_area, theme);
+}
+
+/// Draws the signature on the row above the cursor, or below it on the top row, with the active
+/// parameter highlighted.
+fn render_signature<B: Backend>(
+    f: &mut TerminalFrame<B>,
+    label: &str,
+    active: Option<Range<usize>>,
+    cursor: Cursor,
+    screen: Rect,
+    theme: &Theme,
+) {
+    let width = (label.chars().count() as u16 + 2).min(screen.width);
+    let x = (cursor.0 as u16).min(screen.right() - width);
+    let y = if cursor.1 as u16 > screen.y {
+        cursor.1 as u16 - 1
+    } else if (cursor.1 as u16) + 1 < screen.bottom() {
+        cursor.1 as u16 + 1
+    } else {
+        return;
+    };
+    let spans = match active {
+        Some(active) => vec![
+            Span::raw(format!(" {}", &label[..active.start])),
+            Span::styled(&label[active.clone()], theme.ui("active_parameter")),
+            Span::raw(format!("{} ", &label[active.end..])),
+        ],
+        None => vec![Span::raw(format!(" {} ", label))],
+    };
+    let area = Rect::new(x, y, width, 1);
+
+    f.render_widget(Clear, area);
+    f.render_widget(
+        Paragraph::new(Spans::from(spans)).style(theme.ui("popup")),
+        area,
+    );
+}
+
+/// Draws a menu of the items at the cursor, scrolled to show the selected one.
+fn render_menu<B: Backend>(
+    f: &mut TerminalFrame<B>,
+    items: &[&str],
+    selected: usize,
+    cursor: Cursor,
+    screen: Rect,
+    theme: &Theme,
+) {
+    let height = items.len().min(COMPLETION_HEIGHT);
+    let first = selected.saturating_sub(height - 1);
+    let width = items
+        .iter()
+        .map(|item| item.chars().count())
+        .max()
+        .unwrap_or_default();
+    let area = popup_area(
+        (cursor.0 as u16, cursor.1 as u16),
+        (width + 2) as u16,
+        height as u16,
+        screen,
+    );
+    let text: Vec<Spans> = items[first..first + height]
+        .iter()
+        .enumerate()
+        .map(|(idx, item)| {
+            let style = if first + idx == selected {
+                theme.ui("popup_selected")
+            } else {
+                theme.ui("popup")
+            };
+
+            Spans::from(Span::styled(format!(" {:width$} ", item), style))
+        })
+        .collect();
+
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(text).style(theme.ui("popup")), area);
+}
+
+/// The size of a popup showing the lines, wrapped if they are too long.
+fn text_size(lines: &[String]) -> (u16, u16) {
+    let width = lines
+        .iter()
+        .map(|line| line.chars().count() as u16 + 2)
+        .max()
+        .unwrap_or_default()
+        .min(DOCUMENTATION_WIDTH);
+
+    (width, (lines.len() as u16).min(DOCUMENTATION_HEIGHT))
+}
+
+fn render_text<B: Backend>(f: &mut TerminalFrame<B>, lines: &[String], area: Rect, theme: &Theme) {
+    let text: Vec<Spans> = lines
+        .iter()
+        .map(|line| Spans::from(format!(" {}", line)))
+        .collect();
+
+    f.render_widget(Clear, area);
+    f.render_widget(
+        Paragraph::new(text)
+            .style(theme.ui("popup"))
+            .wrap(Wrap { trim: false }),
+        area,
+    );
+}
+
+/// The area of a popup of the given size next to the anchor: below it, or above it if there is
+/// more room there, moved left to fit on the screen.
+fn popup_area(anchor: (u16, u16), width: u16, height: u16, screen: Rect) -> Rect {
+    let width = width.min(screen.width);
+    let x = anchor.0.min(screen.right() - width);
+    let below = screen.bottom().saturating_sub(anchor.1 + 1);
+    let above = anchor.1.saturating_sub(screen.y);
+
+    if height <= below || below >= above {
+        Rect::new(x, anchor.1 + 1, width, height.min(below))
+    } else {
+        let height = height.min(above);
+
+        Rect::new(x, anchor.1 - height, width, height)
+    }
 }
 
 #[cfg(test)]
 mod monkey_test {
     use super::*;
+    use crossterm::event::Event as TermEvent;
     use rand::distributions::WeightedIndex;
     use rand::prelude::*;
 
@@ -209,9 +880,64 @@ mod monkey_test {
         Paste,
         SplitHorizontal,
         SplitVertical,
+        ToggleZoom,
         MoveFocus,
-        NewBuffer,
+        SwitchBuffer,
+        KillBuffer,
+        ListBuffers,
+        OpenEntry,
+        FindFile,
+        RestoreSession,
+        NewTab,
+        CloseTab,
+        NextTab,
+        PreviousTab,
+        RenameTab,
         RemoveFrame,
+        EnlargeHorizontally,
+        ShrinkHorizontally,
+        EnlargeVertically,
+        ShrinkVertically,
+        Balance,
+        FocusLeft,
+        FocusRight,
+        FocusUp,
+        FocusDown,
+        FocusNumber,
+        SwapFrame,
+        RotateFrames,
+        FlipSplit,
+        NextDiagnostic,
+        PreviousDiagnostic,
+        ListDiagnostics,
+        StartCompletion,
+        SelectCompletion,
+        AcceptCompletion,
+        CancelCompletion,
+        GotoDefinition,
+        JumpBack,
+        FindReferences,
+        Hover,
+        Undo,
+        Format,
+        CodeActions,
+        SelectCodeAction,
+        AcceptCodeAction,
+        CancelCodeAction,
+        RenameSymbol,
+        CancelSignatureHelp,
+        ShowOutline,
+        WorkspaceSymbol,
+        NextTheme,
+        ToggleLineNumbers,
+        ToggleSoftWrap,
+        ExpandRegion,
+        ClearRegion,
+        ToggleFold,
+        ToggleLanguage,
+        MouseClick,
+        MouseDrag,
+        MouseScroll,
     }
 
     fn create_events() -> (Vec<(Event, u32, u32)>, WeightedIndex<u32>) {
@@ -232,9 +958,64 @@ mod monkey_test {
             (Event::Paste, 5, 0),
             (Event::SplitHorizontal, 5, 0),
             (Event::SplitVertical, 5, 0),
+            (Event::ToggleZoom, 3, 0),
             (Event::MoveFocus, 8, 0),
-            (Event::NewBuffer, 2, 0),
+            (Event::SwitchBuffer, 3, 0),
+            (Event::KillBuffer, 1, 0),
+            (Event::ListBuffers, 2, 0),
+            (Event::OpenEntry, 3, 0),
+            (Event::FindFile, 1, 0),
+            (Event::RestoreSession, 2, 0),
+            (Event::NewTab, 2, 0),
+            (Event::CloseTab, 2, 0),
+            (Event::NextTab, 3, 0),
+            (Event::PreviousTab, 3, 0),
+            (Event::RenameTab, 1, 0),
+            (Event::MouseClick, 5, 0),
+            (Event::MouseDrag, 5, 0),
+            (Event::MouseScroll, 5, 0),
             (Event::RemoveFrame, 15, 0),
+            (Event::EnlargeHorizontally, 3, 0),
+            (Event::ShrinkHorizontally, 3, 0),
+            (Event::EnlargeVertically, 3, 0),
+            (Event::ShrinkVertically, 3, 0),
+            (Event::Balance, 2, 0),
+            (Event::FocusLeft, 3, 0),
+            (Event::FocusRight, 3, 0),
+            (Event::FocusUp, 3, 0),
+            (Event::FocusDown, 3, 0),
+            (Event::FocusNumber, 3, 0),
+            (Event::SwapFrame, 3, 0),
+            (Event::RotateFrames, 2, 0),
+            (Event::FlipSplit, 2, 0),
+            (Event::NextDiagnostic, 2, 0),
+            (Event::PreviousDiagnostic, 2, 0),
+            (Event::ListDiagnostics, 1, 0),
+            (Event::StartCompletion, 8, 0),
+            (Event::SelectCompletion, 5, 0),
+            (Event::AcceptCompletion, 5, 0),
+            (Event::CancelCompletion, 2, 0),
+            (Event::GotoDefinition, 2, 0),
+            (Event::JumpBack, 2, 0),
+            (Event::FindReferences, 1, 0),
+            (Event::Hover, 2, 0),
+            (Event::Undo, 8, 0),
+            (Event::Format, 1, 0),
+            (Event::CodeActions, 2, 0),
+            (Event::SelectCodeAction, 1, 0),
+            (Event::AcceptCodeAction, 1, 0),
+            (Event::CancelCodeAction, 1, 0),
+            (Event::RenameSymbol, 1, 0),
+            (Event::CancelSignatureHelp, 1, 0),
+            (Event::ShowOutline, 2, 0),
+            (Event::WorkspaceSymbol, 1, 0),
+            (Event::NextTheme, 2, 0),
+            (Event::ToggleLineNumbers, 3, 0),
+            (Event::ToggleSoftWrap, 3, 0),
+            (Event::ExpandRegion, 5, 0),
+            (Event::ClearRegion, 3, 0),
+            (Event::ToggleFold, 5, 0),
+            (Event::ToggleLanguage, 3, 0),
         ];
 
         (
@@ -243,6 +1024,173 @@ mod monkey_test {
         )
     }
 
+    fn key(code: KeyCode) -> TermEvent {
+        TermEvent::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn char_key(c: char) -> TermEvent {
+        key(KeyCode::Char(c))
+    }
+
+    fn ctrl(c: char) -> TermEvent {
+        TermEvent::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL))
+    }
+
+    fn alt(c: char) -> TermEvent {
+        TermEvent::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::ALT))
+    }
+
+    fn mouse(kind: MouseEventKind, column: u16, row: u16) -> TermEvent {
+        TermEvent::Mouse(MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        })
+    }
+
+    fn random_char(rng: &mut ThreadRng) -> TermEvent {
+        char_key(rng.sample(rand::distributions::Alphanumeric) as char)
+    }
+
+    /// The keys typed or the mouse actions made for the event on a terminal of the size.
+    fn inputs(event: Event, size: Rect, rng: &mut ThreadRng) -> Vec<TermEvent> {
+        let mut position = || {
+            (
+                rng.gen_range(0..size.width.max(1)),
+                rng.gen_range(0..size.height.max(1)),
+            )
+        };
+        let sides = [KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down];
+
+        match event {
+            Event::NewChar => vec![random_char(rng)],
+            Event::NewLine => vec![ctrl('j')],
+            Event::MoveLeft => vec![ctrl('b')],
+            Event::MoveRight => vec![ctrl('f')],
+            Event::MoveUp => vec![ctrl('p')],
+            Event::MoveDown => vec![ctrl('n')],
+            Event::MoveFront => vec![ctrl('a')],
+            Event::MoveEnd => vec![ctrl('e')],
+            Event::MoveTop => vec![ctrl('x'), char_key('[')],
+            Event::MoveBottom => vec![ctrl('x'), char_key(']')],
+            Event::Backspace => vec![ctrl('h')],
+            Event::Delete => vec![ctrl('d')],
+            Event::Kill => vec![ctrl('k')],
+            Event::Paste => vec![ctrl('y')],
+            Event::SplitHorizontal => vec![ctrl('x'), char_key('3')],
+            Event::SplitVertical => vec![ctrl('x'), char_key('2')],
+            Event::ToggleZoom => vec![ctrl('x'), char_key('z')],
+            Event::MoveFocus => vec![ctrl('x'), char_key('o')],
+            Event::SwitchBuffer => {
+                let mut inputs = vec![ctrl('x'), char_key('b')];
+
+                for _ in 0..rng.gen_range(0..3) {
+                    inputs.push(random_char(rng));
+                }
+
+                inputs.extend([key(KeyCode::Tab), key(KeyCode::Enter)]);
+                inputs
+            }
+            Event::KillBuffer => vec![ctrl('x'), char_key('k'), key(KeyCode::Enter)],
+            Event::ListBuffers => vec![ctrl('x'), ctrl('b')],
+            Event::OpenEntry => vec![key(KeyCode::Enter)],
+            Event::FindFile => vec![
+                ctrl('x'),
+                ctrl('f'),
+                random_char(rng),
+                key(KeyCode::Tab),
+                key(KeyCode::Enter),
+            ],
+            Event::RestoreSession => Vec::new(),
+            Event::NewTab => vec![ctrl('x'), ctrl('t')],
+            Event::CloseTab => vec![ctrl('x'), ctrl('w')],
+            Event::NextTab => vec![ctrl('x'), ctrl('n')],
+            Event::PreviousTab => vec![ctrl('x'), ctrl('p')],
+            Event::RenameTab => vec![ctrl('x'), ctrl('r'), random_char(rng), key(KeyCode::Enter)],
+            Event::RemoveFrame => vec![ctrl('x'), char_key('0')],
+            Event::EnlargeHorizontally => vec![ctrl('x'), char_key('}')],
+            Event::ShrinkHorizontally => vec![ctrl('x'), char_key('{')],
+            Event::EnlargeVertically => vec![ctrl('x'), char_key('^')],
+            Event::ShrinkVertically => vec![ctrl('x'), char_key('-')],
+            Event::Balance => vec![ctrl('x'), char_key('+')],
+            Event::FocusLeft => vec![ctrl('x'), key(KeyCode::Left)],
+            Event::FocusRight => vec![ctrl('x'), key(KeyCode::Right)],
+            Event::FocusUp => vec![ctrl('x'), key(KeyCode::Up)],
+            Event::FocusDown => vec![ctrl('x'), key(KeyCode::Down)],
+            Event::FocusNumber => vec![
+                ctrl('x'),
+                char_key('#'),
+                char_key(char::from_digit(rng.gen_range(1..10), 10).unwrap()),
+            ],
+            Event::SwapFrame => vec![
+                ctrl('x'),
+                TermEvent::Key(KeyEvent::new(
+                    *sides.choose(rng).unwrap(),
+                    KeyModifiers::SHIFT,
+                )),
+            ],
+            Event::RotateFrames => vec![ctrl('x'), char_key('r')],
+            Event::FlipSplit => vec![ctrl('x'), char_key('|')],
+            Event::NextDiagnostic => vec![alt('n')],
+            Event::PreviousDiagnostic => vec![alt('p')],
+            Event::ListDiagnostics => vec![ctrl('x'), char_key('d')],
+            Event::StartCompletion => vec![alt('/')],
+            Event::SelectCompletion | Event::SelectCodeAction => {
+                vec![ctrl(if rng.gen() { 'n' } else { 'p' })]
+            }
+            Event::AcceptCompletion | Event::AcceptCodeAction => vec![key(KeyCode::Enter)],
+            Event::CancelCompletion => vec![key(KeyCode::Esc)],
+            Event::GotoDefinition => vec![alt('.')],
+            Event::JumpBack => vec![alt(',')],
+            Event::FindReferences => vec![alt('r')],
+            Event::Hover => vec![alt('h')],
+            Event::Undo => vec![ctrl('/')],
+            Event::Format => vec![alt('q')],
+            Event::CodeActions => vec![alt('a')],
+            Event::CancelCodeAction | Event::CancelSignatureHelp | Event::ClearRegion => {
+                vec![ctrl('g')]
+            }
+            Event::RenameSymbol => vec![alt('m'), random_char(rng), key(KeyCode::Enter)],
+            Event::ShowOutline => vec![alt('o')],
+            Event::WorkspaceSymbol => vec![alt('s'), random_char(rng), key(KeyCode::Enter)],
+            Event::NextTheme => vec![ctrl('x'), char_key('t')],
+            Event::ToggleLineNumbers => vec![ctrl('x'), char_key('n')],
+            Event::ToggleSoftWrap => vec![ctrl('x'), char_key('w')],
+            Event::ExpandRegion => vec![ctrl('x'), char_key('e')],
+            Event::ToggleFold => vec![ctrl('x'), char_key('f')],
+            Event::ToggleLanguage => vec![ctrl('x'), char_key('l')],
+            Event::MouseClick => {
+                let (column, row) = position();
+
+                vec![
+                    mouse(MouseEventKind::Down(MouseButton::Left), column, row),
+                    mouse(MouseEventKind::Up(MouseButton::Left), column, row),
+                ]
+            }
+            Event::MouseDrag => {
+                let (column, row) = position();
+                let (to_column, to_row) = position();
+
+                vec![
+                    mouse(MouseEventKind::Down(MouseButton::Left), column, row),
+                    mouse(MouseEventKind::Drag(MouseButton::Left), to_column, to_row),
+                    mouse(MouseEventKind::Up(MouseButton::Left), to_column, to_row),
+                ]
+            }
+            Event::MouseScroll => {
+                let (column, row) = position();
+                let kind = if rng.gen() {
+                    MouseEventKind::ScrollDown
+                } else {
+                    MouseEventKind::ScrollUp
+                };
+
+                vec![mouse(kind, column, row)]
+            }
+        }
+    }
+
     #[tokio::test]
     async fn run() {
         WriteLogger::init(
@@ -260,42 +1208,33 @@ mod monkey_test {
             let mut rng = thread_rng();
 
             loop {
-                terminal.draw(|f| ui(f, &window)).unwrap();
+                let event = &mut events[weighted_index.sample(&mut rng)];
+
+                if let Event::RestoreSession = event.0 {
+                    // Nothing is bound to it.
+                    let session = window.session();
+                    window.restore_session(&session);
+                }
 
-                let frame = window.focus_frame();
-                let mut event = &mut events[weighted_index.sample(&mut rng)];
+                let size = terminal.size().unwrap();
 
-                match event.0 {
-                    Event::NewChar => {
-                        let c = rng.sample(rand::distributions::Alphanumeric) as char;
-                        frame.borrow_mut().new_char(c);
-                    }
-                    Event::NewLine => frame.borrow_mut().new_line(),
-                    Event::MoveLeft => frame.borrow_mut().move_left(),
-                    Event::MoveRight => frame.borrow_mut().move_right(),
-                    Event::MoveUp => frame.borrow_mut().move_up(),
-                    Event::MoveDown => frame.borrow_mut().move_down(),
-                    Event::MoveFront => frame.borrow_mut().move_front(),
-                    Event::MoveEnd => frame.borrow_mut().move_end(),
-                    Event::MoveTop => frame.borrow_mut().move_top(),
-                    Event::MoveBottom => frame.borrow_mut().move_bottom(),
-                    Event::Backspace => frame.borrow_mut().backspace(),
-                    Event::Delete => frame.borrow_mut().delete(),
-                    Event::Kill => window.kill(),
-                    Event::Paste => window.paste(),
-                    Event::SplitHorizontal => frame.borrow_mut().split(Direction::Horizontal),
-                    Event::SplitVertical => frame.borrow_mut().split(Direction::Vertical),
-                    Event::MoveFocus => window.move_focus(),
-                    Event::NewBuffer => {
-                        // TODO
-                        use buffer::Buffer;
-                        use std::cell::RefCell;
-                        use std::rc::Rc;
-                        frame
-                            .borrow_mut()
-                            .replace_buffer(Rc::new(RefCell::new(Buffer::new())));
+                for input in inputs(event.0, size, &mut rng) {
+                    window.update_language_servers();
+                    window.run_finished_jobs();
+                    terminal.draw(|f| ui(f, &window)).unwrap();
+                    window.clear_message();
+
+                    match input {
+                        TermEvent::Key(key) => assert!(handle_key(&mut window, key)),
+                        TermEvent::Mouse(mouse) => handle_mouse(&mut window, mouse),
+                        TermEvent::Resize(..) => {}
                     }
-                    Event::RemoveFrame => window.remove_focus_frame(),
+
+                    window.update_completion();
+                    window.update_signature_help();
+                    window.update_outline();
+                    window.update_symbol_search();
+                    window.seal_undo();
                 }
 
                 event.2 += 1;
@@ -315,8 +1254,212 @@ mod monkey_test {
 
         reset_terminal().unwrap();
 
-        if let Err(_) = res {
+        if res.is_err() {
             panic!();
         }
     }
 }
+
+#[cfg(test)]
+mod app_test {
+    use super::*;
+    use crate::lsp::tests::{mock_server, project};
+    use futures::stream;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::time::Duration;
+    use tui::backend::TestBackend;
+
+    /// Runs the app on the events for a while. Nothing quits it, so what follows them is
+    /// drawn only if something else wakes it up.
+    async fn run_for(window: &mut Window, events: Vec<Event>) -> Terminal<TestBackend> {
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        let events = stream::iter(events.into_iter().map(Ok)).chain(stream::pending());
+
+        let _ = time::timeout(
+            Duration::from_secs(2),
+            run_app(&mut terminal, window, events),
+        )
+        .await;
+
+        terminal
+    }
+
+    fn bottom_row(terminal: &Terminal<TestBackend>) -> String {
+        let buffer = terminal.backend().buffer();
+        let y = buffer.area.bottom() - 1;
+
+        (0..buffer.area.width)
+            .map(|x| buffer.get(x, y).symbol.as_str())
+            .collect::<String>()
+            .trim_end()
+            .to_owned()
+    }
+
+    fn ctrl(c: char) -> Event {
+        Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL))
+    }
+
+    #[tokio::test]
+    async fn language_server_redraw() {
+        let (root, file) = project("editor-app-diagnostics", "error");
+        let mut window = Window::new();
+
+        window.set_config(Config {
+            language_servers: HashMap::from([("rust".to_owned(), mock_server(&[]))]),
+            ..Config::default()
+        });
+        window.find_file(&file);
+
+        let terminal = run_for(&mut window, Vec::new()).await;
+
+        assert_eq!(bottom_row(&terminal), "error: found error");
+
+        window.shutdown_language_servers().await;
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn finished_job_redraw() {
+        let mut window = Window::new();
+        let terminal = run_for(&mut window, vec![ctrl('x'), ctrl('f')]).await;
+        let prompt = window.prompt().unwrap();
+
+        assert!(prompt.matches().contains(&"Cargo.toml"));
+        let text: String = prompt.text().chars().take(80).collect();
+
+        assert_eq!(bottom_row(&terminal), text.trim_end());
+    }
+}
+
+#[cfg(test)]
+mod fuzz_test {
+    use super::*;
+    use frame::{Frame, MIN_FRAME_HEIGHT, MIN_FRAME_WIDTH};
+    use rand::prelude::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use tui::backend::TestBackend;
+
+    const FUZZ_COUNTER_LIMIT: u32 = 3000;
+
+    /// Changes the terminal size at random between layout commands and checks that the frames
+    /// tile the screen and keep their minimum size whenever the screen has room for it.
+    #[test]
+    fn resize() {
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        let mut window = Window::new();
+        let mut rng = thread_rng();
+
+        for _ in 0..FUZZ_COUNTER_LIMIT {
+            let size = terminal.size().unwrap();
+            let (x, y) = (
+                rng.gen_range(0..size.width.max(1)),
+                rng.gen_range(0..size.height.max(1)),
+            );
+            let direction = if rng.gen() {
+                Direction::Horizontal
+            } else {
+                Direction::Vertical
+            };
+
+            match rng.gen_range(0..10) {
+                0 => terminal
+                    .backend_mut()
+                    .resize(rng.gen_range(0..160), rng.gen_range(0..60)),
+                1 | 2 => window.split(direction),
+                3 => window.remove_focus_frame(),
+                4 => window.resize_focus_frame(direction, SPLIT_RATIO_STEP * rng.gen_range(-4..5)),
+                5 => window.move_focus(),
+                6 => window.focus_frame().borrow_mut().new_char('x'),
+                7 => window.focus_frame().borrow_mut().new_line(),
+                8 => {
+                    window.mouse_down(x, y);
+                    window.mouse_drag(rng.gen_range(0..160), rng.gen_range(0..60));
+                    window.mouse_up();
+                }
+                _ => window.balance(),
+            }
+
+            terminal.draw(|f| ui(f, &window)).unwrap();
+            check_layout(terminal.size().unwrap(), &window);
+        }
+    }
+
+    fn check_layout(size: Rect, window: &Window) {
+        let area = Rect {
+            height: size.height.saturating_sub(MINIBUFFER_HEIGHT),
+            ..size
+        };
+        let main_frame = window.main_frame();
+        let leaves = Frame::leaves(&main_frame);
+        let covered: u32 = leaves
+            .iter()
+            .map(|frame| frame.borrow().rect().area() as u32)
+            .sum();
+
+        assert_eq!(covered, area.area() as u32);
+
+        let fits = area.width >= main_frame.borrow().min_size(&Direction::Horizontal)
+            && area.height >= main_frame.borrow().min_size(&Direction::Vertical);
+
+        for frame in leaves {
+            let r = frame.borrow().rect();
+
+            assert!(
+                r.left() >= area.left() && r.right() <= area.right(),
+                "{:?}",
+                r
+            );
+            assert!(
+                r.top() >= area.top() && r.bottom() <= area.bottom(),
+                "{:?}",
+                r
+            );
+
+            if fits {
+                assert!(
+                    r.width >= MIN_FRAME_WIDTH && r.height >= MIN_FRAME_HEIGHT,
+                    "{:?}",
+                    r
+                );
+            }
+        }
+
+        check_splits(&main_frame);
+
+        if area.width >= MIN_FRAME_WIDTH && area.height >= MIN_FRAME_HEIGHT {
+            let r = window.focus_frame().borrow().rect();
+
+            assert!(
+                r.width >= MIN_FRAME_WIDTH && r.height >= MIN_FRAME_HEIGHT,
+                "{:?}",
+                r
+            );
+        }
+    }
+
+    /// Both frames of every split are shown, or the one holding the focus takes the whole split.
+    fn check_splits(frame: &Rc<RefCell<Frame>>) {
+        let frame = frame.borrow();
+
+        if !frame.has_inner_frames() || frame.rect().area() == 0 {
+            return;
+        }
+
+        let (f0, f1) = frame.clone_inner_frames();
+        let shown = if f1.borrow().has_focus() { &f1 } else { &f0 };
+        let both = f0.borrow().rect().area() > 0 && f1.borrow().rect().area() > 0;
+
+        assert!(
+            both || shown.borrow().rect() == frame.rect(),
+            "{:?} {:?} {:?}",
+            frame.rect(),
+            f0.borrow().rect(),
+            f1.borrow().rect()
+        );
+
+        check_splits(&f0);
+        check_splits(&f1);
+    }
+}
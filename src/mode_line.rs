@@ -0,0 +1,48 @@
+use crate::frame::RenderedFrame;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Segment {
+    Modified,
+    BufferName,
+    Position,
+    FileType,
+    XMode,
+}
+
+impl Segment {
+    fn text(&self, rendered_frame: &RenderedFrame) -> String {
+        let buffer = rendered_frame.buffer.borrow();
+
+        match self {
+            Segment::Modified => match (buffer.is_read_only(), buffer.is_modified()) {
+                (true, _) => "%%",
+                (false, true) => "**",
+                (false, false) => "--",
+            }
+            .to_owned(),
+            Segment::BufferName => buffer.name().to_owned(),
+            Segment::Position => format!(
+                "L{}:C{}",
+                rendered_frame.position.1 + 1,
+                rendered_frame.position.0 + 1
+            ),
+            Segment::FileType => buffer
+                .language()
+                .map_or("text", |language| language.name())
+                .to_owned(),
+            Segment::XMode => if rendered_frame.x_mode { "C-x-" } else { "" }.to_owned(),
+        }
+    }
+}
+
+pub fn mode_line_text(segments: &[Segment], rendered_frame: &RenderedFrame) -> String {
+    let texts: Vec<String> = segments
+        .iter()
+        .map(|segment| segment.text(rendered_frame))
+        .filter(|text| !text.is_empty())
+        .collect();
+
+    format!(" {}", texts.join("  "))
+}
@@ -0,0 +1,160 @@
+use crate::completion::fuzzy_score;
+
+pub const MINIBUFFER_HEIGHT: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PromptKind {
+    SwitchBuffer,
+    KillBuffer,
+    ConfirmKill,
+    FindFile,
+    RenameWorkspace,
+    RenameSymbol,
+    WorkspaceSymbol,
+}
+
+impl PromptKind {
+    fn label(&self) -> &'static str {
+        match self {
+            PromptKind::SwitchBuffer => "Switch to buffer",
+            PromptKind::KillBuffer => "Kill buffer",
+            PromptKind::ConfirmKill => "Buffer modified; kill anyway",
+            PromptKind::FindFile => "Find file",
+            PromptKind::RenameWorkspace => "Rename tab",
+            PromptKind::RenameSymbol => "Rename to",
+            PromptKind::WorkspaceSymbol => "Symbol",
+        }
+    }
+}
+
+/// A line of input read in the minibuffer, completed from a list of candidates.
+pub struct Prompt {
+    kind: PromptKind,
+    input: String,
+    default: Option<String>,
+    candidates: Vec<String>,
+}
+
+impl Prompt {
+    /// The default is taken when the input is left empty.
+    pub fn new(kind: PromptKind, default: Option<String>, candidates: Vec<String>) -> Self {
+        Prompt {
+            kind,
+            input: String::new(),
+            default,
+            candidates,
+        }
+    }
+
+    pub fn kind(&self) -> PromptKind {
+        self.kind
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.input.push(c);
+    }
+
+    pub fn backspace(&mut self) {
+        self.input.pop();
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// Replaces the input, to start from a value to edit.
+    pub fn set_input(&mut self, input: &str) {
+        self.input = input.to_owned();
+    }
+
+    /// Replaces the candidates, for prompts whose candidates depend on the input.
+    pub fn set_candidates(&mut self, candidates: Vec<String>) {
+        self.candidates = candidates;
+    }
+
+    /// Candidates containing the input, the ones starting with it first. Symbols are matched
+    /// fuzzily instead, the best ones first.
+    pub fn matches(&self) -> Vec<&str> {
+        if self.kind == PromptKind::WorkspaceSymbol {
+            let mut scored: Vec<(i64, &str)> = self
+                .candidates
+                .iter()
+                .filter_map(|candidate| {
+                    fuzzy_score(&self.input, candidate).map(|score| (score, candidate.as_str()))
+                })
+                .collect();
+
+            scored.sort_by_key(|(score, _)| -score);
+            return scored.into_iter().map(|(_, candidate)| candidate).collect();
+        }
+
+        let (mut prefixed, contained): (Vec<&str>, Vec<&str>) = self
+            .candidates
+            .iter()
+            .map(|candidate| candidate.as_str())
+            .filter(|candidate| candidate.contains(&self.input))
+            .partition(|candidate| candidate.starts_with(&self.input));
+
+        prefixed.extend(contained);
+        prefixed
+    }
+
+    /// Extends the input to the longest prefix shared by the matches starting with it, or
+    /// takes the only match.
+    pub fn complete(&mut self) {
+        let matches = self.matches();
+
+        if let [only] = matches[..] {
+            self.input = only.to_owned();
+            return;
+        }
+
+        let mut prefixed = matches
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(&self.input));
+
+        if let Some(first) = prefixed.next() {
+            let common = prefixed.fold(first, |common, candidate| {
+                let len = common
+                    .char_indices()
+                    .zip(candidate.chars())
+                    .take_while(|((_, a), b)| a == b)
+                    .last()
+                    .map_or(0, |((idx, a), _)| idx + a.len_utf8());
+
+                &common[..len]
+            });
+
+            self.input = common.to_owned();
+        }
+    }
+
+    /// The input, or the default if nothing has been typed.
+    pub fn value(&self) -> String {
+        match (self.input.is_empty(), &self.default) {
+            (true, Some(default)) => default.clone(),
+            _ => self.input.clone(),
+        }
+    }
+
+    /// The label and the input, followed by the matches if there is anything to complete.
+    pub fn text(&self) -> String {
+        if self.candidates.is_empty() {
+            return self.head();
+        }
+
+        format!("{} {{{}}}", self.head(), self.matches().join(" | "))
+    }
+
+    /// The column of the cursor in `text`.
+    pub fn cursor(&self) -> usize {
+        self.head().chars().count()
+    }
+
+    fn head(&self) -> String {
+        match (self.input.is_empty(), &self.default) {
+            (true, Some(default)) => format!("{} (default {}): ", self.kind.label(), default),
+            _ => format!("{}: {}", self.kind.label(), self.input),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::frame::{Cursor, Direction, Offset};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The state of a `Window` kept across restarts. Buffers are reopened from their paths, the
+/// ones without a path are left out and their frames show another buffer.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    pub buffers: Vec<BufferState>,
+    pub workspaces: Vec<WorkspaceState>,
+    /// The index of the shown workspace.
+    pub workspace: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WorkspaceState {
+    pub name: String,
+    pub frame: FrameState,
+    /// The index of the focused leaf in render order.
+    pub focus: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BufferState {
+    pub name: String,
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum FrameState {
+    Split {
+        direction: SplitDirection,
+        ratio: u16,
+        frames: Box<(FrameState, FrameState)>,
+    },
+    Leaf {
+        buffer: String,
+        cursor: Cursor,
+        /// The horizontal scroll and the buffer line at the top of the frame.
+        offset: Offset,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum SplitDirection {
+    Horizontal,
+    Vertical,
+}
+
+impl From<&Direction> for SplitDirection {
+    fn from(direction: &Direction) -> Self {
+        match direction {
+            Direction::Horizontal => SplitDirection::Horizontal,
+            Direction::Vertical => SplitDirection::Vertical,
+        }
+    }
+}
+
+impl From<SplitDirection> for Direction {
+    fn from(direction: SplitDirection) -> Self {
+        match direction {
+            SplitDirection::Horizontal => Direction::Horizontal,
+            SplitDirection::Vertical => Direction::Vertical,
+        }
+    }
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// `session.json` in the config directory, used when `--session` is given without a file.
+pub fn default_path() -> Option<PathBuf> {
+    crate::config::config_dir().map(|dir| dir.join("session.json"))
+}
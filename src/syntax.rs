@@ -0,0 +1,194 @@
+use std::ops::Range;
+use std::path::Path;
+use tree_sitter::{InputEdit, Node, Parser, Point, Query, QueryCursor, Tree};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Rust,
+}
+
+impl Language {
+    /// The language of a file, told by its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "rs" => Some(Language::Rust),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::Rust => "rust",
+        }
+    }
+
+    fn grammar(&self) -> tree_sitter::Language {
+        match self {
+            Language::Rust => tree_sitter_rust::language(),
+        }
+    }
+
+    fn highlight_query(&self) -> &'static str {
+        match self {
+            Language::Rust => tree_sitter_rust::HIGHLIGHT_QUERY,
+        }
+    }
+}
+
+pub struct Syntax {
+    language: Language,
+    parser: Parser,
+    tree: Tree,
+    query: Query,
+}
+
+impl Syntax {
+    pub fn new(language: Language, lines: &[String]) -> Self {
+        let mut parser = Parser::new();
+        parser.set_language(language.grammar()).unwrap();
+
+        let tree = parser
+            .parse_with(&mut |_, point| read(lines, point), None)
+            .unwrap();
+        let query = Query::new(language.grammar(), language.highlight_query()).unwrap();
+
+        Syntax {
+            language,
+            parser,
+            tree,
+            query,
+        }
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Tells the old tree about an edit and reparses the new lines reusing the unchanged nodes,
+    /// so that only the text around the edit is read. Returns the rows whose syntax changed, the
+    /// edited ones included.
+    pub fn edit(&mut self, edit: &InputEdit, lines: &[String]) -> Range<usize> {
+        let mut rows = edit.start_position.row..edit.new_end_position.row + 1;
+
+        self.tree.edit(edit);
+
+        if let Some(tree) = self
+            .parser
+            .parse_with(&mut |_, point| read(lines, point), Some(&self.tree))
+        {
+            for range in self.tree.changed_ranges(&tree) {
+                rows.start = rows.start.min(range.start_point.row);
+                rows.end = rows.end.max(range.end_point.row + 1);
+            }
+
+            self.tree = tree;
+        }
+
+        rows
+    }
+
+    /// Returns the ranges with their scope names of the nodes touching the rows. A node captured
+    /// by several patterns keeps the first one.
+    pub fn highlights(&self, lines: &[String], rows: Range<usize>) -> Vec<(Point, Point, String)> {
+        let mut cursor = QueryCursor::new();
+        let mut highlights = Vec::new();
+        let mut last_node = None;
+        let text = |node: Node| between(lines, node.start_position(), node.end_position());
+
+        cursor.set_point_range(Point::new(rows.start, 0)..Point::new(rows.end, 0));
+
+        for (m, idx) in cursor.captures(&self.query, self.tree.root_node(), text) {
+            let capture = m.captures[idx];
+
+            if last_node == Some(capture.node.id()) {
+                continue;
+            }
+
+            last_node = Some(capture.node.id());
+
+            highlights.push((
+                capture.node.start_position(),
+                capture.node.end_position(),
+                self.query.capture_names()[capture.index as usize].clone(),
+            ));
+        }
+
+        highlights
+    }
+
+    /// The smallest node strictly enclosing the range, used to expand a region.
+    pub fn enclosing_range(&self, start: Point, end: Point) -> Option<(Point, Point)> {
+        let mut node = self
+            .tree
+            .root_node()
+            .descendant_for_point_range(start, end)?;
+
+        while node.start_position() == start && node.end_position() == end {
+            node = node.parent()?;
+        }
+
+        Some((node.start_position(), node.end_position()))
+    }
+
+    /// The innermost named node containing the point which spans several lines, as a row range.
+    pub fn fold_range(&self, point: Point) -> Option<(usize, usize)> {
+        let mut node: Option<Node> = self
+            .tree
+            .root_node()
+            .descendant_for_point_range(point, point);
+
+        while let Some(n) = node {
+            if n.is_named() && n.start_position().row < n.end_position().row && n.parent().is_some()
+            {
+                return Some((n.start_position().row, n.end_position().row));
+            }
+
+            node = n.parent();
+        }
+
+        None
+    }
+}
+
+pub fn input_edit(start_byte: usize, start: Point, removed: &str, inserted: &str) -> InputEdit {
+    InputEdit {
+        start_byte,
+        old_end_byte: start_byte + removed.len(),
+        new_end_byte: start_byte + inserted.len(),
+        start_position: start,
+        old_end_position: advance(start, removed),
+        new_end_position: advance(start, inserted),
+    }
+}
+
+/// The text from the point to the end of its line, or the line break after it.
+fn read(lines: &[String], point: Point) -> &[u8] {
+    match lines.get(point.row) {
+        Some(line) if point.column < line.len() => &line.as_bytes()[point.column..],
+        Some(_) if point.row + 1 < lines.len() => b"\n",
+        _ => b"",
+    }
+}
+
+/// The text between the points, a piece of a line or a line break at a time.
+fn between(lines: &[String], start: Point, end: Point) -> impl Iterator<Item = &[u8]> {
+    (start.row..=end.row.min(lines.len().saturating_sub(1))).flat_map(move |row| {
+        let line = lines[row].as_bytes();
+        let from = if row == start.row { start.column } else { 0 };
+        let to = if row == end.row {
+            end.column
+        } else {
+            line.len()
+        };
+        let line_break: &[u8] = if row < end.row { b"\n" } else { b"" };
+
+        [&line[from.min(line.len())..to.min(line.len())], line_break]
+    })
+}
+
+fn advance(point: Point, s: &str) -> Point {
+    match s.rfind('\n') {
+        Some(idx) => Point::new(point.row + s.matches('\n').count(), s.len() - idx - 1),
+        None => Point::new(point.row, point.column + s.len()),
+    }
+}
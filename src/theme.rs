@@ -0,0 +1,207 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::BorderType;
+
+const DARK: &str = include_str!("../themes/dark.toml");
+const LIGHT: &str = include_str!("../themes/light.toml");
+
+#[derive(Deserialize)]
+struct StyleDef {
+    fg: Option<String>,
+    bg: Option<String>,
+    #[serde(default)]
+    modifiers: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ThemeFile {
+    name: String,
+    border_type: Option<String>,
+    #[serde(default)]
+    ui: HashMap<String, StyleDef>,
+    #[serde(default)]
+    syntax: HashMap<String, StyleDef>,
+    #[serde(default)]
+    diagnostics: HashMap<String, StyleDef>,
+    #[serde(default)]
+    semantic_tokens: HashMap<String, StyleDef>,
+    #[serde(default)]
+    semantic_modifiers: HashMap<String, StyleDef>,
+}
+
+pub struct Theme {
+    name: String,
+    border_type: BorderType,
+    ui: HashMap<String, Style>,
+    syntax: HashMap<String, Style>,
+    diagnostics: HashMap<String, Style>,
+    semantic_tokens: HashMap<String, Style>,
+    semantic_modifiers: HashMap<String, Style>,
+}
+
+impl Theme {
+    pub fn builtin() -> Vec<Theme> {
+        vec![
+            Theme::from_toml(DARK).unwrap(),
+            Theme::from_toml(LIGHT).unwrap(),
+        ]
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, Box<dyn Error>> {
+        let file: ThemeFile = toml::from_str(s)?;
+
+        Ok(Theme {
+            name: file.name,
+            border_type: parse_border_type(file.border_type.as_deref().unwrap_or("thick"))?,
+            ui: parse_styles(file.ui)?,
+            syntax: parse_styles(file.syntax)?,
+            diagnostics: parse_styles(file.diagnostics)?,
+            semantic_tokens: parse_styles(file.semantic_tokens)?,
+            semantic_modifiers: parse_styles(file.semantic_modifiers)?,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Theme::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Loads every `*.toml` file in the directory, skipping the ones that fail to parse.
+    pub fn load_dir(dir: &Path) -> Vec<Theme> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+
+        entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .filter_map(|path| match Theme::load(&path) {
+                Ok(theme) => Some(theme),
+                Err(e) => {
+                    warn!("failed to load theme {:?}: {}", path, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn border_type(&self) -> BorderType {
+        self.border_type
+    }
+
+    pub fn ui(&self, key: &str) -> Style {
+        self.ui.get(key).copied().unwrap_or_default()
+    }
+
+    /// Looks up a scope like `function.method`, falling back to `function`.
+    pub fn syntax(&self, scope: &str) -> Option<Style> {
+        let mut scope = scope;
+
+        loop {
+            if let Some(style) = self.syntax.get(scope) {
+                return Some(*style);
+            }
+
+            scope = &scope[..scope.rfind('.')?];
+        }
+    }
+
+    /// The style of a semantic token type like `macro`, patched with the styles of its
+    /// modifiers like `mutable`. `None` if the theme styles neither, leaving the syntax style.
+    pub fn semantic(&self, token_type: &str, modifiers: &[String]) -> Option<Style> {
+        let mut styles = self.semantic_tokens.get(token_type).into_iter().chain(
+            modifiers
+                .iter()
+                .filter_map(|modifier| self.semantic_modifiers.get(modifier)),
+        );
+        let first = *styles.next()?;
+
+        Some(styles.fold(first, |style, modifier| style.patch(*modifier)))
+    }
+
+    pub fn diagnostic(&self, severity: &str) -> Style {
+        self.diagnostics.get(severity).copied().unwrap_or_default()
+    }
+}
+
+fn parse_styles(defs: HashMap<String, StyleDef>) -> Result<HashMap<String, Style>, Box<dyn Error>> {
+    defs.into_iter()
+        .map(|(key, def)| Ok((key, parse_style(def)?)))
+        .collect()
+}
+
+fn parse_style(def: StyleDef) -> Result<Style, Box<dyn Error>> {
+    let mut style = Style::default();
+
+    if let Some(fg) = def.fg {
+        style = style.fg(parse_color(&fg)?);
+    }
+
+    if let Some(bg) = def.bg {
+        style = style.bg(parse_color(&bg)?);
+    }
+
+    for modifier in def.modifiers {
+        style = style.add_modifier(match modifier.as_str() {
+            "bold" => Modifier::BOLD,
+            "dim" => Modifier::DIM,
+            "italic" => Modifier::ITALIC,
+            "underlined" => Modifier::UNDERLINED,
+            "reversed" => Modifier::REVERSED,
+            "crossed_out" => Modifier::CROSSED_OUT,
+            _ => return Err(format!("unknown modifier: {}", modifier).into()),
+        });
+    }
+
+    Ok(style)
+}
+
+fn parse_color(s: &str) -> Result<Color, Box<dyn Error>> {
+    if let Some(hex) = s.strip_prefix('#') {
+        if hex.len() == 6 {
+            let rgb = u32::from_str_radix(hex, 16)?;
+            return Ok(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+        }
+    }
+
+    let color = match s {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" => Color::Gray,
+        "darkgray" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return Err(format!("unknown color: {}", s).into()),
+    };
+
+    Ok(color)
+}
+
+fn parse_border_type(s: &str) -> Result<BorderType, Box<dyn Error>> {
+    match s {
+        "plain" => Ok(BorderType::Plain),
+        "rounded" => Ok(BorderType::Rounded),
+        "double" => Ok(BorderType::Double),
+        "thick" => Ok(BorderType::Thick),
+        _ => Err(format!("unknown border type: {}", s).into()),
+    }
+}
@@ -1,35 +1,1536 @@
-use crate::buffer::Buffer;
-use crate::frame::Frame;
+use crate::buffer::{is_word_char, severity_name, Buffer, ListEntry, Marker, FOLD_MARKER, TAB};
+use crate::completion::Completion;
+use crate::config::Config;
+use crate::frame::{Cursor, Direction, Frame};
+use crate::lsp::{
+    active_signature, markdown_lines, symbol_kind_name, Event, LanguageServers, Symbol, Ticket,
+};
+use crate::prompt::{Prompt, PromptKind};
+use crate::session::{BufferState, Session, WorkspaceState};
+use crate::theme::Theme;
+use lsp_types::{
+    AnnotatedTextEdit, CodeActionOrCommand, Diagnostic, DiagnosticSeverity,
+    DocumentChangeOperation, DocumentChanges, Location, OneOf, Range, SignatureHelp,
+    SymbolInformation, TextEdit, WorkspaceEdit,
+};
 use std::cell::RefCell;
-use std::rc::Rc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+use url::Url;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+const BUFFER_LIST_NAME: &str = "*Buffer List*";
+const DIAGNOSTIC_LIST_NAME: &str = "*Diagnostics*";
+const REFERENCE_LIST_NAME: &str = "*References*";
+const OUTLINE_NAME: &str = "*Outline*";
+const SCROLL_ROWS: usize = 3;
+/// How long a buffer is left alone after an edit before its inlay hints and semantic tokens are
+/// asked for, so that typing does not send a request per key.
+const DECORATION_DELAY: Duration = Duration::from_millis(150);
+
+/// What is left to do with the window once blocking work done on another thread has finished.
+pub type Job = Box<dyn FnOnce(&mut Window) + Send>;
+
+/// A place jumped away from, to go back to.
+struct Jump {
+    buffer: Weak<RefCell<Buffer>>,
+    cursor: Marker,
+}
+
+/// A formatting request whose edits are still to come.
+struct PendingFormat {
+    ticket: Ticket,
+    buffer: Rc<RefCell<Buffer>>,
+    /// The version the edits are made for.
+    version: i32,
+    /// Whether to save the buffer once it is formatted.
+    save: bool,
+}
+
+/// The code actions at the cursor, one of them selected.
+struct CodeActionMenu {
+    actions: Vec<CodeActionOrCommand>,
+    selected: usize,
+}
+
+/// The signature of the call the cursor of a frame is in, shown above the cursor line.
+struct SignaturePopup {
+    frame: Rc<RefCell<Frame>>,
+    /// The request for the help at the cursor, if it has not been answered yet.
+    ticket: Option<Ticket>,
+    help: Option<SignatureHelp>,
+    /// Where the cursor was and the version of the buffer when the help was asked for.
+    cursor: Cursor,
+    version: i32,
+}
+
+/// The symbols of the buffer shown in a frame, listed in a frame beside it. Moving through the
+/// list moves the cursor of the first frame along.
+struct Outline {
+    frame: Weak<RefCell<Frame>>,
+    source: Weak<RefCell<Frame>>,
+    /// The buffer and its version the symbols were last asked for.
+    buffer: Weak<RefCell<Buffer>>,
+    version: i32,
+    ticket: Option<Ticket>,
+    /// The line of the list the source frame was last moved for.
+    line: usize,
+    symbols: Vec<Symbol>,
+    /// The symbols whose children are left out of the list, by the keys from the top down.
+    collapsed: HashSet<Vec<SymbolKey>>,
+    /// The keys down to the symbol of each line of the list.
+    paths: Vec<Vec<SymbolKey>>,
+}
+
+/// The kind and name of a symbol in the outline, with how many symbols before it beside it have
+/// the same ones. Unlike its position, it holds as the text above it is edited.
+type SymbolKey = (String, usize);
+
+/// The workspace symbols offered by the symbol prompt, asked for again as its input changes.
+struct SymbolSearch {
+    /// The buffer whose language server is asked.
+    buffer: Rc<RefCell<Buffer>>,
+    ticket: Option<Ticket>,
+    /// The input the symbols were last asked for with.
+    query: String,
+    /// The candidates of the prompt with the symbols they stand for.
+    symbols: Vec<(String, Location)>,
+}
+
+/// What dragging the mouse with the left button held does.
+enum Drag {
+    Region,
+    Border(Rc<RefCell<Frame>>),
+}
 
 pub struct Window {
-    main_frame: Rc<RefCell<Frame>>,
-    detached_buffer: Vec<Rc<RefCell<Buffer>>>,
+    workspaces: Vec<Workspace>,
+    workspace: usize,
+    /// Every buffer, the most recently shown first.
+    buffers: Vec<Rc<RefCell<Buffer>>>,
+    prompt: Option<Prompt>,
     yank: Option<String>,
+    themes: Vec<Theme>,
+    theme: usize,
+    config: Config,
+    selecting_frame: bool,
+    drag: Option<Drag>,
+    language_servers: LanguageServers,
+    /// The diagnostics of every file in the projects, as the servers have last published them.
+    diagnostics: BTreeMap<Url, Vec<Diagnostic>>,
+    /// The completion popup with the frame it was opened in.
+    completion: Option<(Rc<RefCell<Frame>>, Completion)>,
+    /// The request last made with a key, whose response is still to come. Responses to the
+    /// ones before are dropped.
+    awaited: Option<Ticket>,
+    /// The places left by going to definitions, the last one on top.
+    jumps: Vec<Jump>,
+    /// Shown in the minibuffer until the next key.
+    message: Option<String>,
+    /// The lines of the hover popup, shown at the cursor until the next key.
+    hover: Option<Vec<String>>,
+    /// The id the next undo steps are sealed with.
+    undo_id: u64,
+    /// The buffers of each undo step made of edits to several of them, by id.
+    undo_groups: HashMap<u64, Vec<Weak<RefCell<Buffer>>>>,
+    format: Option<PendingFormat>,
+    code_actions: Option<CodeActionMenu>,
+    signature: Option<SignaturePopup>,
+    /// The inlay hint requests not answered yet, with the versions of their buffers.
+    inlay_hints: HashMap<Ticket, (Weak<RefCell<Buffer>>, i32)>,
+    /// The semantic token requests not answered yet, at most one per buffer since a delta is
+    /// made against the tokens answered last.
+    semantic_tokens: HashMap<Ticket, (Weak<RefCell<Buffer>>, i32)>,
+    outline: Option<Outline>,
+    symbol_search: Option<SymbolSearch>,
+    /// The modified buffer whose killing waits for the answer to `PromptKind::ConfirmKill`.
+    killing: Option<String>,
+    job_sender: UnboundedSender<Job>,
+    /// The jobs which have finished, to be run in the event loop.
+    jobs: UnboundedReceiver<Job>,
+}
+
+/// A tab with its own tree of frames. The buffers are shared by all of them.
+struct Workspace {
+    name: String,
+    main_frame: Rc<RefCell<Frame>>,
+    zoomed: bool,
+    /// The leaf which gets the focus back when the workspace is shown again.
+    focus: usize,
 }
 
-impl Window {
-    pub fn new() -> Self {
-        let buffer = Rc::new(RefCell::new(Buffer::new()));
-        let main_frame = Rc::new(RefCell::new(Frame::new(buffer.clone())));
+impl Workspace {
+    fn new(name: String, buffer: Rc<RefCell<Buffer>>) -> Self {
+        let main_frame = Rc::new(RefCell::new(Frame::new(buffer)));
+
+        main_frame.borrow_mut().set_focus();
+
+        Workspace {
+            name,
+            main_frame,
+            zoomed: false,
+            focus: 0,
+        }
+    }
+}
+
+impl Window {
+    pub fn new() -> Self {
+        let buffer = Rc::new(RefCell::new(Buffer::new()));
+        let (job_sender, jobs) = mpsc::unbounded_channel();
+
+        Window {
+            workspaces: vec![Workspace::new("1".to_owned(), buffer.clone())],
+            workspace: 0,
+            buffers: vec![buffer],
+            prompt: None,
+            yank: None,
+            themes: Theme::builtin(),
+            theme: 0,
+            config: Config::default(),
+            selecting_frame: false,
+            drag: None,
+            language_servers: LanguageServers::new(),
+            diagnostics: BTreeMap::new(),
+            completion: None,
+            awaited: None,
+            jumps: Vec::new(),
+            message: None,
+            hover: None,
+            undo_id: 0,
+            undo_groups: HashMap::new(),
+            format: None,
+            code_actions: None,
+            signature: None,
+            inlay_hints: HashMap::new(),
+            semantic_tokens: HashMap::new(),
+            outline: None,
+            symbol_search: None,
+            killing: None,
+            job_sender,
+            jobs,
+        }
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Notified whenever a language server has sent something to handle.
+    pub fn language_server_messages(&self) -> Arc<Notify> {
+        self.language_servers.messages()
+    }
+
+    /// When a buffer will have been left alone long enough after its last edit for its
+    /// decorations to be asked for, if one is still waiting for that.
+    pub fn deadline(&self) -> Option<Instant> {
+        let now = Instant::now();
+
+        self.buffers
+            .iter()
+            .filter_map(|buffer| buffer.borrow().edited_at())
+            .map(|edited_at| edited_at + DECORATION_DELAY)
+            .filter(|deadline| *deadline > now)
+            .min()
+    }
+
+    /// Runs the blocking work on another thread, then what it returns is applied to the window
+    /// in the event loop. Without a runtime both are done at once.
+    fn spawn_job<T: Send + 'static>(
+        &mut self,
+        work: impl FnOnce() -> T + Send + 'static,
+        apply: impl FnOnce(&mut Window, T) + Send + 'static,
+    ) {
+        if tokio::runtime::Handle::try_current().is_err() {
+            apply(self, work());
+            return;
+        }
+
+        let sender = self.job_sender.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let result = work();
+            let job: Job = Box::new(move |window| apply(window, result));
+
+            // The window has gone if nobody receives it.
+            let _ = sender.send(job);
+        });
+    }
+
+    /// Waits for the next job to finish.
+    pub async fn next_job(&mut self) -> Job {
+        // The window holds a sender, so the channel never closes.
+        self.jobs.recv().await.unwrap()
+    }
+
+    /// Runs the jobs which have finished, without waiting for the others. Returns whether there
+    /// were any.
+    pub fn run_finished_jobs(&mut self) -> bool {
+        let mut ran = false;
+
+        while let Ok(job) = self.jobs.try_recv() {
+            job(self);
+            ran = true;
+        }
+
+        ran
+    }
+
+    /// Handles what the language servers have sent and tells them about the buffers which have
+    /// changed since the last call. Returns whether anything was sent, which may change what
+    /// is shown.
+    pub fn update_language_servers(&mut self) -> bool {
+        let events = self.language_servers.poll();
+        let received = !events.is_empty();
+
+        for event in events {
+            match event {
+                Event::Diagnostics(params) => self.set_diagnostics(params.uri, params.diagnostics),
+                Event::Completion {
+                    ticket,
+                    items,
+                    incomplete,
+                } => {
+                    if let Some((_, completion)) = &mut self.completion {
+                        if completion.ticket() == Some(ticket) {
+                            let pattern = completion.pattern().to_owned();
+
+                            completion.set_items(items, incomplete);
+                            completion.filter(&pattern);
+
+                            if !completion.has_matches() {
+                                self.completion = None;
+                            }
+                        }
+                    }
+                }
+                Event::Definition { ticket, locations } if self.awaited == Some(ticket) => {
+                    self.awaited = None;
+
+                    match &locations[..] {
+                        [] => self.message = Some("No definition found".to_owned()),
+                        [location] => {
+                            self.push_jump();
+                            self.open_location(location);
+                        }
+                        _ => {
+                            self.push_jump();
+                            self.list_references(&locations);
+                        }
+                    }
+                }
+                Event::References { ticket, locations } if self.awaited == Some(ticket) => {
+                    self.awaited = None;
+
+                    if locations.is_empty() {
+                        self.message = Some("No references found".to_owned());
+                    } else {
+                        self.list_references(&locations);
+                    }
+                }
+                Event::Hover { ticket, text } if self.awaited == Some(ticket) => {
+                    self.awaited = None;
+
+                    if text.trim().is_empty() {
+                        self.message = Some("Nothing to show".to_owned());
+                    } else {
+                        self.hover = Some(markdown_lines(&text));
+                    }
+                }
+                Event::Formatting { ticket, edits }
+                    if self.format.as_ref().map(|format| format.ticket) == Some(ticket) =>
+                {
+                    let format = self.format.take().unwrap();
+
+                    if format.buffer.borrow().version() == format.version {
+                        self.edit_buffers(|_| {
+                            format.buffer.borrow_mut().apply_edits(&edits);
+                        });
+
+                        if format.save {
+                            self.save(&format.buffer);
+                        }
+                    } else if format.save {
+                        self.message =
+                            Some("The buffer has changed while formatting; not saved".to_owned());
+                    } else {
+                        self.message = Some("The buffer has changed while formatting".to_owned());
+                    }
+                }
+                Event::CodeActions { ticket, actions } if self.awaited == Some(ticket) => {
+                    self.awaited = None;
+
+                    if actions.is_empty() {
+                        self.message = Some("No code actions".to_owned());
+                    } else {
+                        self.code_actions = Some(CodeActionMenu {
+                            actions,
+                            selected: 0,
+                        });
+                    }
+                }
+                Event::Rename { ticket, edit } if self.awaited == Some(ticket) => {
+                    self.awaited = None;
+
+                    match edit {
+                        Some(edit) => self.apply_workspace_edit(edit),
+                        None => self.message = Some("Nothing to rename here".to_owned()),
+                    }
+                }
+                Event::ApplyEdit(edit) => self.apply_workspace_edit(edit),
+                Event::SignatureHelp { ticket, help } => {
+                    if let Some(signature) = &mut self.signature {
+                        if signature.ticket == Some(ticket) {
+                            if help.is_none() {
+                                self.signature = None;
+                            } else {
+                                signature.ticket = None;
+                                signature.help = help;
+                            }
+                        }
+                    }
+                }
+                Event::InlayHints { ticket, hints } => {
+                    if let Some((buffer, version)) = self.inlay_hints.remove(&ticket) {
+                        if let Some(buffer) = buffer.upgrade() {
+                            if buffer.borrow().version() == version {
+                                buffer.borrow_mut().set_inlay_hints(&hints, version);
+                            }
+                        }
+                    }
+                }
+                Event::SemanticTokens { ticket, tokens } => {
+                    if let Some((buffer, version)) = self.semantic_tokens.remove(&ticket) {
+                        if let Some(buffer) = buffer.upgrade() {
+                            if buffer.borrow().version() == version {
+                                buffer.borrow_mut().set_semantic_tokens(&tokens, version);
+                            }
+                        }
+                    }
+                }
+                Event::DocumentSymbols { ticket, symbols } => self.set_outline(ticket, symbols),
+                Event::WorkspaceSymbols { ticket, symbols } => {
+                    self.set_workspace_symbols(ticket, symbols)
+                }
+                Event::Definition { .. }
+                | Event::References { .. }
+                | Event::Hover { .. }
+                | Event::Formatting { .. }
+                | Event::CodeActions { .. }
+                | Event::Rename { .. } => {}
+            }
+        }
+
+        self.language_servers
+            .update(&self.config.language_servers, &self.buffers);
+        self.request_inlay_hints();
+        self.request_semantic_tokens();
+        received
+    }
+
+    /// Asks for the inlay hints of the buffers whose hints are not for their current text,
+    /// unless they have already been asked for or the buffer is still being edited.
+    fn request_inlay_hints(&mut self) {
+        for buffer in &self.buffers {
+            let version = buffer.borrow().version();
+
+            if buffer.borrow().inlay_hints_version() == Some(version)
+                || is_being_edited(&buffer.borrow())
+                || self.inlay_hints.values().any(|(pending, pending_version)| {
+                    *pending_version == version && pending.ptr_eq(&Rc::downgrade(buffer))
+                })
+            {
+                continue;
+            }
+
+            if let Some(ticket) = self.language_servers.inlay_hints(buffer) {
+                self.inlay_hints
+                    .insert(ticket, (Rc::downgrade(buffer), version));
+            }
+        }
+    }
+
+    /// Asks for the semantic tokens of the buffers whose tokens are not for their current
+    /// text, once the tokens asked for before have come and the buffer is left alone.
+    fn request_semantic_tokens(&mut self) {
+        for buffer in &self.buffers {
+            let version = buffer.borrow().version();
+
+            if buffer.borrow().semantic_tokens_version() == Some(version)
+                || is_being_edited(&buffer.borrow())
+                || self
+                    .semantic_tokens
+                    .values()
+                    .any(|(pending, _)| pending.ptr_eq(&Rc::downgrade(buffer)))
+            {
+                continue;
+            }
+
+            if let Some(ticket) = self.language_servers.semantic_tokens(buffer) {
+                self.semantic_tokens
+                    .insert(ticket, (Rc::downgrade(buffer), version));
+            }
+        }
+    }
+
+    fn set_diagnostics(&mut self, uri: Url, diagnostics: Vec<Diagnostic>) {
+        if let Some(buffer) = uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| self.buffer_visiting(&path))
+        {
+            buffer.borrow_mut().set_diagnostics(&diagnostics);
+        }
+
+        if diagnostics.is_empty() {
+            self.diagnostics.remove(&uri);
+        } else {
+            self.diagnostics.insert(uri, diagnostics);
+        }
+
+        self.refresh_diagnostic_list();
+    }
+
+    /// The message of the diagnostic under the cursor of the focused frame.
+    pub fn diagnostic_message(&self) -> Option<String> {
+        let frame = self.focus_frame();
+        let frame = frame.borrow();
+        let buffer = frame.clone_buffer();
+        let buffer = buffer.borrow();
+
+        buffer.diagnostic_at(frame.cursor()).map(|diagnostic| {
+            format!(
+                "{}: {}",
+                diagnostic.severity_name(),
+                diagnostic.message.lines().next().unwrap_or_default()
+            )
+        })
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn hover(&self) -> Option<&[String]> {
+        self.hover.as_deref()
+    }
+
+    /// Clears the message and the hover popup, which last until the next key.
+    pub fn clear_message(&mut self) {
+        self.message = None;
+        self.hover = None;
+    }
+
+    /// Sends a request about the cursor of the focused frame, to be handled when its response
+    /// comes unless another one is made first.
+    fn request(
+        &mut self,
+        request: impl FnOnce(&mut LanguageServers, &Rc<RefCell<Buffer>>, Cursor) -> Option<Ticket>,
+    ) {
+        let frame = self.focus_frame();
+        let buffer = frame.borrow().clone_buffer();
+        let cursor = frame.borrow().cursor();
+
+        self.awaited = request(&mut self.language_servers, &buffer, cursor);
+
+        if self.awaited.is_none() {
+            self.message = Some("No language server can do that here".to_owned());
+        }
+    }
+
+    /// Goes to the definition of the symbol under the cursor, or lists them if there are more.
+    pub fn goto_definition(&mut self) {
+        self.request(LanguageServers::definition);
+    }
+
+    pub fn find_references(&mut self) {
+        self.request(LanguageServers::references);
+    }
+
+    /// Renames the symbol under the cursor wherever the language server finds it.
+    fn rename_symbol(&mut self, new_name: &str) {
+        self.request(|servers, buffer, cursor| servers.rename(buffer, cursor, new_name));
+    }
+
+    /// Shows what the language server knows about the symbol under the cursor in a popup.
+    pub fn show_hover(&mut self) {
+        self.request(LanguageServers::hover);
+    }
+
+    fn push_jump(&mut self) {
+        let frame = self.focus_frame();
+        let buffer = frame.borrow().clone_buffer();
+        let cursor = buffer.borrow_mut().create_marker(frame.borrow().cursor());
+
+        self.jumps.push(Jump {
+            buffer: Rc::downgrade(&buffer),
+            cursor,
+        });
+    }
+
+    /// Goes back to where the last jump to a definition was made from, skipping killed buffers.
+    pub fn jump_back(&mut self) {
+        while let Some(jump) = self.jumps.pop() {
+            let buffer = match jump.buffer.upgrade() {
+                Some(buffer) if self.buffers.iter().any(|b| Rc::ptr_eq(b, &buffer)) => buffer,
+                _ => continue,
+            };
+
+            self.show_buffer(buffer);
+            self.refresh_buffer_list();
+            self.focus_frame().borrow_mut().move_to(jump.cursor.get());
+            return;
+        }
+
+        self.message = Some("No jump to go back from".to_owned());
+    }
+
+    /// Shows the locations in the reference list, each with its line of text.
+    fn list_references(&mut self, locations: &[Location]) {
+        let dir = std::env::current_dir().unwrap_or_default();
+        let mut files: HashMap<&Url, Vec<String>> = HashMap::new();
+        let mut lines = Vec::new();
+        let mut entries = Vec::new();
+
+        for location in locations {
+            let path = match location.uri.to_file_path() {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            let text = files.entry(&location.uri).or_insert_with(|| {
+                match self.buffer_visiting(&path) {
+                    Some(buffer) => buffer.borrow().text_full(),
+                    None => fs::read_to_string(&path).unwrap_or_default(),
+                }
+                .lines()
+                .map(|line| line.to_owned())
+                .collect()
+            });
+
+            lines.push(format!(
+                "{}:{}:{}: {}",
+                path.strip_prefix(&dir).unwrap_or(&path).display(),
+                location.range.start.line + 1,
+                location.range.start.character + 1,
+                text.get(location.range.start.line as usize)
+                    .map_or("", |line| line.trim())
+            ));
+            entries.push(Some(ListEntry::Location(location.clone())));
+        }
+
+        if lines.is_empty() {
+            lines.push(String::new());
+            entries.push(None);
+        }
+
+        let buffer = self
+            .find_buffer(REFERENCE_LIST_NAME)
+            .unwrap_or_else(|| Rc::new(RefCell::new(Buffer::with_name(REFERENCE_LIST_NAME))));
+
+        buffer.borrow_mut().set_list(lines, entries);
+        self.clamp_focus_cursor();
+        self.show_buffer(buffer);
+        self.refresh_buffer_list();
+    }
+
+    /// Lists the symbols of the focused buffer in a new frame beside it, or focuses the outline
+    /// if it is already shown.
+    pub fn show_outline(&mut self) {
+        if let Some(frame) = self
+            .outline
+            .as_ref()
+            .and_then(|outline| outline.frame.upgrade())
+        {
+            self.set_focus_frame(&frame);
+            return;
+        }
+
+        let source = self.focus_frame();
+        let buffer = source.borrow().clone_buffer();
+
+        if !source.borrow().can_split(&Direction::Horizontal) {
+            self.message = Some("The frame is too small to split".to_owned());
+            return;
+        }
+
+        let ticket = match self.language_servers.document_symbols(&buffer) {
+            Some(ticket) => ticket,
+            None => {
+                self.message = Some("No language server can do that here".to_owned());
+                return;
+            }
+        };
+
+        self.split(Direction::Horizontal);
+
+        let (source, frame) = source.borrow().clone_inner_frames();
+        let list = self
+            .find_buffer(OUTLINE_NAME)
+            .unwrap_or_else(|| Rc::new(RefCell::new(Buffer::with_name(OUTLINE_NAME))));
+
+        list.borrow_mut().set_list(vec![String::new()], vec![None]);
+        self.set_focus_frame(&frame);
+        self.show_buffer(list);
+        self.refresh_buffer_list();
+
+        let version = buffer.borrow().version();
+
+        self.outline = Some(Outline {
+            frame: Rc::downgrade(&frame),
+            source: Rc::downgrade(&source),
+            buffer: Rc::downgrade(&buffer),
+            version,
+            ticket: Some(ticket),
+            line: 0,
+            symbols: Vec::new(),
+            collapsed: HashSet::new(),
+            paths: Vec::new(),
+        });
+    }
+
+    /// Asks for the symbols again once the source buffer has changed, and moves the source
+    /// frame to the symbol under the cursor of the focused outline. The outline is forgotten
+    /// once either of its frames is gone or shows something else.
+    pub fn update_outline(&mut self) {
+        let mut outline = match self.outline.take() {
+            Some(outline) => outline,
+            None => return,
+        };
+        let (frame, source) = match (outline.frame.upgrade(), outline.source.upgrade()) {
+            (Some(frame), Some(source))
+                if [&frame, &source].iter().all(|frame| {
+                    frame.borrow().is_shown() && !frame.borrow().has_inner_frames()
+                }) && frame.borrow().clone_buffer().borrow().name() == OUTLINE_NAME =>
+            {
+                (frame, source)
+            }
+            _ => return,
+        };
+        let buffer = source.borrow().clone_buffer();
+        let version = buffer.borrow().version();
+
+        if outline.ticket.is_none()
+            && (!outline.buffer.ptr_eq(&Rc::downgrade(&buffer)) || outline.version != version)
+        {
+            outline.buffer = Rc::downgrade(&buffer);
+            outline.version = version;
+            outline.ticket = self.language_servers.document_symbols(&buffer);
+
+            if outline.ticket.is_none() {
+                frame
+                    .borrow()
+                    .clone_buffer()
+                    .borrow_mut()
+                    .set_list(vec![String::new()], vec![None]);
+            }
+        }
+
+        let line = frame.borrow().cursor().1;
+
+        if line != outline.line {
+            outline.line = line;
+
+            let entry = frame.borrow().clone_buffer().borrow().entry(line).cloned();
+
+            if let Some(ListEntry::Location(location)) = entry {
+                if frame.borrow().has_focus() && outline.buffer.ptr_eq(&Rc::downgrade(&buffer)) {
+                    let cursor = buffer.borrow().cursor_of_position(location.range.start);
+
+                    source.borrow_mut().move_to(cursor);
+                }
+            }
+        }
+
+        self.outline = Some(outline);
+    }
+
+    fn set_outline(&mut self, ticket: Ticket, symbols: Vec<Symbol>) {
+        match &mut self.outline {
+            Some(outline) if outline.ticket == Some(ticket) => {
+                outline.ticket = None;
+                outline.symbols = symbols;
+            }
+            _ => return,
+        }
+
+        self.refresh_outline();
+    }
+
+    /// Lists the symbols of the outline as a tree, leaving out the ones inside collapsed ones.
+    fn refresh_outline(&mut self) {
+        let list = match self.find_buffer(OUTLINE_NAME) {
+            Some(list) => list,
+            None => return,
+        };
+        let outline = match &mut self.outline {
+            Some(outline) => outline,
+            None => return,
+        };
+        let uri = match outline
+            .buffer
+            .upgrade()
+            .and_then(|buffer| Url::from_file_path(buffer.borrow().path()?).ok())
+        {
+            Some(uri) => uri,
+            None => return,
+        };
+        let mut lines = Vec::new();
+        let mut entries = Vec::new();
+
+        outline.paths.clear();
+        outline_lines(
+            &outline.symbols,
+            &mut Vec::new(),
+            &outline.collapsed,
+            &uri,
+            &mut lines,
+            &mut entries,
+            &mut outline.paths,
+        );
+
+        if lines.is_empty() {
+            lines.push(String::new());
+            entries.push(None);
+        }
+
+        list.borrow_mut().set_list(lines, entries);
+        self.clamp_focus_cursor();
+    }
+
+    /// Collapses or expands the symbol under the cursor of the outline, if it is focused.
+    fn toggle_outline_symbol(&mut self) -> bool {
+        let frame = self.focus_frame();
+        let outline = match &mut self.outline {
+            Some(outline) if outline.frame.ptr_eq(&Rc::downgrade(&frame)) => outline,
+            _ => return false,
+        };
+
+        if let Some(path) = outline.paths.get(frame.borrow().cursor().1) {
+            if !outline.collapsed.remove(path) {
+                outline.collapsed.insert(path.clone());
+            }
+        }
+
+        self.refresh_outline();
+        true
+    }
+
+    /// Folds the syntax node around the cursor, or collapses the symbol under it in the outline.
+    pub fn toggle_fold(&mut self) {
+        if !self.toggle_outline_symbol() {
+            self.focus_frame().borrow_mut().toggle_fold();
+        }
+    }
+
+    /// Asks for the symbols matching the input of the symbol prompt once it has changed and
+    /// the symbols asked for before have come. The search ends with the prompt.
+    pub fn update_symbol_search(&mut self) {
+        let input = match &self.prompt {
+            Some(prompt) if prompt.kind() == PromptKind::WorkspaceSymbol => prompt.input(),
+            _ => {
+                self.symbol_search = None;
+                return;
+            }
+        };
+
+        if let Some(search) = &mut self.symbol_search {
+            if search.ticket.is_none() && search.query != input {
+                search.query = input.to_owned();
+                search.ticket = self
+                    .language_servers
+                    .workspace_symbols(&search.buffer, input);
+            }
+        }
+    }
+
+    fn set_workspace_symbols(&mut self, ticket: Ticket, symbols: Vec<SymbolInformation>) {
+        let search = match &mut self.symbol_search {
+            Some(search) if search.ticket == Some(ticket) => search,
+            _ => return,
+        };
+        let dir = std::env::current_dir().unwrap_or_default();
+
+        search.ticket = None;
+        search.symbols = symbols
+            .into_iter()
+            .filter_map(|symbol| {
+                let path = symbol.location.uri.to_file_path().ok()?;
+                let candidate = format!(
+                    "{} {} {}:{}",
+                    symbol.name,
+                    symbol_kind_name(symbol.kind),
+                    path.strip_prefix(&dir).unwrap_or(&path).display(),
+                    symbol.location.range.start.line + 1
+                );
+
+                Some((candidate, symbol.location))
+            })
+            .collect();
+
+        if let Some(prompt) = &mut self.prompt {
+            prompt.set_candidates(
+                search
+                    .symbols
+                    .iter()
+                    .map(|(candidate, _)| candidate.clone())
+                    .collect(),
+            );
+        }
+    }
+
+    /// Ends the undo steps of every buffer, so that what has been edited since the last call
+    /// is undone at once.
+    pub fn seal_undo(&mut self) {
+        let sealed: Vec<_> = self
+            .buffers
+            .iter()
+            .filter(|buffer| buffer.borrow_mut().seal_undo(self.undo_id))
+            .map(Rc::downgrade)
+            .collect();
+
+        if sealed.len() > 1 {
+            self.undo_groups.insert(self.undo_id, sealed);
+        }
+
+        self.undo_id += 1;
+    }
+
+    /// Reverts the last edits of the focused buffer, together with the ones made to other
+    /// buffers at the same time. Refuses if any of those has been edited since.
+    pub fn undo(&mut self) {
+        self.seal_undo();
+
+        let frame = self.focus_frame();
+        let buffer = frame.borrow().clone_buffer();
+        let id = match buffer.borrow().undo_id() {
+            Some(id) if !buffer.borrow().is_read_only() => id,
+            _ => {
+                self.message = Some("Nothing to undo".to_owned());
+                return;
+            }
+        };
+
+        let group: Vec<_> = self
+            .undo_groups
+            .get(&id)
+            .map(|group| group.iter().filter_map(Weak::upgrade).collect())
+            .unwrap_or_default();
+
+        if let Some(edited) = group
+            .iter()
+            .find(|other| other.borrow().undo_id() != Some(id))
+        {
+            self.message = Some(format!(
+                "{} has been edited since, undo that first",
+                edited.borrow().name()
+            ));
+            return;
+        }
+
+        self.undo_groups.remove(&id);
+
+        for other in &group {
+            if !Rc::ptr_eq(other, &buffer) {
+                other.borrow_mut().undo();
+            }
+        }
+
+        let cursor = buffer.borrow_mut().undo();
+
+        if let Some(cursor) = cursor {
+            frame.borrow_mut().clear_region();
+            frame.borrow_mut().move_to(cursor);
+        }
+    }
+
+    /// Makes edits to buffers as one undo step, keeping the cursor of the focused frame where
+    /// it is in the text.
+    fn edit_buffers(&mut self, edit: impl FnOnce(&mut Self)) {
+        let frame = self.focus_frame();
+        let cursor = frame
+            .borrow()
+            .clone_buffer()
+            .borrow_mut()
+            .create_marker(frame.borrow().cursor());
+
+        self.seal_undo();
+        edit(self);
+        self.seal_undo();
+        frame.borrow_mut().move_to(cursor.get());
+    }
+
+    /// Makes the changes of a workspace edit, opening the files which have no buffers yet.
+    pub fn apply_workspace_edit(&mut self, edit: WorkspaceEdit) {
+        let text_edits = |edits: Vec<OneOf<TextEdit, AnnotatedTextEdit>>| -> Vec<TextEdit> {
+            edits
+                .into_iter()
+                .map(|edit| match edit {
+                    OneOf::Left(edit) => edit,
+                    OneOf::Right(edit) => edit.text_edit,
+                })
+                .collect()
+        };
+        let changes: Vec<_> = match edit.document_changes {
+            Some(DocumentChanges::Edits(edits)) => edits
+                .into_iter()
+                .map(|edit| (edit.text_document.uri, text_edits(edit.edits)))
+                .collect(),
+            Some(DocumentChanges::Operations(operations)) => operations
+                .into_iter()
+                .filter_map(|operation| match operation {
+                    DocumentChangeOperation::Edit(edit) => {
+                        Some((edit.text_document.uri, text_edits(edit.edits)))
+                    }
+                    DocumentChangeOperation::Op(operation) => {
+                        warn!("unsupported resource operation {:?}", operation);
+                        None
+                    }
+                })
+                .collect(),
+            None => edit.changes.into_iter().flatten().collect(),
+        };
+
+        self.edit_buffers(|window: &mut Window| {
+            for (uri, edits) in changes {
+                let path = match uri.to_file_path() {
+                    Ok(path) => path,
+                    Err(_) => continue,
+                };
+                let buffer = match window.open(&path) {
+                    Some(buffer) => buffer,
+                    None => continue,
+                };
+
+                if buffer.borrow().is_read_only() {
+                    continue;
+                }
+
+                buffer.borrow_mut().apply_edits(&edits);
+            }
+        });
+
+        self.refresh_buffer_list();
+    }
+
+    /// Formats the focused buffer with its language server.
+    pub fn format_buffer(&mut self) {
+        self.request_format(false);
+    }
+
+    fn request_format(&mut self, save: bool) -> bool {
+        let buffer = self.focus_frame().borrow().clone_buffer();
+        let ticket = self.language_servers.formatting(&buffer, TAB.len() as u32);
+
+        let version = buffer.borrow().version();
+
+        self.format = ticket.map(|ticket| PendingFormat {
+            ticket,
+            version,
+            buffer,
+            save,
+        });
+
+        if self.format.is_none() && !save {
+            self.message = Some("No language server can do that here".to_owned());
+        }
+
+        self.format.is_some()
+    }
+
+    /// Writes the focused buffer to its file, formatted first if `format_on_save` is set.
+    pub fn save_buffer(&mut self) {
+        if self.config.format_on_save && self.request_format(true) {
+            return;
+        }
+
+        self.save(&self.focus_frame().borrow().clone_buffer());
+    }
+
+    fn save(&mut self, buffer: &Rc<RefCell<Buffer>>) {
+        let mut buffer = buffer.borrow_mut();
+
+        self.message = Some(match buffer.save() {
+            Ok(()) => format!("Saved {}", buffer.name()),
+            Err(e) => format!("Failed to save {}: {}", buffer.name(), e),
+        });
+    }
+
+    /// Asks for the code actions of the region, or of the cursor if there is none, to show them
+    /// in a menu.
+    pub fn request_code_actions(&mut self) {
+        let frame = self.focus_frame();
+        let buffer = frame.borrow().clone_buffer();
+        let cursor = frame.borrow().cursor();
+        let (start, end) = frame.borrow().region().unwrap_or((cursor, cursor));
+        // The mark stays where it was set while the buffer is edited in other frames.
+        let (start, end) = (buffer.borrow().clamp(start), buffer.borrow().clamp(end));
+        let (start_position, end_position) = (
+            buffer.borrow().lsp_position(start),
+            buffer.borrow().lsp_position(end),
+        );
+        let diagnostics = buffer
+            .borrow()
+            .path()
+            .and_then(|path| Url::from_file_path(path).ok())
+            .and_then(|uri| self.diagnostics.get(&uri))
+            .into_iter()
+            .flatten()
+            .filter(|diagnostic| {
+                diagnostic.range.start <= end_position && diagnostic.range.end >= start_position
+            })
+            .cloned()
+            .collect();
+
+        self.request(|servers, buffer, _| servers.code_actions(buffer, (start, end), diagnostics));
+    }
+
+    /// The titles of the code actions in the menu with the index of the selected one.
+    pub fn code_actions(&self) -> Option<(Vec<&str>, usize)> {
+        self.code_actions.as_ref().map(|menu| {
+            let titles = menu
+                .actions
+                .iter()
+                .map(|action| match action {
+                    CodeActionOrCommand::Command(command) => command.title.as_str(),
+                    CodeActionOrCommand::CodeAction(action) => action.title.as_str(),
+                })
+                .collect();
+
+            (titles, menu.selected)
+        })
+    }
+
+    pub fn select_code_action(&mut self, forward: bool) {
+        if let Some(menu) = &mut self.code_actions {
+            let len = menu.actions.len();
+
+            menu.selected = if forward {
+                (menu.selected + 1) % len
+            } else {
+                (menu.selected + len - 1) % len
+            };
+        }
+    }
+
+    /// Makes the edit of the selected code action and runs its command, closing the menu.
+    pub fn accept_code_action(&mut self) {
+        let mut menu = match self.code_actions.take() {
+            Some(menu) => menu,
+            None => return,
+        };
+        let buffer = self.focus_frame().borrow().clone_buffer();
+        let (edit, command) = match menu.actions.swap_remove(menu.selected) {
+            CodeActionOrCommand::Command(command) => (None, Some(command)),
+            CodeActionOrCommand::CodeAction(action) => {
+                if let Some(disabled) = action.disabled {
+                    self.message = Some(disabled.reason);
+                    return;
+                }
+
+                (action.edit, action.command)
+            }
+        };
+
+        if let Some(edit) = edit {
+            self.apply_workspace_edit(edit);
+        }
+
+        if let Some(command) = command {
+            self.language_servers.execute_command(&buffer, command);
+        }
+    }
+
+    pub fn cancel_code_actions(&mut self) {
+        self.code_actions = None;
+    }
+
+    pub fn completion(&self) -> Option<&Completion> {
+        self.completion.as_ref().map(|(_, completion)| completion)
+    }
+
+    /// Opens the completion popup for the word before the cursor, with the words of every
+    /// buffer and what the language server offers, if any. A character which triggered it is
+    /// given to the server.
+    pub fn start_completion(&mut self, trigger: Option<char>) {
+        let frame = self.focus_frame();
+        let buffer = frame.borrow().clone_buffer();
+        let cursor = frame.borrow().cursor();
+
+        if buffer.borrow().is_read_only() {
+            return;
+        }
+
+        let start = buffer.borrow().word_start(cursor);
+        let current = buffer.borrow().line(cursor.1)[start.0..].to_owned();
+        let current: String = current.chars().take_while(|c| is_word_char(*c)).collect();
+        let mut words = HashSet::new();
+
+        for buffer in &self.buffers {
+            words.extend(
+                buffer
+                    .borrow()
+                    .words()
+                    .filter(|word| {
+                        *word != current && !word.starts_with(|c: char| c.is_ascii_digit())
+                    })
+                    .map(|word| word.to_owned()),
+            );
+        }
+
+        let ticket = self.language_servers.completion(&buffer, cursor, trigger);
+        let mut completion = Completion::new(start, cursor, words.into_iter().collect(), ticket);
+        let pattern = buffer.borrow().text_between(start, cursor);
+
+        completion.filter(&pattern);
+
+        if completion.has_matches() || ticket.is_some() {
+            self.completion = Some((frame, completion));
+        }
+    }
+
+    /// Filters the completions by the word before the cursor as it is typed, asking the server
+    /// again if it has said that its items are incomplete. The popup closes once the cursor
+    /// leaves the word, another frame gets the focus or nothing matches.
+    pub fn update_completion(&mut self) {
+        let (frame, start) = match &self.completion {
+            Some((frame, completion)) => (frame.clone(), completion.start()),
+            None => return,
+        };
+        let buffer = frame.borrow().clone_buffer();
+        let cursor = frame.borrow().cursor();
+        let pattern = if Rc::ptr_eq(&frame, &self.focus_frame())
+            && cursor.1 == start.1
+            && cursor.0 >= start.0
+            && cursor.0 <= buffer.borrow().line_len(cursor)
+        {
+            Some(buffer.borrow().text_between(start, cursor))
+        } else {
+            None
+        };
+
+        match (pattern, &mut self.completion) {
+            (Some(pattern), Some((_, completion))) if pattern.chars().all(is_word_char) => {
+                if pattern == completion.pattern() {
+                    return;
+                }
+
+                if completion.is_incomplete() && completion.ticket().is_none() {
+                    if let Some(ticket) = self.language_servers.completion(&buffer, cursor, None) {
+                        completion.request_again(cursor, ticket);
+                    }
+                }
+
+                completion.filter(&pattern);
+
+                if !completion.has_matches() && completion.ticket().is_none() {
+                    self.completion = None;
+                }
+            }
+            _ => self.completion = None,
+        }
+    }
+
+    pub fn select_completion(&mut self, forward: bool) {
+        if let Some((_, completion)) = &mut self.completion {
+            completion.select(forward);
+        }
+    }
+
+    /// Inserts the selected completion and closes the popup.
+    pub fn accept_completion(&mut self) {
+        if let Some((frame, completion)) = self.completion.take() {
+            let buffer = frame.borrow().clone_buffer();
+            let cursor = frame.borrow().cursor();
+            let accepted = completion.accept(&mut buffer.borrow_mut(), cursor);
+
+            if let Some(cursor) = accepted {
+                frame.borrow_mut().clear_region();
+                frame.borrow_mut().move_to(cursor);
+            }
+        }
+    }
+
+    pub fn cancel_completion(&mut self) {
+        self.completion = None;
+    }
+
+    /// Types the character, opening the completion popup or asking for signature help if the
+    /// language server wants it to.
+    pub fn new_char(&mut self, c: char) {
+        let frame = self.focus_frame();
+
+        frame.borrow_mut().new_char(c);
+
+        let buffer = frame.borrow().clone_buffer();
+
+        if self.completion.is_none()
+            && self
+                .language_servers
+                .is_trigger_character(&buffer.borrow(), c)
+        {
+            self.start_completion(Some(c));
+        }
+
+        if self
+            .language_servers
+            .is_signature_trigger(&buffer.borrow(), c)
+        {
+            self.request_signature_help(Some(c));
+        }
+    }
+
+    /// The label of the active signature and the range of its active parameter in it.
+    pub fn signature_help(&self) -> Option<(&str, Option<std::ops::Range<usize>>)> {
+        self.signature
+            .as_ref()
+            .and_then(|signature| signature.help.as_ref())
+            .and_then(active_signature)
+    }
+
+    /// Asks for the signature of the call at the cursor, keeping the help shown until the
+    /// answer comes.
+    fn request_signature_help(&mut self, trigger: Option<char>) {
+        let frame = self.focus_frame();
+        let buffer = frame.borrow().clone_buffer();
+        let cursor = frame.borrow().cursor();
+        let shown = self
+            .signature
+            .take()
+            .filter(|signature| Rc::ptr_eq(&signature.frame, &frame))
+            .and_then(|signature| signature.help);
+        let ticket = self
+            .language_servers
+            .signature_help(&buffer, cursor, trigger, shown.clone());
+
+        if ticket.is_some() {
+            self.signature = Some(SignaturePopup {
+                frame,
+                ticket,
+                help: shown,
+                cursor,
+                version: buffer.borrow().version(),
+            });
+        }
+    }
+
+    /// Asks for the signature help again once the cursor moves or the text changes. It closes
+    /// when the cursor leaves the line or another frame gets the focus.
+    pub fn update_signature_help(&mut self) {
+        let signature = match &self.signature {
+            Some(signature) => signature,
+            None => return,
+        };
+
+        if !Rc::ptr_eq(&signature.frame, &self.focus_frame()) {
+            self.signature = None;
+            return;
+        }
+
+        let cursor = signature.frame.borrow().cursor();
+        let version = signature.frame.borrow().clone_buffer().borrow().version();
+
+        if cursor.1 != signature.cursor.1 {
+            self.signature = None;
+        } else if signature.ticket.is_none()
+            && (cursor != signature.cursor || version != signature.version)
+        {
+            self.request_signature_help(None);
+        }
+    }
+
+    pub fn cancel_signature_help(&mut self) {
+        self.signature = None;
+    }
+
+    pub async fn shutdown_language_servers(&mut self) {
+        self.language_servers.shutdown().await;
+    }
+
+    pub fn add_themes(&mut self, themes: Vec<Theme>) {
+        self.themes.extend(themes);
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.themes[self.theme]
+    }
+
+    pub fn next_theme(&mut self) {
+        self.theme = (self.theme + 1) % self.themes.len();
+        debug!("theme: {}", self.theme().name());
+    }
+
+    pub fn move_focus(&self) {
+        if !self.main_frame().borrow_mut().move_focus() {
+            self.main_frame().borrow_mut().clear_focus();
+            self.main_frame().borrow_mut().set_focus();
+        }
+    }
+
+    fn set_focus_frame(&self, frame: &Rc<RefCell<Frame>>) {
+        self.main_frame().borrow_mut().clear_focus();
+        frame.borrow_mut().set_focus();
+    }
+
+    /// Moves the focus to the frame next to the focused one on the given side.
+    pub fn move_focus_to(&mut self, side: Side) {
+        self.workspace_mut().zoomed = false;
+
+        if let Some(frame) = self.frame_on(side) {
+            self.set_focus_frame(&frame);
+        }
+    }
+
+    /// Swaps the buffer of the focused frame with the one of the frame on the given side. The
+    /// focus goes along with the buffer.
+    pub fn swap_frame(&mut self, side: Side) {
+        self.workspace_mut().zoomed = false;
+
+        if let Some(frame) = self.frame_on(side) {
+            Frame::swap_buffers(&self.focus_frame(), &frame);
+            self.set_focus_frame(&frame);
+        }
+    }
+
+    /// Moves every buffer to the next frame, the last one to the first. The focus stays in place.
+    pub fn rotate_frames(&self) {
+        let leaves = Frame::leaves(&self.main_frame());
+
+        for frame in leaves.iter().skip(1) {
+            Frame::swap_buffers(&leaves[0], frame);
+        }
+    }
+
+    pub fn flip_split(&mut self) {
+        self.workspace_mut().zoomed = false;
+        self.main_frame().borrow_mut().flip_focus_split();
+    }
+
+    /// The frame next to the focused one on the given side, picking the closest one and, among
+    /// those, the one level with the cursor.
+    fn frame_on(&self, side: Side) -> Option<Rc<RefCell<Frame>>> {
+        let focus = self.focus_frame();
+        let from = focus.borrow().rect();
+        let (cursor_x, cursor_y) = focus.borrow().screen_cursor();
+
+        Frame::leaves(&self.main_frame())
+            .into_iter()
+            .filter(|frame| !Rc::ptr_eq(frame, &focus))
+            .filter_map(|frame| {
+                let to = frame.borrow().rect();
+                let (distance, overlaps, level) = match side {
+                    Side::Left => (
+                        from.left() as i32 - to.right() as i32,
+                        overlap(from.top(), from.bottom(), to.top(), to.bottom()),
+                        (to.top()..to.bottom()).contains(&cursor_y),
+                    ),
+                    Side::Right => (
+                        to.left() as i32 - from.right() as i32,
+                        overlap(from.top(), from.bottom(), to.top(), to.bottom()),
+                        (to.top()..to.bottom()).contains(&cursor_y),
+                    ),
+                    Side::Up => (
+                        from.top() as i32 - to.bottom() as i32,
+                        overlap(from.left(), from.right(), to.left(), to.right()),
+                        (to.left()..to.right()).contains(&cursor_x),
+                    ),
+                    Side::Down => (
+                        to.top() as i32 - from.bottom() as i32,
+                        overlap(from.left(), from.right(), to.left(), to.right()),
+                        (to.left()..to.right()).contains(&cursor_x),
+                    ),
+                };
+
+                if distance >= 0 && overlaps > 0 {
+                    Some((distance, !level, frame))
+                } else {
+                    None
+                }
+            })
+            .min_by_key(|(distance, not_level, _)| (*distance, *not_level))
+            .map(|(_, _, frame)| frame)
+    }
+
+    /// Shows a number on every frame until the next key picks one with `focus_number`.
+    pub fn start_selecting_frame(&mut self) {
+        self.workspace_mut().zoomed = false;
+        self.selecting_frame = true;
+    }
+
+    pub fn stop_selecting_frame(&mut self) {
+        self.selecting_frame = false;
+    }
+
+    pub fn is_selecting_frame(&self) -> bool {
+        self.selecting_frame
+    }
+
+    /// Focuses the frame labeled with the number (counting from 1) and stops showing labels.
+    pub fn focus_number(&mut self, number: usize) {
+        self.selecting_frame = false;
+
+        if let Some(frame) = Frame::leaves(&self.main_frame()).get(number.saturating_sub(1)) {
+            self.set_focus_frame(frame);
+        }
+    }
 
-        main_frame.borrow_mut().set_focus();
+    /// Splits the focused frame unless the halves would be smaller than the minimum.
+    pub fn split(&mut self, direction: Direction) {
+        let frame = self.focus_frame();
 
-        Window {
-            main_frame,
-            detached_buffer: Vec::new(),
-            yank: None,
+        if !frame.borrow().can_split(&direction) {
+            debug!("frame too small to split: {:?}", frame.borrow().rect());
+            return;
         }
+
+        self.workspace_mut().zoomed = false;
+        frame.borrow_mut().split(direction);
     }
 
-    pub fn move_focus(&self) {
-        if !self.main_frame.borrow_mut().move_focus() {
-            self.main_frame.borrow_mut().clear_focus();
-            self.main_frame.borrow_mut().set_focus();
+    /// Lets the focused frame fill the whole window, or shows the split frames again. The tree
+    /// is kept as it is, so the zoom follows the focus while it lasts.
+    pub fn toggle_zoom(&mut self) {
+        let workspace = self.workspace_mut();
+        workspace.zoomed = !workspace.zoomed && workspace.main_frame.borrow().has_inner_frames();
+    }
+
+    /// The frame drawn on the whole window: the focused one while zoomed, the main one otherwise.
+    pub fn shown_frame(&self) -> Rc<RefCell<Frame>> {
+        if self.workspace().zoomed {
+            self.focus_frame()
+        } else {
+            self.main_frame()
         }
     }
 
+    pub fn resize_focus_frame(&mut self, direction: Direction, delta: i16) {
+        self.workspace_mut().zoomed = false;
+        self.main_frame()
+            .borrow_mut()
+            .resize_focus(direction, delta);
+    }
+
+    pub fn balance(&mut self) {
+        self.workspace_mut().zoomed = false;
+        self.main_frame().borrow_mut().balance();
+    }
+
     pub fn kill(&mut self) {
         let frame = self.focus_frame();
         self.yank = frame.borrow_mut().kill();
@@ -43,28 +1544,1034 @@ impl Window {
     }
 
     pub fn remove_focus_frame(&mut self) {
+        self.workspace_mut().zoomed = false;
+
         let frame = self.focus_frame();
         if !frame.borrow().is_main_frame() {
-            let buffer = frame.borrow_mut().release_buffer();
+            frame.borrow_mut().release_buffer();
             self.main_frame().borrow_mut().clean_removed_frame();
-            self.detached_buffer.push(buffer);
         }
     }
 
     pub fn main_frame(&self) -> Rc<RefCell<Frame>> {
-        self.main_frame.clone()
+        self.workspace().main_frame.clone()
+    }
+
+    fn workspace(&self) -> &Workspace {
+        &self.workspaces[self.workspace]
+    }
+
+    fn workspace_mut(&mut self) -> &mut Workspace {
+        &mut self.workspaces[self.workspace]
+    }
+
+    /// Presses the left button: a tab switches to it, a border between frames starts dragging it
+    /// and a frame gets the focus with the cursor put under the mouse.
+    pub fn mouse_down(&mut self, x: u16, y: u16) {
+        self.drag = None;
+
+        if let Some(idx) = self.tab_at(x, y) {
+            self.switch_workspace(idx);
+        } else if let Some(split) = Frame::split_at(&self.shown_frame(), x, y) {
+            self.drag = Some(Drag::Border(split));
+        } else if let Some(frame) = self.frame_at(x, y) {
+            if !frame.borrow().has_focus() {
+                self.set_focus_frame(&frame);
+            }
+
+            frame.borrow_mut().click(x, y);
+            self.drag = Some(Drag::Region);
+        }
+    }
+
+    pub fn mouse_drag(&mut self, x: u16, y: u16) {
+        match self.drag {
+            Some(Drag::Border(ref split)) => split.borrow_mut().move_border(x, y),
+            Some(Drag::Region) => self.focus_frame().borrow_mut().drag(x, y),
+            None => {}
+        }
+    }
+
+    pub fn mouse_up(&mut self) {
+        self.drag = None;
+    }
+
+    /// Scrolls the frame under the mouse, whether it has the focus or not.
+    pub fn scroll(&self, x: u16, y: u16, down: bool) {
+        if let Some(frame) = self.frame_at(x, y) {
+            frame.borrow_mut().scroll(SCROLL_ROWS, down);
+        }
+    }
+
+    fn frame_at(&self, x: u16, y: u16) -> Option<Rc<RefCell<Frame>>> {
+        Frame::leaves(&self.shown_frame())
+            .into_iter()
+            .find(|frame| {
+                let r = frame.borrow().rect();
+                (r.left()..r.right()).contains(&x) && (r.top()..r.bottom()).contains(&y)
+            })
+    }
+
+    /// The tab at the point of the tab bar, which is on the top row when there are several tabs.
+    fn tab_at(&self, x: u16, y: u16) -> Option<usize> {
+        if self.workspaces.len() < 2 || y != 0 {
+            return None;
+        }
+
+        let mut left = 0;
+
+        for (idx, workspace) in self.workspaces.iter().enumerate() {
+            let width = workspace.name.chars().count() + 2;
+
+            if (left..left + width).contains(&(x as usize)) {
+                return Some(idx);
+            }
+
+            left += width;
+        }
+
+        None
+    }
+
+    /// Opens a tab showing the focused buffer and switches to it.
+    pub fn new_workspace(&mut self) {
+        let buffer = self.focus_frame().borrow().clone_buffer();
+        let name = (1..)
+            .map(|n| n.to_string())
+            .find(|name| self.workspaces.iter().all(|w| w.name != *name))
+            .unwrap();
+
+        self.leave_workspace();
+        self.workspaces
+            .insert(self.workspace + 1, Workspace::new(name, buffer));
+        self.workspace += 1;
+    }
+
+    /// Closes the shown tab unless it is the last one.
+    pub fn close_workspace(&mut self) {
+        if self.workspaces.len() > 1 {
+            self.workspaces.remove(self.workspace);
+            self.workspace = self.workspace.min(self.workspaces.len() - 1);
+            self.enter_workspace();
+        }
+    }
+
+    pub fn next_workspace(&mut self, forward: bool) {
+        let len = self.workspaces.len();
+
+        self.switch_workspace(if forward {
+            (self.workspace + 1) % len
+        } else {
+            (self.workspace + len - 1) % len
+        });
+    }
+
+    fn switch_workspace(&mut self, idx: usize) {
+        self.leave_workspace();
+        self.workspace = idx;
+        self.enter_workspace();
+    }
+
+    pub fn rename_workspace(&mut self, name: &str) {
+        self.workspace_mut().name = name.to_owned();
+    }
+
+    /// The names of the tabs and the index of the shown one.
+    pub fn workspaces(&self) -> (Vec<&str>, usize) {
+        (
+            self.workspaces.iter().map(|w| w.name.as_str()).collect(),
+            self.workspace,
+        )
+    }
+
+    /// Unfocuses the frames of the tab being hidden, so that they follow edits made from other
+    /// tabs, and remembers which one had the focus.
+    fn leave_workspace(&mut self) {
+        let main_frame = self.main_frame();
+
+        self.workspace_mut().focus = Frame::leaves(&main_frame)
+            .iter()
+            .position(|frame| frame.borrow().has_focus())
+            .unwrap_or(0);
+        main_frame.borrow_mut().clear_focus();
+    }
+
+    fn enter_workspace(&mut self) {
+        let leaves = Frame::leaves(&self.main_frame());
+
+        self.set_focus_frame(leaves.get(self.workspace().focus).unwrap_or(&leaves[0]));
     }
 
     pub fn focus_frame(&self) -> Rc<RefCell<Frame>> {
-        if self.main_frame.borrow().has_inner_frames() {
-            self.main_frame.borrow().focus_child_frame()
+        if self.main_frame().borrow().has_inner_frames() {
+            self.main_frame().borrow().focus_child_frame()
+        } else {
+            self.main_frame()
+        }
+    }
+
+    fn find_buffer(&self, name: &str) -> Option<Rc<RefCell<Buffer>>> {
+        self.buffers
+            .iter()
+            .find(|buffer| buffer.borrow().name() == name)
+            .cloned()
+    }
+
+    /// Shows the buffer in the focused frame and makes it the most recent one.
+    fn show_buffer(&mut self, buffer: Rc<RefCell<Buffer>>) {
+        let frame = self.focus_frame();
+
+        if !Rc::ptr_eq(&frame.borrow().clone_buffer(), &buffer) {
+            frame.borrow_mut().replace_buffer(buffer.clone());
+        }
+
+        self.buffers.retain(|b| !Rc::ptr_eq(b, &buffer));
+        self.buffers.insert(0, buffer);
+    }
+
+    /// Shows the buffer with the name in the focused frame, creating it if there is none.
+    pub fn switch_to_buffer(&mut self, name: &str) {
+        let buffer = self
+            .find_buffer(name)
+            .unwrap_or_else(|| Rc::new(RefCell::new(Buffer::with_name(name))));
+
+        self.show_buffer(buffer);
+        self.refresh_buffer_list();
+    }
+
+    pub fn session(&self) -> Session {
+        let workspaces = self
+            .workspaces
+            .iter()
+            .enumerate()
+            .map(|(idx, workspace)| {
+                let focus = if idx == self.workspace {
+                    Frame::leaves(&workspace.main_frame)
+                        .iter()
+                        .position(|frame| frame.borrow().has_focus())
+                        .unwrap_or(0)
+                } else {
+                    workspace.focus
+                };
+
+                WorkspaceState {
+                    name: workspace.name.clone(),
+                    frame: workspace.main_frame.borrow().state(),
+                    focus,
+                }
+            })
+            .collect();
+
+        Session {
+            buffers: self
+                .buffers
+                .iter()
+                .map(|buffer| BufferState {
+                    name: buffer.borrow().name().to_owned(),
+                    path: buffer.borrow().path().map(|path| path.to_owned()),
+                })
+                .collect(),
+            workspaces,
+            workspace: self.workspace,
+        }
+    }
+
+    /// Replaces the buffers and the frames with the ones of the session.
+    pub fn restore_session(&mut self, session: &Session) {
+        self.buffers = session
+            .buffers
+            .iter()
+            .filter_map(|state| {
+                let mut buffer = match state.path {
+                    Some(ref path) => Buffer::open(path).unwrap_or_else(|e| {
+                        warn!("failed to open {:?}: {}", path, e);
+                        Buffer::with_name(&state.name)
+                    }),
+                    None => {
+                        info!("not restoring {}, which visits no file", state.name);
+                        return None;
+                    }
+                };
+
+                buffer.set_name(&state.name);
+                Some(Rc::new(RefCell::new(buffer)))
+            })
+            .collect();
+
+        if self.buffers.is_empty() {
+            self.buffers.push(Rc::new(RefCell::new(Buffer::new())));
+        }
+
+        let buffers = self.buffers.clone();
+        let buffer = |name: &str| {
+            buffers
+                .iter()
+                .find(|buffer| buffer.borrow().name() == name)
+                .unwrap_or(&buffers[0])
+                .clone()
+        };
+
+        self.workspaces = session
+            .workspaces
+            .iter()
+            .map(|state| Workspace {
+                name: state.name.clone(),
+                main_frame: Rc::new(RefCell::new(Frame::from_state(&state.frame, &buffer))),
+                zoomed: false,
+                focus: state.focus,
+            })
+            .collect();
+
+        if self.workspaces.is_empty() {
+            self.workspaces
+                .push(Workspace::new("1".to_owned(), self.buffers[0].clone()));
+        }
+
+        self.workspace = session.workspace.min(self.workspaces.len() - 1);
+        self.enter_workspace();
+        self.refresh_buffer_list();
+    }
+
+    /// Shows the buffer visiting the file in the focused frame, opening it if there is none.
+    pub fn find_file(&mut self, path: &Path) {
+        if let Some(buffer) = self.open(path) {
+            self.show_buffer(buffer);
+            self.refresh_buffer_list();
+        }
+    }
+
+    /// The buffer visiting the file, opened as the least recent one if there is none yet.
+    fn open(&mut self, path: &Path) -> Option<Rc<RefCell<Buffer>>> {
+        let path = match std::path::absolute(path) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("failed to open {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        if let Some(buffer) = self.buffer_visiting(&path) {
+            return Some(buffer);
+        }
+
+        match Buffer::open(&path) {
+            Ok(mut buffer) => {
+                let name = self.unique_name(buffer.name());
+                let diagnostics = Url::from_file_path(&path)
+                    .ok()
+                    .and_then(|uri| self.diagnostics.get(&uri));
+
+                buffer.set_name(&name);
+
+                if let Some(diagnostics) = diagnostics {
+                    buffer.set_diagnostics(diagnostics);
+                }
+
+                let buffer = Rc::new(RefCell::new(buffer));
+
+                self.buffers.push(buffer.clone());
+                Some(buffer)
+            }
+            Err(e) => {
+                warn!("failed to open {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn buffer_visiting(&self, path: &Path) -> Option<Rc<RefCell<Buffer>>> {
+        self.buffers
+            .iter()
+            .find(|buffer| buffer.borrow().path() == Some(path))
+            .cloned()
+    }
+
+    /// Shows the file in the focused frame with the cursor at the start of the range.
+    fn open_location(&mut self, location: &Location) {
+        let path = match location.uri.to_file_path() {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+
+        self.find_file(&path);
+
+        let frame = self.focus_frame();
+        let cursor = frame
+            .borrow()
+            .clone_buffer()
+            .borrow()
+            .cursor_of_position(location.range.start);
+
+        frame.borrow_mut().move_to(cursor);
+    }
+
+    /// The name, or the name followed by the smallest number like `<2>` no buffer has yet.
+    fn unique_name(&self, name: &str) -> String {
+        (1..)
+            .map(|n| match n {
+                1 => name.to_owned(),
+                n => format!("{}<{}>", name, n),
+            })
+            .find(|name| self.find_buffer(name).is_none())
+            .unwrap()
+    }
+
+    /// Kills the buffer, asking first if it has changes which are not saved.
+    fn confirm_kill(&mut self, name: &str) {
+        let modified = self
+            .find_buffer(name)
+            .is_some_and(|buffer| buffer.borrow().is_modified());
+
+        if modified {
+            self.killing = Some(name.to_owned());
+            self.start_prompt(PromptKind::ConfirmKill);
         } else {
-            self.main_frame.clone()
+            self.kill_buffer(name);
+        }
+    }
+
+    /// Forgets the buffer. Frames showing it switch to the most recent other buffer, or to a new
+    /// scratch buffer if it was the last one.
+    pub fn kill_buffer(&mut self, name: &str) {
+        let killed = match self.find_buffer(name) {
+            Some(buffer) => buffer,
+            None => return,
+        };
+
+        self.buffers.retain(|b| !Rc::ptr_eq(b, &killed));
+
+        if self.buffers.is_empty() {
+            self.buffers.push(Rc::new(RefCell::new(Buffer::new())));
+        }
+
+        let leaves = self
+            .workspaces
+            .iter()
+            .flat_map(|workspace| Frame::leaves(&workspace.main_frame));
+
+        for frame in leaves {
+            if Rc::ptr_eq(&frame.borrow().clone_buffer(), &killed) {
+                frame.borrow_mut().replace_buffer(self.buffers[0].clone());
+            }
+        }
+
+        self.refresh_buffer_list();
+    }
+
+    /// Shows the list of buffers in the focused frame.
+    pub fn list_buffers(&mut self) {
+        let buffer = self
+            .find_buffer(BUFFER_LIST_NAME)
+            .unwrap_or_else(|| Rc::new(RefCell::new(Buffer::with_name(BUFFER_LIST_NAME))));
+
+        self.show_buffer(buffer);
+        self.refresh_buffer_list();
+    }
+
+    fn refresh_buffer_list(&self) {
+        let list = match self.find_buffer(BUFFER_LIST_NAME) {
+            Some(list) => list,
+            None => return,
+        };
+
+        let rows: Vec<(String, String, String, String)> = self
+            .buffers
+            .iter()
+            .filter(|buffer| !Rc::ptr_eq(buffer, &list))
+            .map(|buffer| {
+                let buffer = buffer.borrow();
+
+                (
+                    if buffer.is_modified() { "*" } else { "" }.to_owned(),
+                    buffer.name().to_owned(),
+                    buffer.size().to_string(),
+                    buffer
+                        .path()
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_default(),
+                )
+            })
+            .collect();
+        let name_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0).max(4);
+        let size_width = rows.iter().map(|row| row.2.len()).max().unwrap_or(0).max(4);
+        let line = |modified: &str, name: &str, size: &str, path: &str| {
+            format!(
+                "{:1} {:name_width$} {:>size_width$} {}",
+                modified, name, size, path
+            )
+            .trim_end()
+            .to_owned()
+        };
+
+        let mut lines = vec![line("M", "Name", "Size", "Path")];
+        let mut entries = vec![None];
+
+        for (modified, name, size, path) in rows {
+            lines.push(line(&modified, &name, &size, &path));
+            entries.push(Some(ListEntry::Buffer(name)));
+        }
+
+        list.borrow_mut().set_list(lines, entries);
+        self.clamp_focus_cursor();
+    }
+
+    /// Moves the cursor of the focused frame back into the text. The frame keeps its cursor as
+    /// it is while the text is replaced under it, as when a list it shows is refreshed.
+    fn clamp_focus_cursor(&self) {
+        let frame = self.focus_frame();
+        let cursor = frame.borrow().cursor();
+
+        frame.borrow_mut().move_to(cursor);
+    }
+
+    /// Shows the diagnostics of all files in the focused frame.
+    pub fn list_diagnostics(&mut self) {
+        let buffer = self
+            .find_buffer(DIAGNOSTIC_LIST_NAME)
+            .unwrap_or_else(|| Rc::new(RefCell::new(Buffer::with_name(DIAGNOSTIC_LIST_NAME))));
+
+        self.show_buffer(buffer);
+        self.refresh_diagnostic_list();
+        self.refresh_buffer_list();
+    }
+
+    fn refresh_diagnostic_list(&self) {
+        let list = match self.find_buffer(DIAGNOSTIC_LIST_NAME) {
+            Some(list) => list,
+            None => return,
+        };
+
+        let dir = std::env::current_dir().unwrap_or_default();
+        let mut lines = Vec::new();
+        let mut entries = Vec::new();
+
+        for (uri, diagnostics) in &self.diagnostics {
+            let path = match uri.to_file_path() {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            let path = path
+                .strip_prefix(&dir)
+                .unwrap_or(&path)
+                .display()
+                .to_string();
+            let mut diagnostics: Vec<&Diagnostic> = diagnostics.iter().collect();
+
+            diagnostics.sort_by_key(|diagnostic| {
+                (
+                    diagnostic.range.start.line,
+                    diagnostic.range.start.character,
+                )
+            });
+
+            for diagnostic in diagnostics {
+                lines.push(format!(
+                    "{}:{}:{}: {}: {}",
+                    path,
+                    diagnostic.range.start.line + 1,
+                    diagnostic.range.start.character + 1,
+                    severity_name(diagnostic.severity.unwrap_or(DiagnosticSeverity::ERROR)),
+                    diagnostic.message.lines().next().unwrap_or_default()
+                ));
+                entries.push(Some(ListEntry::Location(Location::new(
+                    uri.clone(),
+                    diagnostic.range,
+                ))));
+            }
+        }
+
+        if lines.is_empty() {
+            lines.push(String::new());
+            entries.push(None);
+        }
+
+        list.borrow_mut().set_list(lines, entries);
+        self.clamp_focus_cursor();
+    }
+
+    /// Opens the entry under the cursor in a list buffer, otherwise breaks the line.
+    pub fn new_line(&mut self) {
+        if !self.open_entry() {
+            self.focus_frame().borrow_mut().new_line();
+        }
+    }
+
+    /// Opens what the line under the cursor of a list buffer refers to. Returns false if there
+    /// is nothing to open.
+    pub fn open_entry(&mut self) -> bool {
+        let frame = self.focus_frame();
+        let entry = frame
+            .borrow()
+            .clone_buffer()
+            .borrow()
+            .entry(frame.borrow().cursor().1)
+            .cloned();
+
+        match entry {
+            Some(ListEntry::Buffer(name)) => {
+                self.switch_to_buffer(&name);
+                true
+            }
+            Some(ListEntry::Location(location)) => {
+                let source = self
+                    .outline
+                    .as_ref()
+                    .filter(|outline| outline.frame.ptr_eq(&Rc::downgrade(&frame)))
+                    .and_then(|outline| outline.source.upgrade());
+
+                if let Some(source) = source {
+                    self.set_focus_frame(&source);
+                }
+
+                self.open_location(&location);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reads a name or a file path in the minibuffer. Switching buffers offers the other buffers
+    /// first, killing offers the shown one and finding a file offers the files in the current
+    /// directory. Symbols are offered as the language server finds them.
+    pub fn start_prompt(&mut self, kind: PromptKind) {
+        let current = self.focus_frame().borrow().clone_buffer();
+
+        if kind == PromptKind::WorkspaceSymbol {
+            match self.language_servers.workspace_symbols(&current, "") {
+                Some(ticket) => {
+                    self.symbol_search = Some(SymbolSearch {
+                        buffer: current.clone(),
+                        ticket: Some(ticket),
+                        query: String::new(),
+                        symbols: Vec::new(),
+                    })
+                }
+                None => {
+                    self.message = Some("No language server can do that here".to_owned());
+                    return;
+                }
+            }
+        }
+
+        let mut candidates: Vec<String> = self
+            .buffers
+            .iter()
+            .filter(|buffer| !Rc::ptr_eq(buffer, &current))
+            .map(|buffer| buffer.borrow().name().to_owned())
+            .collect();
+        let current = current.borrow().name().to_owned();
+
+        self.prompt = Some(match kind {
+            PromptKind::SwitchBuffer => {
+                let default = candidates
+                    .first()
+                    .cloned()
+                    .unwrap_or_else(|| current.clone());
+
+                candidates.push(current);
+                Prompt::new(kind, Some(default), candidates)
+            }
+            PromptKind::KillBuffer => {
+                candidates.insert(0, current.clone());
+                Prompt::new(kind, Some(current), candidates)
+            }
+            PromptKind::FindFile => {
+                self.spawn_job(
+                    || files_in(Path::new(".")),
+                    |window, files| {
+                        if let Some(prompt) = &mut window.prompt {
+                            if prompt.kind() == PromptKind::FindFile {
+                                prompt.set_candidates(files);
+                            }
+                        }
+                    },
+                );
+                Prompt::new(kind, None, Vec::new())
+            }
+            PromptKind::RenameWorkspace => {
+                Prompt::new(kind, Some(self.workspace().name.clone()), Vec::new())
+            }
+            PromptKind::ConfirmKill => Prompt::new(
+                kind,
+                Some("no".to_owned()),
+                vec!["yes".to_owned(), "no".to_owned()],
+            ),
+            PromptKind::RenameSymbol => {
+                let frame = self.focus_frame();
+                let cursor = frame.borrow().cursor();
+                let mut prompt = Prompt::new(kind, None, Vec::new());
+
+                prompt.set_input(frame.borrow().clone_buffer().borrow().word_at(cursor));
+                prompt
+            }
+            PromptKind::WorkspaceSymbol => Prompt::new(kind, None, Vec::new()),
+        });
+    }
+
+    pub fn prompt(&self) -> Option<&Prompt> {
+        self.prompt.as_ref()
+    }
+
+    pub fn prompt_mut(&mut self) -> Option<&mut Prompt> {
+        self.prompt.as_mut()
+    }
+
+    pub fn cancel_prompt(&mut self) {
+        self.prompt = None;
+    }
+
+    pub fn finish_prompt(&mut self) {
+        if let Some(prompt) = self.prompt.take() {
+            if prompt.kind() == PromptKind::WorkspaceSymbol {
+                self.goto_symbol(prompt.matches().first().copied());
+                return;
+            }
+
+            let value = prompt.value();
+
+            if value.is_empty() {
+                return;
+            }
+
+            match prompt.kind() {
+                PromptKind::SwitchBuffer => self.switch_to_buffer(&value),
+                PromptKind::KillBuffer => self.confirm_kill(&value),
+                PromptKind::ConfirmKill => {
+                    if let Some(name) = self.killing.take() {
+                        if "yes".starts_with(&value) {
+                            self.kill_buffer(&name);
+                        }
+                    }
+                }
+                PromptKind::FindFile => self.find_file(Path::new(&value)),
+                PromptKind::RenameWorkspace => self.rename_workspace(&value),
+                PromptKind::RenameSymbol => self.rename_symbol(&value),
+                PromptKind::WorkspaceSymbol => {}
+            }
+        }
+    }
+
+    /// Goes to the symbol the candidate of the symbol prompt stands for.
+    fn goto_symbol(&mut self, candidate: Option<&str>) {
+        let location = self.symbol_search.take().and_then(|search| {
+            search
+                .symbols
+                .into_iter()
+                .find(|(c, _)| Some(c.as_str()) == candidate)
+                .map(|(_, location)| location)
+        });
+
+        match location {
+            Some(location) => {
+                self.push_jump();
+                self.open_location(&location);
+            }
+            None => self.message = Some("No symbol found".to_owned()),
+        }
+    }
+}
+
+/// Appends a line for each symbol, indented by its depth, followed by the ones inside it unless
+/// it is collapsed. `path` holds the keys of the symbols around them.
+fn outline_lines(
+    symbols: &[Symbol],
+    path: &mut Vec<SymbolKey>,
+    collapsed: &HashSet<Vec<SymbolKey>>,
+    uri: &Url,
+    lines: &mut Vec<String>,
+    entries: &mut Vec<Option<ListEntry>>,
+    paths: &mut Vec<Vec<SymbolKey>>,
+) {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    for symbol in symbols {
+        let label = format!("{} {}", symbol_kind_name(symbol.kind), symbol.name);
+        let count = seen.entry(label.clone()).or_default();
+
+        path.push((label.clone(), *count));
+        *count += 1;
+
+        let is_collapsed = collapsed.contains(path);
+
+        lines.push(format!(
+            "{:indent$}{}{}",
+            "",
+            label,
+            if is_collapsed && !symbol.children.is_empty() {
+                FOLD_MARKER
+            } else {
+                ""
+            },
+            indent = (path.len() - 1) * 2
+        ));
+        entries.push(Some(ListEntry::Location(Location::new(
+            uri.clone(),
+            Range::new(symbol.position, symbol.position),
+        ))));
+        paths.push(path.clone());
+
+        if !is_collapsed {
+            outline_lines(
+                &symbol.children,
+                path,
+                collapsed,
+                uri,
+                lines,
+                entries,
+                paths,
+            );
         }
+
+        path.pop();
+    }
+}
+
+/// Whether the buffer has been edited too recently for its decorations to be asked for.
+fn is_being_edited(buffer: &Buffer) -> bool {
+    buffer
+        .edited_at()
+        .is_some_and(|edited_at| edited_at.elapsed() < DECORATION_DELAY)
+}
+
+fn overlap(start0: u16, end0: u16, start1: u16, end1: u16) -> i32 {
+    end0.min(end1) as i32 - start0.max(start1) as i32
+}
+
+/// The names of the files in the directory, sorted.
+fn files_in(dir: &Path) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_file())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::{Position, SymbolKind};
+    use tui::layout::Rect;
+
+    fn text(window: &Window) -> String {
+        window
+            .focus_frame()
+            .borrow()
+            .clone_buffer()
+            .borrow()
+            .text_full()
+    }
+
+    fn render(window: &Window) {
+        window
+            .main_frame()
+            .borrow_mut()
+            .render(Rect::new(0, 0, 80, 24));
+    }
+
+    #[test]
+    fn kill_modified_buffer() {
+        let mut window = Window::new();
+
+        window.new_char('x');
+        window.start_prompt(PromptKind::KillBuffer);
+        window.finish_prompt();
+        assert_eq!(
+            window.prompt().map(|prompt| prompt.kind()),
+            Some(PromptKind::ConfirmKill)
+        );
+
+        // The default answer keeps it.
+        window.finish_prompt();
+        assert_eq!(text(&window), "x");
+
+        window.start_prompt(PromptKind::KillBuffer);
+        window.finish_prompt();
+        window.prompt_mut().unwrap().insert('y');
+        window.finish_prompt();
+        assert!(window.prompt().is_none());
+        assert_eq!(text(&window), "");
+    }
+
+    #[test]
+    fn session_round_trip() {
+        let dir = std::env::temp_dir().join(format!("session-{}", std::process::id()));
+        let mut window = Window::new();
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "one\ntwo\nthree").unwrap();
+        fs::write(dir.join("b.txt"), "four\nfive").unwrap();
+
+        window.new_char('x');
+        window.find_file(&dir.join("a.txt"));
+        render(&window);
+        window.split(Direction::Horizontal);
+        window.resize_focus_frame(Direction::Horizontal, 10);
+        window.focus_frame().borrow_mut().move_to((2, 2));
+        window.move_focus();
+        window.find_file(&dir.join("b.txt"));
+        window.focus_frame().borrow_mut().move_to((3, 1));
+
+        let json = serde_json::to_string(&window.session()).unwrap();
+        let mut restored = Window::new();
+
+        restored.restore_session(&serde_json::from_str(&json).unwrap());
+        render(&restored);
+
+        let state = |window: &Window| serde_json::to_value(window.main_frame().borrow().state());
+
+        assert_eq!(state(&restored).unwrap(), state(&window).unwrap());
+        assert!(json.contains("\"ratio\":60"));
+        assert_eq!(text(&restored), "four\nfive");
+        assert_eq!(restored.focus_frame().borrow().cursor(), (3, 1));
+
+        // The scratch buffer visits no file.
+        let names: Vec<String> = restored
+            .buffers
+            .iter()
+            .map(|buffer| buffer.borrow().name().to_owned())
+            .collect();
+
+        assert_eq!(names, vec!["b.txt", "a.txt"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn undo_workspace_edit() {
+        let dir = std::env::temp_dir().join(format!("workspace-edit-{}", std::process::id()));
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        let mut window = Window::new();
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&a, "alpha beta").unwrap();
+        fs::write(&b, "beta gamma").unwrap();
+        window.find_file(&a);
+
+        let edit = |path: &Path, start: u32, end: u32| {
+            (
+                Url::from_file_path(path).unwrap(),
+                vec![TextEdit::new(
+                    Range::new(Position::new(0, start), Position::new(0, end)),
+                    "delta".to_owned(),
+                )],
+            )
+        };
+
+        window.apply_workspace_edit(WorkspaceEdit {
+            changes: Some(HashMap::from([edit(&a, 6, 10), edit(&b, 0, 4)])),
+            ..Default::default()
+        });
+
+        // The other file has been opened.
+        let b = window.buffer_visiting(&b).unwrap();
+
+        assert_eq!(text(&window), "alpha delta");
+        assert_eq!(b.borrow().text_full(), "delta gamma");
+
+        // Edits made since to the other buffer have to be undone first.
+        b.borrow_mut().insert_char((0, 0), 'x');
+        window.undo();
+        assert!(window.message().is_some());
+        assert_eq!(text(&window), "alpha delta");
+        assert_eq!(b.borrow().text_full(), "xdelta gamma");
+
+        b.borrow_mut().undo();
+        window.undo();
+        assert_eq!(text(&window), "alpha beta");
+        assert_eq!(b.borrow().text_full(), "beta gamma");
+
+        fs::remove_dir_all(dir).unwrap();
     }
 
-    #[allow(unused)]
-    pub fn detached_buffer(&self) -> Vec<Rc<RefCell<Buffer>>> {
-        self.detached_buffer.clone()
+    #[test]
+    fn outline_tree() {
+        let dir = std::env::temp_dir().join(format!("outline-{}", std::process::id()));
+        let file = dir.join("a.rs");
+        let mut window = Window::new();
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            &file,
+            "struct A;\nimpl A {\n    fn new() {}\n    fn get() {}\n}\nimpl A {\n    fn set() {}\n}",
+        )
+        .unwrap();
+        window.find_file(&file);
+        render(&window);
+
+        let source = window.focus_frame();
+        let buffer = source.borrow().clone_buffer();
+
+        window.split(Direction::Horizontal);
+
+        let (source, frame) = source.borrow().clone_inner_frames();
+
+        window.set_focus_frame(&frame);
+        window.switch_to_buffer(OUTLINE_NAME);
+
+        let symbol = |name: &str, kind, line, children| Symbol {
+            name: name.to_owned(),
+            kind,
+            position: Position::new(line, 0),
+            children,
+        };
+        let methods = vec![
+            symbol("new", SymbolKind::METHOD, 2, Vec::new()),
+            symbol("get", SymbolKind::METHOD, 3, Vec::new()),
+        ];
+
+        window.outline = Some(Outline {
+            frame: Rc::downgrade(&frame),
+            source: Rc::downgrade(&source),
+            buffer: Rc::downgrade(&buffer),
+            version: buffer.borrow().version(),
+            ticket: None,
+            line: 0,
+            symbols: vec![
+                symbol("A", SymbolKind::STRUCT, 0, Vec::new()),
+                symbol("A", SymbolKind::OBJECT, 1, methods),
+                symbol(
+                    "A",
+                    SymbolKind::OBJECT,
+                    5,
+                    vec![symbol("set", SymbolKind::METHOD, 6, Vec::new())],
+                ),
+            ],
+            collapsed: HashSet::new(),
+            paths: Vec::new(),
+        });
+        window.refresh_outline();
+        assert_eq!(
+            text(&window),
+            "struct A\nimpl A\n  fn new\n  fn get\nimpl A\n  fn set"
+        );
+
+        frame.borrow_mut().move_to((0, 1));
+        window.toggle_fold();
+        assert_eq!(text(&window), "struct A\nimpl A ...\nimpl A\n  fn set");
+
+        // Stays collapsed when the symbols come again.
+        window.refresh_outline();
+        assert_eq!(text(&window), "struct A\nimpl A ...\nimpl A\n  fn set");
+
+        // The other block of the same name is collapsed on its own.
+        frame.borrow_mut().move_to((0, 2));
+        window.toggle_fold();
+        assert_eq!(text(&window), "struct A\nimpl A ...\nimpl A ...");
+
+        frame.borrow_mut().move_to((0, 1));
+        window.toggle_fold();
+        assert_eq!(
+            text(&window),
+            "struct A\nimpl A\n  fn new\n  fn get\nimpl A ..."
+        );
+
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,89 @@
+use crate::buffer::Buffer;
+use tui::text::{Span, Spans};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoftWrap {
+    Off,
+    Char,
+    Word,
+}
+
+/// Byte indices where each visual row of the line starts. A full last row gets an empty row
+/// after it so that the cursor at the end of the line has somewhere to go.
+pub fn wrap_points(line: &str, width: usize, word: bool) -> Vec<usize> {
+    let mut points = vec![0];
+
+    if width == 0 {
+        return points;
+    }
+
+    let mut start = 0;
+
+    while line.len() - start >= width {
+        if line.len() - start == width {
+            points.push(line.len());
+            break;
+        }
+
+        let mut end = start + width;
+
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        if word {
+            if let Some(space) = line[start..end].rfind(' ') {
+                if space > 0 {
+                    end = start + space + 1;
+                }
+            }
+        }
+
+        if end == start {
+            end = start + line[start..].chars().next().map_or(1, |c| c.len_utf8());
+        }
+
+        points.push(end);
+        start = end;
+    }
+
+    points
+}
+
+/// The visual rows of a buffer line under the given wrap mode.
+pub fn line_rows(buffer: &Buffer, line: usize, soft_wrap: SoftWrap, width: usize) -> Vec<usize> {
+    match soft_wrap {
+        SoftWrap::Off => vec![0],
+        SoftWrap::Char => wrap_points(&buffer.display_line(line), width, false),
+        SoftWrap::Word => wrap_points(&buffer.display_line(line), width, true),
+    }
+}
+
+/// The row of `points` the byte index belongs to.
+pub fn row_of(points: &[usize], idx: usize) -> usize {
+    points.iter().rposition(|point| *point <= idx).unwrap_or(0)
+}
+
+/// Cuts styled text of one line into the rows starting at `points`.
+pub fn wrap_spans<'a>(spans: Spans<'a>, points: &[usize]) -> Vec<Spans<'a>> {
+    let mut rows: Vec<Vec<Span>> = vec![Vec::new(); points.len()];
+    let mut idx = 0;
+
+    for span in spans.0 {
+        let mut content = span.content.as_ref();
+
+        while !content.is_empty() {
+            let row = row_of(points, idx);
+            let len = match points.get(row + 1) {
+                Some(next) => (next - idx).min(content.len()),
+                None => content.len(),
+            };
+
+            rows[row].push(Span::styled(content[..len].to_owned(), span.style));
+            content = &content[len..];
+            idx += len;
+        }
+    }
+
+    rows.into_iter().map(Spans::from).collect()
+}